@@ -0,0 +1,943 @@
+// JSON endpoints under /api that expose statistics about the loaded data.
+
+use std::collections;
+use std::io;
+use std::sync;
+
+use chrono::prelude::*;
+use rocket;
+use rocket::http::Status;
+use rocket::response::Failure;
+
+use Field;
+use RenderOptions;
+use WeatherMeasurement;
+use WeatherStation;
+use WindMeasurement;
+use {parse_datetime, station_value};
+use json::JSON;
+use parquet;
+use store;
+use units;
+
+// Longitude/latitude box in degrees, given in query strings as
+// `bbox=<longitude_min>,<latitude_min>,<longitude_max>,<latitude_max>`.
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+  pub longitude_min: f32,
+  pub latitude_min: f32,
+  pub longitude_max: f32,
+  pub latitude_max: f32,
+}
+
+impl BoundingBox {
+  pub fn parse(bbox: &str) -> Result<BoundingBox, String> {
+    let values = try!(bbox.split(",")
+      .map(|v| v.trim().parse::<f32>().map_err(|e| e.to_string()))
+      .collect::<Result<Vec<_>, _>>());
+    if values.len() != 4 {
+      return Err(format!("bbox needs 4 values, got {}", values.len()));
+    }
+    let bbox = BoundingBox {
+      longitude_min: values[0],
+      latitude_min: values[1],
+      longitude_max: values[2],
+      latitude_max: values[3],
+    };
+    if bbox.longitude_min > bbox.longitude_max ||
+       bbox.latitude_min > bbox.latitude_max {
+      return Err(format!("empty bbox {}", bbox));
+    }
+    Ok(bbox)
+  }
+
+  pub fn contains(&self, station: &WeatherStation) -> bool {
+    station.longitude >= self.longitude_min &&
+    station.longitude <= self.longitude_max &&
+    station.latitude >= self.latitude_min &&
+    station.latitude <= self.latitude_max
+  }
+}
+
+impl ::std::fmt::Display for BoundingBox {
+  fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+    write!(f,
+           "{},{},{},{}",
+           self.longitude_min,
+           self.latitude_min,
+           self.longitude_max,
+           self.latitude_max)
+  }
+}
+
+fn parse_bbox(bbox: &Option<String>) -> Result<Option<BoundingBox>, Failure> {
+  match *bbox {
+    Some(ref bbox) => {
+      BoundingBox::parse(bbox)
+        .map(Some)
+        .map_err(|_| Failure(Status::BadRequest))
+    }
+    None => Ok(None),
+  }
+}
+
+fn stations_in<'a>(stations: &'a Vec<WeatherStation>,
+                   bbox: Option<BoundingBox>)
+                   -> Vec<&'a WeatherStation> {
+  stations.iter()
+    .filter(|s| bbox.map(|b| b.contains(s)).unwrap_or(true))
+    .collect()
+}
+
+#[derive(Serialize)]
+struct Histogram {
+  // bins + 1 edges; bin i covers [edges[i], edges[i + 1]), the last bin also
+  // includes its upper edge.
+  edges: Vec<f64>,
+  counts: Vec<usize>,
+}
+
+// Histogram of the number of measurements per station.
+fn measurement_histogram(stations: &[&WeatherStation],
+                         bins: usize)
+                         -> Histogram {
+  check_ge!(bins, 1);
+  let lengths =
+    stations.iter().map(|s| s.measurements.len()).collect::<Vec<_>>();
+  let min = lengths.iter().cloned().min().unwrap_or(0) as f64;
+  let max = lengths.iter().cloned().max().unwrap_or(0) as f64;
+  let width = ((max - min) / bins as f64).max(1.0);
+
+  let mut counts = vec![0; bins];
+  for n in lengths {
+    let bin = ((n as f64 - min) / width) as usize;
+    counts[bin.min(bins - 1)] += 1;
+  }
+
+  Histogram {
+    edges: (0..bins + 1).map(|i| min + i as f64 * width).collect(),
+    counts: counts,
+  }
+}
+
+#[derive(FromForm)]
+struct HistogramParams {
+  bins: Option<usize>,
+  bbox: Option<String>,
+}
+
+#[get("/api/stats/measurement-histogram?<params>")]
+fn measurement_histogram_route
+  (params: HistogramParams,
+   stations: rocket::State<store::SharedStore>)
+   -> Result<JSON<Histogram>, Failure> {
+  let bins = params.bins.unwrap_or(20);
+  if bins == 0 || bins > 10000 {
+    return Err(Failure(Status::BadRequest));
+  }
+  let bbox = try!(parse_bbox(&params.bbox));
+  let store = stations.read().unwrap();
+  let selected = stations_in(&store.stations, bbox);
+  Ok(JSON(measurement_histogram(&selected, bins)))
+}
+
+#[get("/api/stats/measurement-histogram", rank = 2)]
+fn measurement_histogram_default
+  (stations: rocket::State<store::SharedStore>)
+   -> Result<JSON<Histogram>, Failure> {
+  measurement_histogram_route(HistogramParams {
+                                bins: None,
+                                bbox: None,
+                              },
+                              stations)
+}
+
+// The largest gap between consecutive measurements, as the pair bounding it.
+fn largest_gap(measurements: &[WeatherMeasurement])
+               -> Option<(DateTime<UTC>, DateTime<UTC>)> {
+  measurements.windows(2)
+    .max_by_key(|pair| pair[1].datetime - pair[0].datetime)
+    .map(|pair| (pair[0].datetime, pair[1].datetime))
+}
+
+#[derive(Serialize)]
+struct StationGap {
+  usaf: String,
+  wban: String,
+  name: Option<String>,
+  gap_hours: f64,
+  gap_start: String,
+  gap_end: String,
+}
+
+// Stations whose largest gap between measurements exceeds `hours`, longest
+// gaps first.
+fn station_gaps(stations: &Vec<WeatherStation>, hours: f64) -> Vec<StationGap> {
+  let mut gaps = stations.iter()
+    .filter_map(|station| {
+      largest_gap(&station.measurements).and_then(|(start, end)| {
+        let gap_hours = (end - start).num_seconds() as f64 / 3600.0;
+        if gap_hours <= hours {
+          return None;
+        }
+        Some(StationGap {
+          usaf: station.usaf.clone(),
+          wban: station.wban.clone(),
+          name: station.name.clone(),
+          gap_hours: gap_hours,
+          gap_start: start.to_rfc3339(),
+          gap_end: end.to_rfc3339(),
+        })
+      })
+    })
+    .collect::<Vec<_>>();
+  gaps.sort_by(|a, b| b.gap_hours.partial_cmp(&a.gap_hours).unwrap());
+  gaps
+}
+
+#[derive(FromForm)]
+struct GapParams {
+  hours: f64,
+}
+
+#[get("/api/stats/gaps?<params>")]
+fn gaps(params: GapParams,
+        stations: rocket::State<store::SharedStore>)
+        -> Result<JSON<Vec<StationGap>>, Failure> {
+  if !(params.hours >= 0.0) {
+    return Err(Failure(Status::BadRequest));
+  }
+  Ok(JSON(station_gaps(&stations.read().unwrap().stations, params.hours)))
+}
+
+#[derive(Serialize, Clone, Copy)]
+struct FieldRange {
+  min: Option<f32>,
+  max: Option<f32>,
+  count: usize,
+  units: units::Units,
+}
+
+fn field_range_of(stations: &Vec<WeatherStation>, field: Field) -> FieldRange {
+  let mut range = FieldRange {
+    min: None,
+    max: None,
+    count: 0,
+    units: units::Units::new(units::TemperatureUnit::Celsius,
+                             units::WindUnit::MetersPerSecond),
+  };
+  for m in stations.iter().flat_map(|s| s.measurements.iter()) {
+    if let Some(v) = field.value(m) {
+      range.min = Some(range.min.map_or(v, |min| min.min(v)));
+      range.max = Some(range.max.map_or(v, |max| max.max(v)));
+      range.count += 1;
+    }
+  }
+  range
+}
+
+// Global field ranges only change with the data, so they are computed once per
+// field and StationStore::version on first request.
+pub struct FieldRanges {
+  ranges: sync::Mutex<(u64, collections::HashMap<Field, FieldRange>)>,
+}
+
+impl FieldRanges {
+  pub fn new() -> FieldRanges {
+    FieldRanges { ranges: sync::Mutex::new((0, collections::HashMap::new())) }
+  }
+}
+
+#[derive(FromForm)]
+struct FieldRangeParams {
+  field: String,
+  unit: Option<String>,
+  wind_unit: Option<String>,
+}
+
+#[get("/api/stats/field-range?<params>")]
+fn field_range(params: FieldRangeParams,
+               stations: rocket::State<store::SharedStore>,
+               ranges: rocket::State<FieldRanges>)
+               -> Result<JSON<FieldRange>, Failure> {
+  let field = try!(Field::parse(&params.field)
+    .map_err(|_| Failure(Status::BadRequest)));
+  let unit = try!(units::TemperatureUnit::parse_option(&params.unit)
+    .map_err(|_| Failure(Status::BadRequest)));
+  let wind_unit = try!(units::WindUnit::parse_option(&params.wind_unit)
+    .map_err(|_| Failure(Status::BadRequest)));
+  let mut range = {
+    let store = stations.read().unwrap();
+    let mut ranges = ranges.ranges.lock().unwrap();
+    if ranges.0 != store.version {
+      *ranges = (store.version, collections::HashMap::new());
+    }
+    *ranges.1
+      .entry(field)
+      .or_insert_with(|| field_range_of(&store.stations, field))
+  };
+  range.min = range.min.map(|v| field.convert(v, unit, wind_unit));
+  range.max = range.max.map(|v| field.convert(v, unit, wind_unit));
+  range.units = units::Units::new(unit, wind_unit);
+  Ok(JSON(range))
+}
+
+// The station with the given ids, or 404.
+fn find_station<'a>(store: &'a store::StationStore,
+                    usaf: &str,
+                    wban: &str)
+                    -> Result<&'a WeatherStation, Failure> {
+  store.get(usaf, wban).ok_or(Failure(Status::NotFound))
+}
+
+#[derive(Serialize)]
+struct Bucket {
+  // YYYY-MM-DD or YYYY-MM.
+  start: String,
+  value: f32,
+}
+
+#[derive(Serialize)]
+struct Aggregate {
+  usaf: String,
+  wban: String,
+  name: Option<String>,
+  buckets: Vec<Bucket>,
+  units: units::Units,
+}
+
+// Groups the air temperatures by UTC calendar day or month and reduces each
+// group to its mean, min or max. Buckets without temperatures are omitted.
+fn aggregate(measurements: &[WeatherMeasurement],
+             by: &str,
+             stat: &str)
+             -> Result<Vec<Bucket>, String> {
+  let format = match by {
+    "day" => "%Y-%m-%d",
+    "month" => "%Y-%m",
+    _ => return Err(format!("unknown bucket size {:?}", by)),
+  };
+  fn mean(v: &[f32]) -> f32 {
+    v.iter().sum::<f32>() / v.len() as f32
+  }
+  fn min(v: &[f32]) -> f32 {
+    v.iter().cloned().fold(::std::f32::MAX, f32::min)
+  }
+  fn max(v: &[f32]) -> f32 {
+    v.iter().cloned().fold(::std::f32::MIN, f32::max)
+  }
+  let reduce: fn(&[f32]) -> f32 = match stat {
+    "mean" => mean,
+    "min" => min,
+    "max" => max,
+    _ => return Err(format!("unknown statistic {:?}", stat)),
+  };
+
+  // Measurements are sorted by time, so each bucket is a contiguous run.
+  let mut groups: Vec<(String, Vec<f32>)> = vec![];
+  for m in measurements {
+    let t = match m.air_temperature {
+      Some(t) => t,
+      None => continue,
+    };
+    let key = m.datetime.format(format).to_string();
+    if groups.last().map(|&(ref last, _)| *last != key).unwrap_or(true) {
+      groups.push((key, vec![]));
+    }
+    groups.last_mut().unwrap().1.push(t);
+  }
+  Ok(groups.into_iter()
+    .map(|(start, values)| {
+      Bucket {
+        start: start,
+        value: reduce(&values),
+      }
+    })
+    .collect())
+}
+
+#[derive(FromForm, Default)]
+struct AggregateParams {
+  // day (default) or month.
+  by: Option<String>,
+  // mean (default), min or max.
+  stat: Option<String>,
+  unit: Option<String>,
+}
+
+#[get("/api/station/<usaf>/<wban>/aggregate.json?<params>")]
+fn station_aggregate(usaf: String,
+                     wban: String,
+                     params: AggregateParams,
+                     stations: rocket::State<store::SharedStore>)
+                     -> Result<JSON<Aggregate>, Failure> {
+  let store = stations.read().unwrap();
+  let station = try!(find_station(&store, &usaf, &wban));
+  let unit = try!(units::TemperatureUnit::parse_option(&params.unit)
+    .map_err(|_| Failure(Status::BadRequest)));
+  let by = params.by.as_ref().map_or("day", |s| s.as_str());
+  let stat = params.stat.as_ref().map_or("mean", |s| s.as_str());
+  let mut buckets = try!(aggregate(&station.measurements, by, stat)
+    .map_err(|_| Failure(Status::BadRequest)));
+  for bucket in &mut buckets {
+    bucket.value = unit.from_celsius(bucket.value);
+  }
+  Ok(JSON(Aggregate {
+    usaf: station.usaf.clone(),
+    wban: station.wban.clone(),
+    name: station.name.clone(),
+    buckets: buckets,
+    units: units::Units::new(unit, units::WindUnit::MetersPerSecond),
+  }))
+}
+
+#[get("/api/station/<usaf>/<wban>/aggregate.json", rank = 2)]
+fn station_aggregate_default(usaf: String,
+                             wban: String,
+                             stations: rocket::State<store::SharedStore>)
+                             -> Result<JSON<Aggregate>, Failure> {
+  station_aggregate(usaf, wban, AggregateParams::default(), stations)
+}
+
+#[derive(Serialize)]
+struct Extreme {
+  value: f32,
+  time: String,
+}
+
+#[derive(Serialize)]
+struct Extremes {
+  usaf: String,
+  wban: String,
+  name: Option<String>,
+  max_temperature: Option<Extreme>,
+  min_temperature: Option<Extreme>,
+  max_wind_speed: Option<Extreme>,
+  units: units::Units,
+}
+
+type Record = Option<(f32, DateTime<UTC>)>;
+
+// The highest and lowest air temperature and the highest wind speed, each
+// with when it was first reached.
+fn extremes(measurements: &[WeatherMeasurement]) -> (Record, Record, Record) {
+  let mut max_temperature: Record = None;
+  let mut min_temperature: Record = None;
+  let mut max_wind_speed: Record = None;
+  for m in measurements {
+    if let Some(t) = m.air_temperature {
+      if max_temperature.map_or(true, |(max, _)| t > max) {
+        max_temperature = Some((t, m.datetime));
+      }
+      if min_temperature.map_or(true, |(min, _)| t < min) {
+        min_temperature = Some((t, m.datetime));
+      }
+    }
+    if let Some(speed) = Field::WindSpeed.value(m) {
+      if max_wind_speed.map_or(true, |(max, _)| speed > max) {
+        max_wind_speed = Some((speed, m.datetime));
+      }
+    }
+  }
+  (max_temperature, min_temperature, max_wind_speed)
+}
+
+#[derive(FromForm, Default)]
+struct ExtremesParams {
+  unit: Option<String>,
+  wind_unit: Option<String>,
+}
+
+#[get("/api/station/<usaf>/<wban>/extremes?<params>")]
+fn station_extremes(usaf: String,
+                    wban: String,
+                    params: ExtremesParams,
+                    stations: rocket::State<store::SharedStore>)
+                    -> Result<JSON<Extremes>, Failure> {
+  let store = stations.read().unwrap();
+  let station = try!(find_station(&store, &usaf, &wban));
+  let unit = try!(units::TemperatureUnit::parse_option(&params.unit)
+    .map_err(|_| Failure(Status::BadRequest)));
+  let wind_unit = try!(units::WindUnit::parse_option(&params.wind_unit)
+    .map_err(|_| Failure(Status::BadRequest)));
+  let (max_temperature, min_temperature, max_wind_speed) =
+    extremes(&station.measurements);
+  let extreme = |field: Field, record: Record| {
+    record.map(|(value, time)| {
+      Extreme {
+        value: field.convert(value, unit, wind_unit),
+        time: time.to_rfc3339(),
+      }
+    })
+  };
+  Ok(JSON(Extremes {
+    usaf: station.usaf.clone(),
+    wban: station.wban.clone(),
+    name: station.name.clone(),
+    max_temperature: extreme(Field::Temperature, max_temperature),
+    min_temperature: extreme(Field::Temperature, min_temperature),
+    max_wind_speed: extreme(Field::WindSpeed, max_wind_speed),
+    units: units::Units::new(unit, wind_unit),
+  }))
+}
+
+#[get("/api/station/<usaf>/<wban>/extremes", rank = 2)]
+fn station_extremes_default(usaf: String,
+                            wban: String,
+                            stations: rocket::State<store::SharedStore>)
+                            -> Result<JSON<Extremes>, Failure> {
+  station_extremes(usaf, wban, ExtremesParams::default(), stations)
+}
+
+#[derive(Serialize)]
+struct MeasurementRecord {
+  time: String,
+  air_temperature: Option<f32>,
+  dew_point: Option<f32>,
+  air_pressure: Option<f32>,
+  wind_speed: Option<f32>,
+  wind_direction: Option<i32>,
+  // Input file the measurement was loaded from; only with --track-provenance.
+  source: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Measurements {
+  usaf: String,
+  wban: String,
+  name: Option<String>,
+  measurements: Vec<MeasurementRecord>,
+  units: units::Units,
+}
+
+#[derive(FromForm, Default)]
+struct MeasurementsParams {
+  unit: Option<String>,
+  wind_unit: Option<String>,
+}
+
+#[get("/api/station/<usaf>/<wban>/measurements.json?<params>")]
+fn station_measurements(usaf: String,
+                        wban: String,
+                        params: MeasurementsParams,
+                        stations: rocket::State<store::SharedStore>)
+                        -> Result<JSON<Measurements>, Failure> {
+  let store = stations.read().unwrap();
+  let station = try!(find_station(&store, &usaf, &wban));
+  let unit = try!(units::TemperatureUnit::parse_option(&params.unit)
+    .map_err(|_| Failure(Status::BadRequest)));
+  let wind_unit = try!(units::WindUnit::parse_option(&params.wind_unit)
+    .map_err(|_| Failure(Status::BadRequest)));
+  let convert = |field: Field, m: &WeatherMeasurement| {
+    field.value(m).map(|v| field.convert(v, unit, wind_unit))
+  };
+  let measurements = station.measurements
+    .iter()
+    .map(|m| {
+      MeasurementRecord {
+        time: m.datetime.to_rfc3339(),
+        air_temperature: convert(Field::Temperature, m),
+        dew_point: convert(Field::DewPoint, m),
+        air_pressure: convert(Field::Pressure, m),
+        wind_speed: convert(Field::WindSpeed, m),
+        wind_direction: match m.wind {
+          Some(WindMeasurement::Normal { direction, .. }) => direction,
+          _ => None,
+        },
+        source: m.source
+          .and_then(|i| store.sources.get(i as usize))
+          .cloned(),
+      }
+    })
+    .collect();
+  Ok(JSON(Measurements {
+    usaf: station.usaf.clone(),
+    wban: station.wban.clone(),
+    name: station.name.clone(),
+    measurements: measurements,
+    units: units::Units::new(unit, wind_unit),
+  }))
+}
+
+#[get("/api/station/<usaf>/<wban>/measurements.json", rank = 2)]
+fn station_measurements_default(usaf: String,
+                                wban: String,
+                                stations: rocket::State<store::SharedStore>)
+                                -> Result<JSON<Measurements>, Failure> {
+  station_measurements(usaf, wban, MeasurementsParams::default(), stations)
+}
+
+// The station's measurements as columns, null where a value is missing.
+fn measurement_columns(measurements: &[WeatherMeasurement]) -> parquet::File {
+  let float = |name: &str, field: Field| {
+    parquet::Column {
+      name: String::from(name),
+      values: parquet::Values::Float(measurements.iter()
+        .map(|m| field.value(m))
+        .collect()),
+    }
+  };
+  parquet::File {
+    columns: vec![parquet::Column {
+                    name: String::from("time"),
+                    values: parquet::Values::Timestamp(measurements.iter()
+                      .map(|m| m.datetime.timestamp() * 1000)
+                      .collect()),
+                  },
+                  float("air_temperature", Field::Temperature),
+                  float("dew_point", Field::DewPoint),
+                  float("air_pressure", Field::Pressure),
+                  float("wind_speed", Field::WindSpeed),
+                  parquet::Column {
+                    name: String::from("wind_direction"),
+                    values: parquet::Values::Int32(measurements.iter()
+                      .map(|m| match m.wind {
+                        Some(WindMeasurement::Normal { direction, .. }) => {
+                          direction
+                        }
+                        _ => None,
+                      })
+                      .collect()),
+                  }],
+  }
+}
+
+#[get("/api/station/<usaf>/<wban>/data.parquet")]
+fn station_parquet<'a>(usaf: String,
+                       wban: String,
+                       stations: rocket::State<store::SharedStore>)
+                       -> Result<rocket::Response<'a>, Failure> {
+  let store = stations.read().unwrap();
+  let station = try!(find_station(&store, &usaf, &wban));
+  let mut data = vec![];
+  try!(measurement_columns(&station.measurements)
+    .write(&mut data)
+    .map_err(|_| Failure(Status::InternalServerError)));
+  rocket::Response::build()
+    .header(rocket::http::ContentType::new("application", "vnd.apache.parquet"))
+    .raw_header("Content-Disposition",
+                format!("attachment; filename=\"{}-{}.parquet\"", usaf, wban))
+    .sized_body(io::Cursor::new(data))
+    .ok()
+}
+
+#[derive(Serialize)]
+struct StationSummary {
+  usaf: String,
+  wban: String,
+  name: Option<String>,
+  latitude: f32,
+  longitude: f32,
+  elevation: Option<i32>,
+  measurements: usize,
+}
+
+#[derive(Serialize)]
+struct StationPage {
+  stations: Vec<StationSummary>,
+  // Number of stations across all pages.
+  total: usize,
+  // Where the next page starts, None on the last one.
+  next_offset: Option<usize>,
+}
+
+const DEFAULT_PAGE_SIZE: usize = 1000;
+const MAX_PAGE_SIZE: usize = 10000;
+
+#[derive(FromForm, Default)]
+struct StationsParams {
+  limit: Option<usize>,
+  offset: Option<usize>,
+}
+
+// The loaded stations ordered by id, a page at a time.
+#[get("/api/stations?<params>")]
+fn station_list(params: StationsParams,
+                stations: rocket::State<store::SharedStore>)
+                -> Result<JSON<StationPage>, Failure> {
+  let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+  if limit == 0 || limit > MAX_PAGE_SIZE {
+    return Err(Failure(Status::BadRequest));
+  }
+  let offset = params.offset.unwrap_or(0);
+  Ok(JSON(station_page(&stations.read().unwrap(), offset, limit)))
+}
+
+fn station_page(store: &store::StationStore,
+                offset: usize,
+                limit: usize)
+                -> StationPage {
+  let total = store.stations.len();
+  let page = store.page(offset, limit)
+    .into_iter()
+    .map(|station| {
+      StationSummary {
+        usaf: station.usaf.clone(),
+        wban: station.wban.clone(),
+        name: station.name.clone(),
+        latitude: station.latitude,
+        longitude: station.longitude,
+        elevation: station.elevation,
+        measurements: station.measurements.len(),
+      }
+    })
+    .collect::<Vec<_>>();
+  let end = offset + page.len();
+  StationPage {
+    stations: page,
+    total: total,
+    next_offset: if end < total { Some(end) } else { None },
+  }
+}
+
+#[get("/api/stations", rank = 2)]
+fn station_list_default(stations: rocket::State<store::SharedStore>)
+                        -> Result<JSON<StationPage>, Failure> {
+  station_list(StationsParams::default(), stations)
+}
+
+#[derive(Serialize)]
+struct Alert {
+  usaf: String,
+  wban: String,
+  name: Option<String>,
+  latitude: f32,
+  longitude: f32,
+  value: f32,
+}
+
+#[derive(Serialize)]
+struct Alerts {
+  // Most extreme first.
+  stations: Vec<Alert>,
+  units: units::Units,
+}
+
+#[derive(FromForm)]
+struct AlertParams {
+  field: String,
+  // gt or lt.
+  op: String,
+  // In the requested units.
+  value: f32,
+  start: Option<String>,
+  end: Option<String>,
+  unit: Option<String>,
+  wind_unit: Option<String>,
+}
+
+// The stations whose value in the window, selected as for tiles, is above or
+// below a threshold.
+fn alerts_of(store: &store::StationStore,
+             params: &AlertParams,
+             options: &RenderOptions)
+             -> Result<Alerts, String> {
+  let field = try!(Field::parse(&params.field));
+  let above = match params.op.as_str() {
+    "gt" => true,
+    "lt" => false,
+    op => return Err(format!("op must be gt or lt, got {:?}", op)),
+  };
+  let unit = try!(units::TemperatureUnit::parse_option(&params.unit));
+  let wind_unit = try!(units::WindUnit::parse_option(&params.wind_unit));
+  let (default_start, default_end) = options.window();
+  let parse_time = |time: &Option<String>, default: DateTime<UTC>| {
+    time.as_ref().map_or(Ok(default), |t| parse_datetime(t))
+  };
+  let start = try!(parse_time(&params.start, default_start));
+  let end = try!(parse_time(&params.end, default_end));
+
+  let mut alerts = store.stations
+    .iter()
+    .filter_map(|station| {
+      station_value(&station.measurements,
+                    start,
+                    end,
+                    options,
+                    |m: &WeatherMeasurement| field.value(m))
+        .map(|v| (station, field.convert(v, unit, wind_unit)))
+    })
+    .filter(|&(_, v)| {
+      if above { v > params.value } else { v < params.value }
+    })
+    .map(|(station, v)| {
+      Alert {
+        usaf: station.usaf.clone(),
+        wban: station.wban.clone(),
+        name: station.name.clone(),
+        latitude: station.latitude,
+        longitude: station.longitude,
+        value: v,
+      }
+    })
+    .collect::<Vec<_>>();
+  alerts.sort_by(|a, b| {
+    let order = a.value.partial_cmp(&b.value).unwrap();
+    if above { order.reverse() } else { order }
+  });
+  Ok(Alerts {
+    stations: alerts,
+    units: units::Units::new(unit, wind_unit),
+  })
+}
+
+#[get("/api/alerts?<params>")]
+fn alerts(params: AlertParams,
+          stations: rocket::State<store::SharedStore>,
+          defaults: rocket::State<RenderOptions>)
+          -> Result<JSON<Alerts>, Failure> {
+  alerts_of(&stations.read().unwrap(), &params, defaults.inner())
+    .map(JSON)
+    .map_err(|_| Failure(Status::BadRequest))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing;
+
+  // Stations with the given numbers of measurements.
+  fn stations_with(lengths: &[usize]) -> Vec<WeatherStation> {
+    lengths.iter()
+      .enumerate()
+      .map(|(i, &n)| {
+        testing::station(&format!("01000{}", i),
+                         0.0,
+                         0.0,
+                         testing::series(&vec![Some(0.0); n]))
+      })
+      .collect()
+  }
+
+  #[test]
+  fn histogram_bins_stations_by_measurement_count() {
+    let stations = stations_with(&[0, 1, 1, 4]);
+    let selected = stations.iter().collect::<Vec<_>>();
+    let histogram = measurement_histogram(&selected, 2);
+    assert_eq!(histogram.edges, vec![0.0, 2.0, 4.0]);
+    // The longest station lands in the last bin, which includes its edge.
+    assert_eq!(histogram.counts, vec![3, 1]);
+  }
+
+  #[test]
+  fn reports_only_gaps_longer_than_threshold() {
+    let mut series = testing::series(&[Some(1.0), Some(2.0)]);
+    series.push(testing::measurement(testing::time(7), Some(3.0)));
+    let stations = vec![testing::station("020000", 0.0, 0.0, series),
+                        stations_with(&[5]).remove(0)];
+    let gaps = station_gaps(&stations, 3.0);
+    assert_eq!(gaps.len(), 1);
+    assert_eq!(gaps[0].usaf, "020000");
+    assert_eq!(gaps[0].gap_hours, 6.0);
+    assert_eq!(gaps[0].gap_start, testing::time(1).to_rfc3339());
+  }
+
+  #[test]
+  fn field_range_spans_every_station() {
+    let stations =
+      vec![testing::station("010000",
+                            0.0,
+                            0.0,
+                            testing::series(&[Some(-3.5), None, Some(2.0)])),
+           testing::station("020000", 0.0, 0.0, testing::series(&[Some(7.0)]))];
+    let range = field_range_of(&stations, Field::Temperature);
+    assert_eq!(range.min, Some(-3.5));
+    assert_eq!(range.max, Some(7.0));
+    assert_eq!(range.count, 3);
+  }
+
+  #[test]
+  fn aggregates_temperatures_by_day() {
+    // 36 hours from midnight: 1.0 on the first day, 3.0 on the second.
+    let temperatures = (0..36)
+      .map(|hour| Some(if hour < 24 { 1.0 } else { 3.0 }))
+      .collect::<Vec<_>>();
+    let buckets = aggregate(&testing::series(&temperatures), "day", "mean")
+      .unwrap();
+    let buckets = buckets.iter()
+      .map(|b| (b.start.as_str(), b.value))
+      .collect::<Vec<_>>();
+    assert_eq!(buckets, vec![("2016-01-01", 1.0), ("2016-01-02", 3.0)]);
+    assert!(aggregate(&[], "week", "mean").is_err());
+  }
+
+  #[test]
+  fn pages_through_stations_in_id_order() {
+    let mut stations = stations_with(&[1, 2, 3]);
+    stations.reverse();
+    let store = store::StationStore::new(stations, 0);
+    let first = station_page(&store, 0, 2);
+    let ids = |page: &StationPage| {
+      page.stations.iter().map(|s| s.usaf.clone()).collect::<Vec<_>>()
+    };
+    assert_eq!(ids(&first), vec!["010000", "010001"]);
+    assert_eq!(first.total, 3);
+    assert_eq!(first.next_offset, Some(2));
+    let second = station_page(&store, first.next_offset.unwrap(), 2);
+    assert_eq!(ids(&second), vec!["010002"]);
+    assert_eq!(second.stations[0].measurements, 3);
+    assert_eq!(second.next_offset, None);
+  }
+
+  #[test]
+  fn extremes_are_first_reached() {
+    let mut series = testing::series(&[Some(3.0),
+                                       Some(-5.0),
+                                       Some(12.0),
+                                       Some(12.0),
+                                       None,
+                                       Some(-5.0)]);
+    series[1].wind = Some(WindMeasurement::observed(2.0, Some(90)));
+    series[3].wind = Some(WindMeasurement::observed(7.5, Some(180)));
+    series[4].wind = Some(WindMeasurement::observed(7.5, None));
+    let (max_temperature, min_temperature, max_wind_speed) =
+      extremes(&series);
+    assert_eq!(max_temperature, Some((12.0, testing::time(2))));
+    assert_eq!(min_temperature, Some((-5.0, testing::time(1))));
+    assert_eq!(max_wind_speed, Some((7.5, testing::time(3))));
+
+    assert_eq!(extremes(&[]), (None, None, None));
+  }
+
+  #[test]
+  fn alerts_filter_and_order_by_threshold() {
+    let stations = [Some(30.0), Some(36.0), Some(40.0), None]
+      .iter()
+      .enumerate()
+      .map(|(i, &t)| {
+        testing::station(&format!("01000{}", i),
+                         i as f32,
+                         0.0,
+                         testing::series(&[t]))
+      })
+      .collect();
+    let store = store::StationStore::new(stations, 0);
+    let alerted = |field: &str, op: &str, value: f32, unit: Option<&str>| {
+      let params = AlertParams {
+        field: String::from(field),
+        op: String::from(op),
+        value: value,
+        start: None,
+        end: None,
+        unit: unit.map(String::from),
+        wind_unit: None,
+      };
+      alerts_of(&store, &params, &RenderOptions::default()).map(|alerts| {
+        alerts.stations
+          .iter()
+          .map(|a| (a.usaf.clone(), a.value))
+          .collect::<Vec<_>>()
+      })
+    };
+    assert_eq!(alerted("temperature", "gt", 35.0, None),
+               Ok(vec![(String::from("010002"), 40.0),
+                       (String::from("010001"), 36.0)]));
+    assert_eq!(alerted("temperature", "lt", 35.0, None),
+               Ok(vec![(String::from("010000"), 30.0)]));
+    // Thresholds are in the requested unit: 36 °C is 96.8 °F, 40 °C 104 °F.
+    assert_eq!(alerted("temperature", "gt", 100.0, Some("f")),
+               Ok(vec![(String::from("010002"), 104.0)]));
+    assert!(alerted("temperature", "ge", 35.0, None).is_err());
+    assert!(alerted("sunshine", "gt", 35.0, None).is_err());
+  }
+}
@@ -0,0 +1,154 @@
+// Standard meteorological wind barbs: a staff pointing into the wind with a
+// pennant per 50 kn, a full barb per 10 kn and a half barb for 5 kn, the
+// speed rounded to the nearest 5 kn. Calm is drawn as a ring instead.
+
+use image;
+
+use draw_line;
+use draw_line_aa;
+use draw_ring;
+use units;
+
+// Staff length in pixels, and the spacing between flags along it.
+const LENGTH: f32 = 16.0;
+const SPACING: f32 = 3.0;
+// How far a full barb sticks out from the staff.
+const FEATHER: f32 = 7.0;
+
+pub type Point = (f32, f32);
+
+#[derive(Debug, Default)]
+pub struct Barb {
+  pub lines: Vec<(Point, Point)>,
+  pub pennants: Vec<(Point, Point, Point)>,
+}
+
+// Numbers of (pennants, full barbs, half barbs) for a speed in m/s.
+pub fn flags(speed: f32) -> (u32, u32, u32) {
+  let fives = (units::WindUnit::Knots.from_ms(speed) / 5.0).round() as u32;
+  (fives / 10, fives % 10 / 2, fives % 2)
+}
+
+// The barb for a wind of `speed` m/s coming from `direction` degrees, for a
+// station at (x, y) in image coordinates.
+pub fn barb(x: f32, y: f32, direction: i32, speed: f32) -> Barb {
+  let angle = (direction as f32).to_radians();
+  // Along the staff, towards where the wind comes from, and perpendicular to
+  // it, clockwise on screen.
+  let (ux, uy) = (angle.sin(), -angle.cos());
+  let (px, py) = (-uy, ux);
+  let at = |along: f32| (x + ux * along, y + uy * along);
+
+  let mut barb = Barb::default();
+  barb.lines.push(((x, y), at(LENGTH)));
+
+  let (pennants, full, half) = flags(speed);
+  let mut along = LENGTH;
+  for _ in 0..pennants {
+    let (sx, sy) = at(along);
+    barb.pennants.push(((sx, sy),
+                        at(along - SPACING),
+                        (sx + px * FEATHER, sy + py * FEATHER)));
+    along -= SPACING + 1.0;
+  }
+  let feather = |along: f32, length: f32| {
+    let (sx, sy) = at(along);
+    ((sx, sy),
+     (sx + px * length + ux * length / 3.0,
+      sy + py * length + uy * length / 3.0))
+  };
+  for _ in 0..full {
+    barb.lines.push(feather(along, FEATHER));
+    along -= SPACING;
+  }
+  if half > 0 {
+    // A lone half barb is set back from the tip so it isn't mistaken for a
+    // full one.
+    if pennants == 0 && full == 0 {
+      along -= SPACING;
+    }
+    barb.lines.push(feather(along, FEATHER / 2.0));
+  }
+  barb
+}
+
+fn put(img: &mut image::RgbImage, x: i32, y: i32, color: image::Rgb<u8>) {
+  let (width, height) = img.dimensions();
+  if x >= 0 && x < width as i32 && y >= 0 && y < height as i32 {
+    img.put_pixel(x as u32, y as u32, color);
+  }
+}
+
+fn fill_triangle(img: &mut image::RgbImage,
+                 (a, b, c): (Point, Point, Point),
+                 color: image::Rgb<u8>) {
+  let cross = |o: Point, p: Point, q: Point| {
+    (p.0 - o.0) * (q.1 - o.1) - (p.1 - o.1) * (q.0 - o.0)
+  };
+  let x_min = a.0.min(b.0).min(c.0).floor() as i32;
+  let x_max = a.0.max(b.0).max(c.0).ceil() as i32;
+  let y_min = a.1.min(b.1).min(c.1).floor() as i32;
+  let y_max = a.1.max(b.1).max(c.1).ceil() as i32;
+  for x in x_min..x_max + 1 {
+    for y in y_min..y_max + 1 {
+      let p = (x as f32, y as f32);
+      let (d0, d1, d2) = (cross(a, b, p), cross(b, c, p), cross(c, a, p));
+      if (d0 >= 0.0 && d1 >= 0.0 && d2 >= 0.0) ||
+         (d0 <= 0.0 && d1 <= 0.0 && d2 <= 0.0) {
+        put(img, x, y, color);
+      }
+    }
+  }
+}
+
+pub fn draw(img: &mut image::RgbImage,
+            barb: &Barb,
+            color: image::Rgb<u8>,
+            antialias: bool) {
+  let line: fn(&mut image::RgbImage, Point, Point, image::Rgb<u8>) =
+    if antialias { draw_line_aa } else { draw_line };
+  for &(from, to) in &barb.lines {
+    line(img, from, to, color);
+  }
+  for &pennant in &barb.pennants {
+    fill_triangle(img, pennant, color);
+  }
+}
+
+pub const CALM_RADIUS: f32 = 3.0;
+
+pub fn draw_calm(img: &mut image::RgbImage,
+                 x: i32,
+                 y: i32,
+                 color: image::Rgb<u8>) {
+  draw_ring(img, x, y, CALM_RADIUS as u32, color);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // m/s of a speed in knots.
+  fn knots(speed: f32) -> f32 {
+    speed * 1852.0 / 3600.0
+  }
+
+  #[test]
+  fn flags_add_up_to_the_speed() {
+    assert_eq!(flags(knots(0.0)), (0, 0, 0));
+    assert_eq!(flags(knots(5.0)), (0, 0, 1));
+    assert_eq!(flags(knots(10.0)), (0, 1, 0));
+    assert_eq!(flags(knots(50.0)), (1, 0, 0));
+    assert_eq!(flags(knots(65.0)), (1, 1, 1));
+    // Rounded to the nearest 5 kn.
+    assert_eq!(flags(knots(23.0)), (0, 2, 1));
+  }
+
+  #[test]
+  fn barb_draws_each_flag() {
+    let barb = barb(0.0, 0.0, 270, knots(65.0));
+    assert_eq!(barb.pennants.len(), 1);
+    // The staff, a full and a half barb.
+    assert_eq!(barb.lines.len(), 3);
+  }
+}
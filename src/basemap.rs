@@ -0,0 +1,83 @@
+// A very coarse outline of the continents and larger islands, drawn under the
+// stations for geographic context without an external tile source. Points
+// are (longitude, latitude) and every outline is closed.
+
+use image;
+
+use draw_line;
+
+pub const COLOR: image::Rgb<u8> = image::Rgb([96, 96, 96]);
+
+const OUTLINES: &'static [&'static [(f32, f32)]] = &[
+  // North America.
+  &[(-168.0, 65.0), (-162.0, 70.0), (-140.0, 70.0), (-125.0, 70.0),
+    (-95.0, 72.0), (-80.0, 73.0), (-65.0, 62.0), (-55.0, 52.0),
+    (-66.0, 44.0), (-70.0, 41.0), (-76.0, 35.0), (-81.0, 30.0),
+    (-80.0, 25.0), (-82.0, 28.0), (-84.0, 30.0), (-90.0, 29.0),
+    (-97.0, 27.0), (-97.0, 21.0), (-87.0, 21.0), (-88.0, 16.0),
+    (-83.0, 10.0), (-78.0, 8.0), (-85.0, 11.0), (-92.0, 15.0),
+    (-105.0, 20.0), (-110.0, 23.0), (-115.0, 30.0), (-117.0, 33.0),
+    (-121.0, 35.0), (-124.0, 40.0), (-124.0, 47.0), (-130.0, 55.0),
+    (-140.0, 60.0), (-152.0, 58.0), (-160.0, 58.0), (-165.0, 62.0),
+    (-168.0, 65.0)],
+  // South America.
+  &[(-78.0, 8.0), (-72.0, 12.0), (-62.0, 10.0), (-50.0, 0.0), (-35.0, -5.0),
+    (-39.0, -15.0), (-48.0, -26.0), (-58.0, -35.0), (-65.0, -42.0),
+    (-68.0, -52.0), (-72.0, -54.0), (-75.0, -48.0), (-73.0, -37.0),
+    (-71.0, -28.0), (-70.0, -18.0), (-76.0, -14.0), (-81.0, -5.0),
+    (-80.0, 0.0), (-77.0, 4.0), (-78.0, 8.0)],
+  // Greenland.
+  &[(-55.0, 60.0), (-43.0, 60.0), (-30.0, 68.0), (-20.0, 75.0),
+    (-20.0, 82.0), (-40.0, 83.0), (-60.0, 82.0), (-70.0, 77.0),
+    (-58.0, 70.0), (-55.0, 60.0)],
+  // Eurasia.
+  &[(-10.0, 36.0), (-9.0, 43.0), (-2.0, 44.0), (-5.0, 48.0), (5.0, 53.0),
+    (8.0, 57.0), (5.0, 62.0), (15.0, 68.0), (25.0, 71.0), (40.0, 67.0),
+    (60.0, 70.0), (80.0, 73.0), (105.0, 78.0), (140.0, 72.0),
+    (170.0, 70.0), (180.0, 66.0), (160.0, 60.0), (155.0, 52.0),
+    (142.0, 46.0), (135.0, 43.0), (129.0, 35.0), (122.0, 31.0),
+    (120.0, 23.0), (110.0, 21.0), (106.0, 10.0), (100.0, 13.0),
+    (104.0, 1.0), (98.0, 8.0), (92.0, 20.0), (80.0, 15.0), (77.0, 8.0),
+    (72.0, 20.0), (67.0, 25.0), (57.0, 25.0), (58.0, 20.0), (52.0, 16.0),
+    (44.0, 12.0), (40.0, 16.0), (35.0, 28.0), (32.0, 31.0), (35.0, 36.0),
+    (27.0, 37.0), (26.0, 40.0), (23.0, 40.0), (22.0, 37.0), (19.0, 40.0),
+    (16.0, 38.0), (12.0, 44.0), (8.0, 44.0), (3.0, 43.0), (-1.0, 37.0),
+    (-6.0, 36.0), (-10.0, 36.0)],
+  // Africa.
+  &[(-17.0, 21.0), (-17.0, 15.0), (-12.0, 7.0), (-5.0, 5.0), (8.0, 4.0),
+    (9.0, -1.0), (13.0, -10.0), (12.0, -17.0), (18.0, -34.0),
+    (25.0, -34.0), (32.0, -29.0), (35.0, -22.0), (40.0, -15.0),
+    (40.0, -5.0), (51.0, 11.0), (43.0, 12.0), (32.0, 31.0), (20.0, 32.0),
+    (10.0, 37.0), (-6.0, 36.0), (-10.0, 30.0), (-17.0, 21.0)],
+  // Great Britain.
+  &[(-5.0, 50.0), (1.0, 51.0), (2.0, 53.0), (-2.0, 56.0), (-2.0, 58.0),
+    (-5.0, 58.6), (-6.0, 56.0), (-3.0, 54.0), (-5.0, 52.0), (-5.0, 50.0)],
+  // Japan.
+  &[(130.0, 31.0), (135.0, 34.0), (140.0, 35.0), (142.0, 40.0),
+    (141.0, 45.0), (145.0, 44.0), (140.0, 41.0), (135.0, 35.0),
+    (130.0, 34.0), (130.0, 31.0)],
+  // Madagascar.
+  &[(44.0, -25.0), (47.0, -25.0), (50.0, -15.0), (49.0, -12.0),
+    (44.0, -16.0), (44.0, -25.0)],
+  // Australia.
+  &[(114.0, -22.0), (114.0, -34.0), (123.0, -34.0), (135.0, -35.0),
+    (140.0, -38.0), (150.0, -38.0), (153.0, -27.0), (146.0, -19.0),
+    (142.0, -11.0), (136.0, -12.0), (130.0, -12.0), (123.0, -17.0),
+    (114.0, -22.0)],
+  // New Zealand.
+  &[(172.0, -34.0), (178.0, -38.0), (175.0, -41.0), (171.0, -46.0),
+    (167.0, -46.0), (172.0, -41.0), (172.0, -34.0)],
+];
+
+// Draws the outlines, with `project` mapping (longitude, latitude) to image
+// coordinates.
+pub fn draw(img: &mut image::RgbImage,
+            project: &Fn(f32, f32) -> (f32, f32),
+            color: image::Rgb<u8>) {
+  for outline in OUTLINES {
+    for segment in outline.windows(2) {
+      let (from, to) = (segment[0], segment[1]);
+      draw_line(img, project(from.0, from.1), project(to.0, to.1), color);
+    }
+  }
+}
@@ -0,0 +1,78 @@
+// Parse throughput benchmarks, run with `cargo bench --features bench`.
+//
+// Bencher only knows how to report throughput in bytes, so b.bytes is set to
+// the number of lines: the reported "MB/s" reads as millions of lines per
+// second.
+
+use std::fs;
+use std::io;
+use std::io::Write;
+
+use clap;
+use flate2;
+use test::Bencher;
+
+use parse;
+use parse_reader;
+use testing;
+use {input_args, load_stations};
+
+const FILENAME: &'static str = "029070-99999-2016";
+const SAMPLE: &'static str = include_str!("../benches/sample.txt");
+
+#[bench]
+fn parse_plain(b: &mut Bencher) {
+  b.bytes = SAMPLE.lines().count() as u64;
+  b.iter(|| {
+    parse(FILENAME, &mut io::Cursor::new(SAMPLE), usize::max_value()).unwrap()
+  });
+}
+
+#[bench]
+fn parse_gzipped(b: &mut Bencher) {
+  let mut encoder = flate2::write::GzEncoder::new(Vec::new(),
+                                                  flate2::Compression::Default);
+  encoder.write_all(SAMPLE.as_bytes()).unwrap();
+  let compressed = encoder.finish().unwrap();
+
+  b.bytes = SAMPLE.lines().count() as u64;
+  b.iter(|| {
+    parse_reader(&format!("{}.gz", FILENAME),
+                 io::Cursor::new(&compressed[..]),
+                 usize::max_value())
+      .unwrap()
+  });
+}
+
+// Loading a directory of many small files, where handing each parsed station
+// from the workers to the loader through the bounded channel dominates.
+#[bench]
+fn load_small_files(b: &mut Bencher) {
+  const NUM_FILES: usize = 500;
+  let dir = testing::scratch_dir("bench-small-files");
+  let lines = SAMPLE.lines().take(10).collect::<Vec<_>>();
+  for i in 0..NUM_FILES {
+    // Each file is a station of its own, named after it.
+    let usaf = format!("{:06}", i);
+    let contents = lines.iter()
+      .map(|line| format!("{}{}{}", &line[..4], usaf, &line[10..]))
+      .collect::<Vec<_>>();
+    fs::File::create(dir.join(format!("{}-99999-2016.txt", usaf)))
+      .unwrap()
+      .write_all(contents.join("\n").as_bytes())
+      .unwrap();
+  }
+  let args = clap::App::new("tenki")
+    .args(&input_args())
+    .get_matches_from(vec!["tenki",
+                           "--directory",
+                           dir.to_str().unwrap(),
+                           "--extensions",
+                           "txt",
+                           "--channel-bound",
+                           "4"]);
+
+  b.bytes = (NUM_FILES * lines.len()) as u64;
+  b.iter(|| load_stations(&args, None));
+  fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,99 @@
+// In-process cache of encoded tiles, so that panning back and forth over the
+// same area doesn't re-render every tile.
+
+use std::collections;
+use std::sync;
+use std::time;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TileKey {
+  pub zoom: u32,
+  pub x: u32,
+  pub y: u32,
+  // StationStore::version the tile was rendered from.
+  pub version: u64,
+  // Canonical form of every parameter that affects the rendered tile.
+  pub params: String,
+}
+
+#[derive(Clone)]
+pub struct Tile {
+  pub png: Vec<u8>,
+  pub stations_drawn: usize,
+}
+
+pub struct TileCache {
+  capacity: usize,
+  ttl: time::Duration,
+  tiles: sync::Mutex<collections::HashMap<TileKey, (time::Instant, Tile)>>,
+}
+
+impl TileCache {
+  // A capacity of 0 disables caching.
+  pub fn new(capacity: usize, ttl: time::Duration) -> TileCache {
+    TileCache {
+      capacity: capacity,
+      ttl: ttl,
+      tiles: sync::Mutex::new(collections::HashMap::new()),
+    }
+  }
+
+  pub fn get(&self, key: &TileKey) -> Option<Tile> {
+    let mut tiles = self.tiles.lock().unwrap();
+    let expired = match tiles.get(key) {
+      Some(&(inserted, ref tile)) if inserted.elapsed() < self.ttl => {
+        return Some(tile.clone())
+      }
+      Some(_) => true,
+      None => false,
+    };
+    if expired {
+      tiles.remove(key);
+    }
+    None
+  }
+
+  // Inserts a tile, evicting the oldest one if the cache is full.
+  pub fn insert(&self, key: TileKey, tile: Tile) {
+    if self.capacity == 0 {
+      return;
+    }
+    let mut tiles = self.tiles.lock().unwrap();
+    if tiles.len() >= self.capacity && !tiles.contains_key(&key) {
+      let oldest = tiles.iter()
+        .min_by_key(|&(_, &(inserted, _))| inserted)
+        .map(|(key, _)| key.clone());
+      if let Some(oldest) = oldest {
+        tiles.remove(&oldest);
+      }
+    }
+    tiles.insert(key, (time::Instant::now(), tile));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn key(version: u64) -> TileKey {
+    TileKey {
+      zoom: 3,
+      x: 1,
+      y: 2,
+      version: version,
+      params: String::from("RenderOptions { .. }"),
+    }
+  }
+
+  #[test]
+  fn new_versions_miss_old_tiles() {
+    let cache = TileCache::new(10, time::Duration::from_secs(60));
+    cache.insert(key(1),
+                 Tile {
+                   png: vec![1, 2, 3],
+                   stations_drawn: 4,
+                 });
+    assert_eq!(cache.get(&key(1)).map(|tile| tile.png), Some(vec![1, 2, 3]));
+    assert!(cache.get(&key(2)).is_none());
+  }
+}
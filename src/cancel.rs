@@ -0,0 +1,38 @@
+// Ctrl-C handling. While the input loads, the first Ctrl-C only asks loading
+// to stop early, keeping what was parsed so far; at any other time, and on a
+// second Ctrl-C, the process exits right away.
+
+use std::process;
+use std::sync::atomic;
+
+use ctrlc;
+
+static LOADING: atomic::AtomicBool = atomic::ATOMIC_BOOL_INIT;
+static CANCELLED: atomic::AtomicBool = atomic::ATOMIC_BOOL_INIT;
+
+pub fn install() {
+  ctrlc::set_handler(|| {
+      if LOADING.load(atomic::Ordering::SeqCst) &&
+         !CANCELLED.swap(true, atomic::Ordering::SeqCst) {
+        warn!("cancelling the load once running parses finish; press \
+               Ctrl-C again to quit");
+      } else {
+        process::exit(130);
+      }
+    })
+    .unwrap();
+}
+
+pub fn set_loading(loading: bool) {
+  LOADING.store(loading, atomic::Ordering::SeqCst);
+}
+
+// Whether loading was cancelled.
+pub fn requested() -> bool {
+  CANCELLED.load(atomic::Ordering::SeqCst)
+}
+
+// The flag behind requested(), for loaders that are handed the flag to check.
+pub fn flag() -> &'static atomic::AtomicBool {
+  &CANCELLED
+}
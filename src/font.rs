@@ -0,0 +1,92 @@
+// A tiny 3x5 pixel bitmap font, enough for short attribution lines. Lower
+// case letters are drawn as upper case, unknown characters as '?'.
+
+use image;
+
+pub const GLYPH_WIDTH: u32 = 3;
+pub const GLYPH_HEIGHT: u32 = 5;
+// Blank columns between glyphs.
+pub const SPACING: u32 = 1;
+
+// Each row is three bits, the most significant one leftmost.
+fn glyph(c: char) -> [u8; 5] {
+  match c.to_ascii_uppercase() {
+    'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+    'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+    'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+    'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+    'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+    'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+    'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+    'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+    'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+    'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+    'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+    'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+    'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+    'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+    'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+    'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+    'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+    'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+    'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+    'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+    'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+    'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+    'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+    'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+    'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+    'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+    '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+    '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+    '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+    '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+    '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+    '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+    '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+    '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+    '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+    '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+    ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+    '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+    ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+    ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+    '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+    '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+    '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+    ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+    _ => [0b110, 0b001, 0b010, 0b000, 0b010],
+  }
+}
+
+pub fn text_width(text: &str) -> u32 {
+  let n = text.chars().count() as u32;
+  if n == 0 {
+    0
+  } else {
+    n * (GLYPH_WIDTH + SPACING) - SPACING
+  }
+}
+
+// Draws text with its top left corner at (x, y), clipped to the image.
+pub fn draw_text(img: &mut image::RgbImage,
+                 x: i32,
+                 y: i32,
+                 text: &str,
+                 color: image::Rgb<u8>) {
+  let (width, height) = img.dimensions();
+  for (i, c) in text.chars().enumerate() {
+    let left = x + (i as u32 * (GLYPH_WIDTH + SPACING)) as i32;
+    for (row, bits) in glyph(c).iter().enumerate() {
+      for column in 0..GLYPH_WIDTH {
+        if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+          continue;
+        }
+        let (px, py) = (left + column as i32, y + row as i32);
+        if px >= 0 && px < width as i32 && py >= 0 && py < height as i32 {
+          img.put_pixel(px as u32, py as u32, color);
+        }
+      }
+    }
+  }
+}
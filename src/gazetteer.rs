@@ -0,0 +1,124 @@
+// Station names from NOAA's station history file, isd-history.csv:
+// https://www1.ncdc.noaa.gov/pub/data/noaa/isd-history.csv
+
+use std::collections;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::mem;
+
+use WeatherStation;
+
+pub struct Entry {
+  pub name: Option<String>,
+  pub country: Option<String>,
+  pub state: Option<String>,
+}
+
+// Splits a CSV line, removing the quotes around fields.
+fn split_csv(line: &str) -> Vec<String> {
+  let mut fields = vec![];
+  let mut field = String::new();
+  let mut quoted = false;
+  let mut chars = line.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      '"' if quoted && chars.peek() == Some(&'"') => {
+        chars.next();
+        field.push('"');
+      }
+      '"' => quoted = !quoted,
+      ',' if !quoted => fields.push(mem::replace(&mut field, String::new())),
+      _ => field.push(c),
+    }
+  }
+  fields.push(field);
+  fields
+}
+
+// Maps (usaf, wban) to the station's entry.
+pub type Gazetteer = collections::HashMap<(String, String), Entry>;
+
+pub fn load(path: &str) -> Result<Gazetteer, io::Error> {
+  let f = try!(fs::File::open(path));
+  let mut lines = io::BufReader::new(f).lines();
+
+  let header = match lines.next() {
+    Some(line) => split_csv(&try!(line)),
+    None => return Ok(collections::HashMap::new()),
+  };
+  let column = |name: &str| {
+    header.iter().position(|h| h == name).ok_or_else(|| {
+      io::Error::new(io::ErrorKind::InvalidData,
+                     format!("{} has no {:?} column", path, name))
+    })
+  };
+  let usaf = try!(column("USAF"));
+  let wban = try!(column("WBAN"));
+  let name = try!(column("STATION NAME"));
+  let country = try!(column("CTRY"));
+  let state = try!(column("STATE"));
+
+  let mut entries = collections::HashMap::new();
+  for line in lines {
+    let fields = split_csv(&try!(line));
+    let get = |i: usize| {
+      fields.get(i)
+        .map(|f| f.trim())
+        .and_then(|f| if f.is_empty() { None } else { Some(f) })
+    };
+    let (usaf, wban) = match (get(usaf), get(wban)) {
+      (Some(usaf), Some(wban)) => (usaf.to_string(), wban.to_string()),
+      _ => continue,
+    };
+    entries.insert((usaf, wban),
+                   Entry {
+                     name: get(name).map(String::from),
+                     country: get(country).map(String::from),
+                     state: get(state).map(String::from),
+                   });
+  }
+  Ok(entries)
+}
+
+// Fills in the names of the stations found in the gazetteer. Returns the
+// number of stations that weren't.
+pub fn apply(entries: &Gazetteer,
+             stations: &mut Vec<WeatherStation>)
+             -> usize {
+  let mut num_unknown = 0;
+  for station in stations.iter_mut() {
+    match entries.get(&(station.usaf.clone(), station.wban.clone())) {
+      Some(entry) => {
+        station.name = entry.name.clone();
+        station.country = entry.country.clone();
+        station.state = entry.state.clone();
+      }
+      None => num_unknown += 1,
+    }
+  }
+  num_unknown
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing;
+
+  #[test]
+  fn names_stations_by_id() {
+    let csv = "\"USAF\",\"WBAN\",\"STATION NAME\",\"CTRY\",\"STATE\"\n\
+               \"010000\",\"99999\",\"BOGUS \"\"NORWAY\"\", N\",\"NO\",\"\"\n";
+    let filename = testing::scratch_file("gazetteer",
+                                         "isd-history.csv",
+                                         csv.as_bytes());
+    let entries = load(filename.to_str().unwrap()).unwrap();
+    let mut stations = vec![testing::station("010000", 0.0, 0.0, vec![]),
+                            testing::station("020000", 0.0, 0.0, vec![])];
+    assert_eq!(apply(&entries, &mut stations), 1);
+    assert_eq!(stations[0].name, Some(String::from("BOGUS \"NORWAY\", N")));
+    assert_eq!(stations[0].country, Some(String::from("NO")));
+    assert_eq!(stations[0].state, None);
+    assert_eq!(stations[1].name, None);
+  }
+}
@@ -0,0 +1,252 @@
+// Minimal writer for single-band 32-bit float GeoTIFFs on a WGS 84
+// longitude/latitude grid: one uncompressed strip, georeferenced by a tie
+// point at the top left corner and a pixel size.
+// Format references:
+// https://www.itu.int/itudoc/itu-t/com16/tiff-fx/docs/tiff6.pdf
+// http://docs.opengeospatial.org/is/19-008r4/19-008r4.html
+
+use std::io;
+use std::io::Write;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+// Field types.
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const DOUBLE: u16 = 12;
+
+// TIFF tags.
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const SAMPLE_FORMAT: u16 = 339;
+const MODEL_PIXEL_SCALE: u16 = 33550;
+const MODEL_TIEPOINT: u16 = 33922;
+const GEO_KEY_DIRECTORY: u16 = 34735;
+// Not part of GeoTIFF, but how GDAL and everything built on it learn the
+// nodata value.
+const GDAL_NODATA: u16 = 42113;
+
+// GeoKeys.
+const GT_MODEL_TYPE: u16 = 1024;
+const GT_RASTER_TYPE: u16 = 1025;
+const GEOGRAPHIC_TYPE: u16 = 2048;
+const MODEL_TYPE_GEOGRAPHIC: u16 = 2;
+const RASTER_PIXEL_IS_AREA: u16 = 1;
+const GCS_WGS_84: u16 = 4326;
+
+const HEADER_LEN: u32 = 8;
+const ENTRY_LEN: u32 = 12;
+
+pub struct Raster {
+  pub width: u32,
+  pub height: u32,
+  // Corner of the top left pixel, in degrees.
+  pub longitude_min: f64,
+  pub latitude_max: f64,
+  // Pixel size in degrees.
+  pub longitude_step: f64,
+  pub latitude_step: f64,
+  // Stands in for missing values.
+  pub nodata: f32,
+  // Row-major, starting with the northernmost row.
+  pub data: Vec<f32>,
+}
+
+// A directory entry with its value already encoded.
+struct Entry {
+  tag: u16,
+  field_type: u16,
+  count: u32,
+  value: Vec<u8>,
+}
+
+fn shorts(tag: u16, values: &[u16]) -> Entry {
+  let mut value = vec![];
+  for &v in values {
+    value.write_u16::<LittleEndian>(v).unwrap();
+  }
+  Entry {
+    tag: tag,
+    field_type: SHORT,
+    count: values.len() as u32,
+    value: value,
+  }
+}
+
+fn long(tag: u16, v: u32) -> Entry {
+  let mut value = vec![];
+  value.write_u32::<LittleEndian>(v).unwrap();
+  Entry {
+    tag: tag,
+    field_type: LONG,
+    count: 1,
+    value: value,
+  }
+}
+
+fn doubles(tag: u16, values: &[f64]) -> Entry {
+  let mut value = vec![];
+  for &v in values {
+    value.write_f64::<LittleEndian>(v).unwrap();
+  }
+  Entry {
+    tag: tag,
+    field_type: DOUBLE,
+    count: values.len() as u32,
+    value: value,
+  }
+}
+
+fn ascii(tag: u16, s: &str) -> Entry {
+  let mut value = s.as_bytes().to_vec();
+  value.push(0);
+  Entry {
+    tag: tag,
+    field_type: ASCII,
+    count: value.len() as u32,
+    value: value,
+  }
+}
+
+impl Raster {
+  pub fn write(&self, w: &mut Write) -> Result<(), io::Error> {
+    let data_len = self.data.len() as u32 * 4;
+    let geo_keys = [1, 1, 0, 3, GT_MODEL_TYPE, 0, 1, MODEL_TYPE_GEOGRAPHIC,
+                    GT_RASTER_TYPE, 0, 1, RASTER_PIXEL_IS_AREA,
+                    GEOGRAPHIC_TYPE, 0, 1, GCS_WGS_84];
+    // Sorted by tag, as readers expect. The strip offset is filled in once
+    // the size of everything before the pixels is known.
+    let mut entries = vec![long(IMAGE_WIDTH, self.width),
+                           long(IMAGE_LENGTH, self.height),
+                           shorts(BITS_PER_SAMPLE, &[32]),
+                           shorts(COMPRESSION, &[1]),
+                           // BlackIsZero.
+                           shorts(PHOTOMETRIC_INTERPRETATION, &[1]),
+                           long(STRIP_OFFSETS, 0),
+                           shorts(SAMPLES_PER_PIXEL, &[1]),
+                           long(ROWS_PER_STRIP, self.height),
+                           long(STRIP_BYTE_COUNTS, data_len),
+                           // IEEE floating point.
+                           shorts(SAMPLE_FORMAT, &[3]),
+                           doubles(MODEL_PIXEL_SCALE,
+                                   &[self.longitude_step,
+                                     self.latitude_step,
+                                     0.0]),
+                           doubles(MODEL_TIEPOINT,
+                                   &[0.0,
+                                     0.0,
+                                     0.0,
+                                     self.longitude_min,
+                                     self.latitude_max,
+                                     0.0]),
+                           shorts(GEO_KEY_DIRECTORY, &geo_keys),
+                           ascii(GDAL_NODATA, &self.nodata.to_string())];
+
+    // Values longer than four bytes go after the directory, word aligned.
+    let directory_len = 2 + entries.len() as u32 * ENTRY_LEN + 4;
+    let mut offset = HEADER_LEN + directory_len;
+    let mut offsets = vec![];
+    for entry in &entries {
+      offsets.push(offset);
+      if entry.value.len() > 4 {
+        offset += (entry.value.len() as u32 + 1) & !1;
+      }
+    }
+    for entry in &mut entries {
+      if entry.tag == STRIP_OFFSETS {
+        *entry = long(STRIP_OFFSETS, offset);
+      }
+    }
+
+    try!(w.write_all(b"II"));
+    try!(w.write_u16::<LittleEndian>(42));
+    try!(w.write_u32::<LittleEndian>(HEADER_LEN));
+    try!(w.write_u16::<LittleEndian>(entries.len() as u16));
+    for (entry, &offset) in entries.iter().zip(&offsets) {
+      try!(w.write_u16::<LittleEndian>(entry.tag));
+      try!(w.write_u16::<LittleEndian>(entry.field_type));
+      try!(w.write_u32::<LittleEndian>(entry.count));
+      if entry.value.len() > 4 {
+        try!(w.write_u32::<LittleEndian>(offset));
+      } else {
+        try!(w.write_all(&entry.value));
+        try!(w.write_all(&[0u8; 4][entry.value.len()..]));
+      }
+    }
+    // No further directories.
+    try!(w.write_u32::<LittleEndian>(0));
+    for entry in entries.iter().filter(|entry| entry.value.len() > 4) {
+      try!(w.write_all(&entry.value));
+      if entry.value.len() % 2 == 1 {
+        try!(w.write_all(&[0]));
+      }
+    }
+    for &v in &self.data {
+      try!(w.write_f32::<LittleEndian>(v));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use byteorder::{ByteOrder, LittleEndian};
+
+  // Tag to (count, value or offset) from the first directory.
+  fn directory(tiff: &[u8]) -> Vec<(u16, u32, u32)> {
+    let start = LittleEndian::read_u32(&tiff[4..8]) as usize;
+    let n = LittleEndian::read_u16(&tiff[start..start + 2]) as usize;
+    (0..n)
+      .map(|i| {
+        let entry = &tiff[start + 2 + i * ENTRY_LEN as usize..];
+        (LittleEndian::read_u16(&entry[0..2]),
+         LittleEndian::read_u32(&entry[4..8]),
+         LittleEndian::read_u32(&entry[8..12]))
+      })
+      .collect()
+  }
+
+  #[test]
+  fn pixels_read_back() {
+    let raster = Raster {
+      width: 3,
+      height: 2,
+      longitude_min: 10.0,
+      latitude_max: 50.0,
+      longitude_step: 0.5,
+      latitude_step: 0.25,
+      nodata: -9999.0,
+      data: vec![1.0, 2.0, 3.0, 4.0, 5.5, -9999.0],
+    };
+    let mut tiff = vec![];
+    raster.write(&mut tiff).unwrap();
+    assert_eq!(&tiff[..4], b"II*\0");
+
+    let entries = directory(&tiff);
+    let value = |tag: u16| {
+      entries.iter().find(|entry| entry.0 == tag).map(|entry| entry.2)
+    };
+    assert_eq!(value(IMAGE_WIDTH), Some(3));
+    assert_eq!(value(IMAGE_LENGTH), Some(2));
+    // Row 1, column 1.
+    let strip = value(STRIP_OFFSETS).unwrap() as usize;
+    let pixel = strip + (raster.width as usize + 1) * 4;
+    assert_eq!(LittleEndian::read_f32(&tiff[pixel..pixel + 4]), 5.5);
+    assert_eq!(tiff.len(), strip + 6 * 4);
+
+    let tiepoint = value(MODEL_TIEPOINT).unwrap() as usize;
+    assert_eq!(LittleEndian::read_f64(&tiff[tiepoint + 24..tiepoint + 32]),
+               10.0);
+    assert_eq!(LittleEndian::read_f64(&tiff[tiepoint + 32..tiepoint + 40]),
+               50.0);
+  }
+}
@@ -0,0 +1,257 @@
+// Spatial interpolation of scattered station values onto regular grids.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+
+pub const EARTH_RADIUS_KM: f32 = 6371.0;
+
+// Great-circle distance between two points given in degrees.
+pub fn haversine_km(latitude_a: f32,
+                    longitude_a: f32,
+                    latitude_b: f32,
+                    longitude_b: f32)
+                    -> f32 {
+  let d_lat = (latitude_b - latitude_a).to_radians();
+  let d_lon = (longitude_b - longitude_a).to_radians();
+  let a = (d_lat / 2.0).sin().powi(2) +
+          latitude_a.to_radians().cos() * latitude_b.to_radians().cos() *
+          (d_lon / 2.0).sin().powi(2);
+  2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+// A value observed at a location, typically one per station.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+  pub latitude: f32,
+  pub longitude: f32,
+  pub value: f32,
+}
+
+// Inverse distance weighted (power 2) estimate at a location from all samples
+// within max_distance_km, or None if there are none. With fewer than
+// min_neighbors such samples, which makes for noisy estimates, the nearest
+// one is used as is.
+pub fn idw(samples: &[Sample],
+           latitude: f32,
+           longitude: f32,
+           max_distance_km: f32,
+           min_neighbors: usize)
+           -> Option<f32> {
+  let mut weighted_sum = 0.0;
+  let mut total_weight = 0.0;
+  let mut neighbors = 0;
+  // (distance, value) of the closest sample.
+  let mut nearest: Option<(f32, f32)> = None;
+  for sample in samples {
+    let d =
+      haversine_km(latitude, longitude, sample.latitude, sample.longitude);
+    if d > max_distance_km {
+      continue;
+    }
+    if d < 1e-3 {
+      return Some(sample.value);
+    }
+    let weight = 1.0 / (d * d);
+    weighted_sum += weight * sample.value;
+    total_weight += weight;
+    neighbors += 1;
+    if nearest.map_or(true, |(nearest_d, _)| d < nearest_d) {
+      nearest = Some((d, sample.value));
+    }
+  }
+  if neighbors < min_neighbors {
+    nearest.map(|(_, value)| value)
+  } else if total_weight > 0.0 {
+    Some(weighted_sum / total_weight)
+  } else {
+    None
+  }
+}
+
+// Cell centers of a regular axis of `resolution` degree cells covering
+// [min, max].
+pub fn axis(min: f32, max: f32, resolution: f32) -> Vec<f32> {
+  let n = ((max - min) / resolution).round().max(1.0) as usize;
+  (0..n).map(|i| min + (i as f32 + 0.5) * resolution).collect()
+}
+
+// Interpolates the samples onto the grid spanned by the given axes, row-major
+// with latitude varying slowest.
+pub fn interpolate(samples: &[Sample],
+                   latitudes: &[f32],
+                   longitudes: &[f32],
+                   max_distance_km: f32,
+                   min_neighbors: usize)
+                   -> Vec<Option<f32>> {
+  let mut values = Vec::with_capacity(latitudes.len() * longitudes.len());
+  for &latitude in latitudes {
+    for &longitude in longitudes {
+      values.push(idw(samples,
+                      latitude,
+                      longitude,
+                      max_distance_km,
+                      min_neighbors));
+    }
+  }
+  values
+}
+
+// Values on a regular latitude/longitude grid, such as a climatological
+// baseline to compare observations against.
+pub struct Grid {
+  // Ascending cell centers.
+  latitudes: Vec<f32>,
+  longitudes: Vec<f32>,
+  // Row-major with latitude varying slowest, as interpolate() returns.
+  values: Vec<Option<f32>>,
+}
+
+// Only the shape, as tile cache keys include the render options' Debug form.
+impl fmt::Debug for Grid {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Grid({}x{})", self.latitudes.len(), self.longitudes.len())
+  }
+}
+
+// Indices of the axis values bracketing x and the fraction of the way from
+// the first to the second, or None outside the axis.
+fn bracket(axis: &[f32], x: f32) -> Option<(usize, usize, f32)> {
+  if axis.len() < 2 || x < axis[0] || x > axis[axis.len() - 1] {
+    return None;
+  }
+  let i = axis.windows(2).position(|w| x <= w[1]).unwrap();
+  Some((i, i + 1, (x - axis[i]) / (axis[i + 1] - axis[i])))
+}
+
+impl Grid {
+  // Reads `latitude,longitude,value` lines covering every cell of a regular
+  // grid, skipping a header line if there is one. Empty or NaN values are
+  // missing cells; coordinates must be finite.
+  pub fn load_csv(path: &str) -> Result<Grid, io::Error> {
+    let invalid = |message: String| {
+      io::Error::new(io::ErrorKind::InvalidData,
+                     format!("{}: {}", path, message))
+    };
+    let f = try!(fs::File::open(path));
+    let mut cells = vec![];
+    for (i, line) in io::BufReader::new(f).lines().enumerate() {
+      let line = try!(line);
+      let fields = line.split(',').map(|f| f.trim()).collect::<Vec<_>>();
+      if fields.len() != 3 {
+        return Err(invalid(format!("line {} needs 3 fields", i + 1)));
+      }
+      let (latitude, longitude) = match (fields[0].parse::<f32>(),
+                                         fields[1].parse::<f32>()) {
+        (Ok(latitude), Ok(longitude)) if latitude.is_finite() &&
+                                         longitude.is_finite() => {
+          (latitude, longitude)
+        }
+        _ if i == 0 => continue,
+        _ => return Err(invalid(format!("bad coordinates on line {}", i + 1))),
+      };
+      let value = match fields[2].parse::<f32>() {
+        Ok(v) if !v.is_nan() => Some(v),
+        _ => None,
+      };
+      cells.push((latitude, longitude, value));
+    }
+
+    let axis = |coordinate: &Fn(&(f32, f32, Option<f32>)) -> f32| {
+      let mut values = cells.iter().map(coordinate).collect::<Vec<_>>();
+      // Coordinates are finite, so they are totally ordered.
+      values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+      values.dedup();
+      values
+    };
+    let latitudes = axis(&|c| c.0);
+    let longitudes = axis(&|c| c.1);
+    if cells.len() != latitudes.len() * longitudes.len() {
+      return Err(invalid(format!("{} cells don't form a regular grid",
+                                 cells.len())));
+    }
+
+    let mut values = vec![None; cells.len()];
+    for &(latitude, longitude, value) in &cells {
+      let row = latitudes.iter().position(|&l| l == latitude).unwrap();
+      let column = longitudes.iter().position(|&l| l == longitude).unwrap();
+      values[row * longitudes.len() + column] = value;
+    }
+    Ok(Grid {
+      latitudes: latitudes,
+      longitudes: longitudes,
+      values: values,
+    })
+  }
+
+  // Bilinear interpolation between the four surrounding cell centers. None
+  // outside the grid or if any of them is missing.
+  pub fn sample(&self, latitude: f32, longitude: f32) -> Option<f32> {
+    let (row0, row1, fy) = match bracket(&self.latitudes, latitude) {
+      Some(b) => b,
+      None => return None,
+    };
+    let (column0, column1, fx) = match bracket(&self.longitudes, longitude) {
+      Some(b) => b,
+      None => return None,
+    };
+    let at = |row: usize, column: usize| {
+      self.values[row * self.longitudes.len() + column]
+    };
+    match (at(row0, column0), at(row0, column1), at(row1, column0),
+           at(row1, column1)) {
+      (Some(v00), Some(v01), Some(v10), Some(v11)) => {
+        let bottom = v00 + (v01 - v00) * fx;
+        let top = v10 + (v11 - v10) * fx;
+        Some(bottom + (top - bottom) * fy)
+      }
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing;
+
+  fn load(test: &str, csv: &str) -> Result<Grid, io::Error> {
+    let path = testing::scratch_file(test, "grid.csv", csv.as_bytes());
+    Grid::load_csv(path.to_str().unwrap())
+  }
+
+  #[test]
+  fn samples_between_cell_centers() {
+    let grid = load("grid-tiny",
+                    "lat,lon,t\n0,0,0\n0,10,10\n10,0,20\n10,10,30\n")
+      .unwrap();
+    assert_eq!(grid.sample(5.0, 5.0), Some(15.0));
+    assert_eq!(grid.sample(0.0, 10.0), Some(10.0));
+    assert_eq!(grid.sample(-1.0, 5.0), None);
+  }
+
+  #[test]
+  fn rejects_nan_coordinates() {
+    assert!(load("grid-nan", "lat,lon,t\n0,0,0\nNaN,10,10\n").is_err());
+  }
+
+  #[test]
+  fn idw_falls_back_to_the_nearest_sample_when_sparse() {
+    let samples = [Sample {
+                     latitude: 0.0,
+                     longitude: 1.0,
+                     value: 10.0,
+                   },
+                   Sample {
+                     latitude: 0.0,
+                     longitude: -3.0,
+                     value: 20.0,
+                   }];
+    // Weighted by inverse squared distance: 1 and 1/9.
+    let blended = idw(&samples, 0.0, 0.0, 1000.0, 2).unwrap();
+    assert!((blended - 11.0).abs() < 1e-3, "{}", blended);
+    assert_eq!(idw(&samples, 0.0, 0.0, 1000.0, 3), Some(10.0));
+    assert_eq!(idw(&samples, 0.0, 0.0, 10.0, 1), None);
+  }
+}
@@ -0,0 +1,64 @@
+// Per-station color overrides for annotating figures, loaded from a file of
+// `<usaf>-<wban> <RRGGBB>` lines. Blank lines and lines starting with '#'
+// are ignored.
+
+use std::collections;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+
+use image;
+
+use parse_color;
+
+pub struct Highlights {
+  colors: collections::HashMap<(String, String), image::Rgb<u8>>,
+  // Highlighted markers are drawn this many times larger.
+  pub scale: u32,
+}
+
+// Only the size, as the options (and so the highlights) are part of the tile
+// cache key.
+impl fmt::Debug for Highlights {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Highlights({}x{})", self.colors.len(), self.scale)
+  }
+}
+
+impl Highlights {
+  pub fn load(path: &str, scale: u32) -> Result<Highlights, io::Error> {
+    let f = io::BufReader::new(try!(fs::File::open(path)));
+    let mut colors = collections::HashMap::new();
+    for (line_number, line) in f.lines().enumerate() {
+      let line = try!(line);
+      let line = line.trim();
+      if line.is_empty() || line.starts_with("#") {
+        continue;
+      }
+      let invalid = |message: String| {
+        io::Error::new(io::ErrorKind::InvalidData,
+                       format!("{}:{}: {}", path, line_number + 1, message))
+      };
+      let fields = line.split_whitespace().collect::<Vec<_>>();
+      if fields.len() != 2 {
+        return Err(invalid(format!("expected <usaf>-<wban> <RRGGBB>, got {:?}",
+                                   line)));
+      }
+      let id = fields[0].split("-").collect::<Vec<_>>();
+      if id.len() != 2 {
+        return Err(invalid(format!("invalid station id {:?}", fields[0])));
+      }
+      let color = try!(parse_color(fields[1]).map_err(&invalid));
+      colors.insert((String::from(id[0]), String::from(id[1])), color);
+    }
+    Ok(Highlights {
+      colors: colors,
+      scale: scale,
+    })
+  }
+
+  pub fn get(&self, usaf: &str, wban: &str) -> Option<image::Rgb<u8>> {
+    self.colors.get(&(String::from(usaf), String::from(wban))).cloned()
+  }
+}
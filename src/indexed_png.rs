@@ -0,0 +1,101 @@
+// Encoder for 8 bit indexed-color PNGs, which the image crate can't write.
+// Tiles use few distinct colors, so these are much smaller than RGB ones.
+// Format reference: https://www.w3.org/TR/PNG/
+
+use std::collections;
+use std::io;
+use std::io::Write;
+
+use byteorder::{BigEndian, WriteBytesExt};
+use flate2;
+use image;
+
+const SIGNATURE: &'static [u8] = b"\x89PNG\r\n\x1a\n";
+const COLOR_TYPE_INDEXED: u8 = 3;
+
+// The distinct colors of the image and each pixel's index into them, or None
+// if there are more than fit in a palette.
+pub fn index_colors(img: &image::RgbImage) -> Option<(Vec<[u8; 3]>, Vec<u8>)> {
+  let mut palette = vec![];
+  let mut lookup = collections::HashMap::new();
+  let mut indices = Vec::with_capacity((img.width() * img.height()) as usize);
+  for pixel in img.pixels() {
+    let color = pixel.data;
+    let index = match lookup.get(&color) {
+      Some(&index) => index,
+      None => {
+        if palette.len() == 256 {
+          return None;
+        }
+        palette.push(color);
+        lookup.insert(color, (palette.len() - 1) as u8);
+        (palette.len() - 1) as u8
+      }
+    };
+    indices.push(index);
+  }
+  Some((palette, indices))
+}
+
+fn write_chunk(w: &mut Write,
+               kind: &[u8],
+               data: &[u8])
+               -> Result<(), io::Error> {
+  try!(w.write_u32::<BigEndian>(data.len() as u32));
+  try!(w.write_all(kind));
+  try!(w.write_all(data));
+  let mut crc = flate2::crc::Crc::new();
+  crc.update(kind);
+  crc.update(data);
+  w.write_u32::<BigEndian>(crc.sum())
+}
+
+// Encodes row-major palette indices, one byte per pixel.
+pub fn encode(width: u32,
+              height: u32,
+              palette: &[[u8; 3]],
+              indices: &[u8])
+              -> Result<Vec<u8>, io::Error> {
+  ret_check_eq!(indices.len(), (width * height) as usize);
+
+  let mut header = vec![];
+  try!(header.write_u32::<BigEndian>(width));
+  try!(header.write_u32::<BigEndian>(height));
+  // Bit depth, color type, compression, filter and interlace method.
+  try!(header.write_all(&[8, COLOR_TYPE_INDEXED, 0, 0, 0]));
+
+  let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(),
+                                                    flate2::Compression::Best);
+  for row in indices.chunks(width as usize) {
+    // Filter type None.
+    try!(encoder.write_all(&[0]));
+    try!(encoder.write_all(row));
+  }
+  let data = try!(encoder.finish());
+
+  let mut png = Vec::from(SIGNATURE);
+  try!(write_chunk(&mut png, b"IHDR", &header));
+  try!(write_chunk(&mut png, b"PLTE", &palette.concat()));
+  try!(write_chunk(&mut png, b"IDAT", &data));
+  try!(write_chunk(&mut png, b"IEND", &[]));
+  Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decodes_to_the_original_pixels() {
+    let img = image::ImageBuffer::from_fn(5, 3, |x, y| if (x + y) % 2 == 0 {
+      image::Rgb([255u8, 0, 0])
+    } else {
+      image::Rgb([0u8, 0, 255])
+    });
+    let (palette, indices) = index_colors(&img).unwrap();
+    assert_eq!(palette.len(), 2);
+    let png = encode(img.width(), img.height(), &palette, &indices).unwrap();
+    let decoded = image::load_from_memory(&png).unwrap().to_rgb();
+    assert_eq!(decoded.into_raw(), img.into_raw());
+  }
+}
@@ -0,0 +1,61 @@
+// JSON responses. Unlike rocket_contrib::JSON, which request bodies still
+// use, these are indented when --pretty-json is given, for reading them while
+// debugging.
+
+use std::sync::atomic;
+
+use rocket::http::Status;
+use rocket::response;
+use rocket::response::{content, Responder};
+use serde::Serialize;
+use serde_json;
+
+static PRETTY: atomic::AtomicBool = atomic::ATOMIC_BOOL_INIT;
+
+pub fn set_pretty(pretty: bool) {
+  PRETTY.store(pretty, atomic::Ordering::Relaxed);
+}
+
+pub struct JSON<T>(pub T);
+
+fn body<T: Serialize>(value: &T,
+                      pretty: bool)
+                      -> Result<String, serde_json::Error> {
+  if pretty {
+    serde_json::to_string_pretty(value)
+  } else {
+    serde_json::to_string(value)
+  }
+}
+
+impl<'r, T: Serialize> Responder<'r> for JSON<T> {
+  fn respond(self) -> response::Result<'r> {
+    match body(&self.0, PRETTY.load(atomic::Ordering::Relaxed)) {
+      Ok(body) => content::JSON(body).respond(),
+      Err(error) => {
+        warn!("failed to serialize response: {}", error);
+        Err(Status::InternalServerError)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Serialize)]
+  struct Response {
+    stations: Vec<u32>,
+  }
+
+  #[test]
+  fn pretty_bodies_are_indented() {
+    let response = Response { stations: vec![1, 2] };
+    assert_eq!(body(&response, false).unwrap(), r#"{"stations":[1,2]}"#);
+    let pretty = body(&response, true).unwrap();
+    assert!(pretty.contains("\n  \"stations\": [\n    1,\n    2\n  ]"),
+            "{}",
+            pretty);
+  }
+}
@@ -0,0 +1,26 @@
+// Tiny leveled logging to stdout. Rocket installs its own `log` backend when
+// it launches, so ours stays independent of the `log` crate.
+
+use std::sync::atomic;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+  Warn = 1,
+  Info = 2,
+  Debug = 3,
+}
+
+// Zero means the level was never set.
+static LEVEL: atomic::AtomicUsize = atomic::ATOMIC_USIZE_INIT;
+
+pub fn set_level(level: Level) {
+  LEVEL.store(level as usize, atomic::Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+  let current = match LEVEL.load(atomic::Ordering::Relaxed) {
+    0 => Level::Info as usize,
+    current => current,
+  };
+  level as usize <= current
+}
@@ -1,37 +1,68 @@
 #![feature(plugin)]
 #![plugin(rocket_codegen)]
+#![cfg_attr(feature = "bench", feature(test))]
 
+extern crate base64;
+extern crate byteorder;
 extern crate chrono;
 extern crate clap;
 extern crate cpuprofiler;
+extern crate ctrlc;
 extern crate flate2;
 extern crate image;
+extern crate memmap;
 extern crate rocket;
 extern crate rocket_contrib;
+extern crate rusqlite;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "bench")]
+extern crate test;
 extern crate threadpool;
 extern crate time;
 
+use std::cmp;
 use std::collections;
 use std::path;
 use std::fs;
 use std::io;
 use std::io::BufRead;
+use std::io::Write;
+use std::mem;
+use std::str;
 use std::sync;
 
+use byteorder::{LittleEndian, WriteBytesExt};
 use chrono::prelude::*;
+use rocket::http::Status;
+use rocket::response::Failure;
+
+use json::JSON;
 
 use std::f64::consts;
 
 macro_rules! ret_check_eq {
-    ($a:expr, $b:expr) => { ret_check_impl!($a, $b, ==) }
+    ($a:expr, $b:expr) => { ret_check_impl!($a, $b, ==) };
+    ($a:expr, $b:expr, $context:expr) => {
+      ret_check_impl!($a, $b, ==, $context)
+    };
 }
 
 macro_rules! ret_check_ge {
-    ($a:expr, $b:expr) => { ret_check_impl!($a, $b, >=) }
+    ($a:expr, $b:expr) => { ret_check_impl!($a, $b, >=) };
+    ($a:expr, $b:expr, $context:expr) => {
+      ret_check_impl!($a, $b, >=, $context)
+    };
 }
 
 macro_rules! ret_check_le {
-    ($a:expr, $b:expr) => { ret_check_impl!($a, $b, <=) }
+    ($a:expr, $b:expr) => { ret_check_impl!($a, $b, <=) };
+    ($a:expr, $b:expr, $context:expr) => {
+      ret_check_impl!($a, $b, <=, $context)
+    };
 }
 
 macro_rules! ret_check_impl {
@@ -42,6 +73,17 @@ macro_rules! ret_check_impl {
                     stringify!($a), stringify!($op), stringify!($b),
                      $a, stringify!($op), $b)));
       }
+    );
+    // $context, e.g. the file and line being parsed, is only evaluated on
+    // failure and prefixed to the message.
+    ($a:expr, $b:expr, $op:tt, $context:expr) => (
+      if !($a $op $b) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                   format!("{}: check {} {} {}; failed for {} {} {}",
+                    $context,
+                    stringify!($a), stringify!($op), stringify!($b),
+                     $a, stringify!($op), $b)));
+      }
     )
 }
 
@@ -82,25 +124,166 @@ macro_rules! ret_check_approx_eq {
     )
 }
 
-#[derive(Debug)]
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => (
+      if ::logging::enabled($level) {
+        println!($($arg)*);
+      }
+    )
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => { log_at!(::logging::Level::Warn, $($arg)*) }
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { log_at!(::logging::Level::Info, $($arg)*) }
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { log_at!(::logging::Level::Debug, $($arg)*) }
+}
+
+mod api;
+#[cfg(feature = "bench")]
+mod bench;
+mod barbs;
+mod basemap;
+mod cache;
+mod cancel;
+mod font;
+mod gazetteer;
+mod geotiff;
+mod grid;
+mod highlight;
+mod json;
+mod indexed_png;
+mod logging;
+mod meteo;
+mod ndjson;
+mod netcdf;
+mod openapi;
+mod parquet;
+mod qc;
+mod ratelimit;
+mod source;
+mod station_cache;
+mod store;
+mod svg;
+mod tar;
+#[cfg(test)]
+mod testing;
+mod units;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum WindMeasurement {
   Calm,
   Variable,
-  Normal { speed: f32, direction: i32 },
+  // The direction is None when it is missing (999) despite a valid speed.
+  // The speed is never 0, see observed().
+  Normal { speed: f32, direction: Option<i32> },
+}
+
+impl WindMeasurement {
+  // A reported speed in m/s. No wind is calm whatever its type and direction
+  // (typically 0) say, rather than a Normal wind without a meaningful
+  // direction to draw.
+  fn observed(speed: f32, direction: Option<i32>) -> WindMeasurement {
+    if speed == 0.0 {
+      WindMeasurement::Calm
+    } else {
+      WindMeasurement::Normal {
+        speed: speed,
+        direction: direction,
+      }
+    }
+  }
+}
+
+// Kind of precipitation, from the present weather observations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PrecipType {
+  Rain,
+  Snow,
+  Freezing,
+  // Rain and snow.
+  Mixed,
+}
+
+impl PrecipType {
+  // From a WMO 4677 present weather code, None for codes that don't report
+  // precipitation at the station.
+  fn from_present_weather(ww: u32) -> Option<PrecipType> {
+    match ww {
+      56 | 57 | 66 | 67 => Some(PrecipType::Freezing),
+      68 | 69 | 83 | 84 => Some(PrecipType::Mixed),
+      50...59 | 60...65 | 80...82 | 91 | 92 | 95 | 97 => {
+        Some(PrecipType::Rain)
+      }
+      70...79 | 85 | 86 | 93 | 94 => Some(PrecipType::Snow),
+      _ => None,
+    }
+  }
+
+  fn color(&self) -> image::Rgb<u8> {
+    match *self {
+      PrecipType::Rain => image::Rgb([40, 120, 255]),
+      PrecipType::Snow => image::Rgb([255, 255, 255]),
+      PrecipType::Freezing => image::Rgb([255, 60, 160]),
+      PrecipType::Mixed => image::Rgb([150, 100, 255]),
+    }
+  }
+}
+
+// The precipitation type of the manually observed present weather (MW1 to
+// MW7) in a line's additional data section, the first one that reports
+// precipitation. The section isn't parsed in full, the identifiers are
+// searched for instead.
+fn parse_present_weather(additional: &str) -> Option<PrecipType> {
+  let bytes = additional.as_bytes();
+  (0..bytes.len().saturating_sub(4))
+    .filter(|&i| {
+      &bytes[i..i + 2] == b"MW" && bytes[i + 2] >= b'1' && bytes[i + 2] <= b'7'
+    })
+    .filter_map(|i| additional[i + 3..i + 5].parse::<u32>().ok())
+    .filter_map(PrecipType::from_present_weather)
+    .next()
+}
+
+// ISH quality codes (as ASCII characters) of the mandatory data section.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct QualityCodes {
+  wind_speed: u8,
+  air_temperature: u8,
+  dew_point: u8,
+  air_pressure: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 struct WeatherMeasurement {
   datetime: DateTime<UTC>,
+  // Report type code such as FM-12 (SYNOP) or FM-15 (METAR).
+  report_type: [u8; 5],
 
   wind: Option<WindMeasurement>,
   air_temperature: Option<f32>,
+  dew_point: Option<f32>,
   air_pressure: Option<f32>,
+  precip_type: Option<PrecipType>,
+  quality: QualityCodes,
+  // Index of the file the measurement was loaded from in
+  // StationStore::sources, with --track-provenance.
+  source: Option<u16>,
 }
 
+#[derive(Clone)]
 struct WeatherStation {
   usaf: String,
   wban: String,
+  // From the --stations-history gazetteer, if the station is listed there.
+  name: Option<String>,
+  country: Option<String>,
+  state: Option<String>,
 
   latitude: f32,
   longitude: f32,
@@ -113,6 +296,56 @@ fn parse(filename: &str,
          reader: &mut BufRead,
          max_measurements: usize)
          -> Result<WeatherStation, io::Error> {
+  parse_lines(filename, reader.split(b'\n'), max_measurements)
+}
+
+// The line as text without its trailing carriage return, or None if it isn't
+// ASCII; ISH files are, so such lines are corrupt.
+fn ascii_line(line: &[u8]) -> Option<&str> {
+  let line = if line.last() == Some(&b'\r') {
+    &line[..line.len() - 1]
+  } else {
+    line
+  };
+  if line.iter().all(|&b| b < 0x80) {
+    str::from_utf8(line).ok()
+  } else {
+    None
+  }
+}
+
+// An ISH record is a control section and a mandatory data section, both of
+// fixed length, followed by as many characters of additional data as the
+// first four characters of the control section say.
+const CONTROL_LEN: usize = 60;
+const MANDATORY_LEN: usize = 45;
+
+// Splits a record into its fixed sections, which fields are sliced from by
+// position, and the additional data the prefix declares; None if the line is
+// shorter than that. Line endings and trailing whitespace don't count, and
+// anything beyond the declared length is ignored.
+fn split_record(line: &str) -> Option<(&str, &str)> {
+  let line = line.trim_right();
+  let fixed_len = CONTROL_LEN + MANDATORY_LEN;
+  if line.len() < fixed_len {
+    return None;
+  }
+  match line[0..4].parse::<usize>() {
+    Ok(additional_len) if line.len() >= fixed_len + additional_len => {
+      Some((&line[..fixed_len],
+            &line[fixed_len..fixed_len + additional_len]))
+    }
+    _ => None,
+  }
+}
+
+fn parse_lines<I, L>(filename: &str,
+                     lines: I,
+                     max_measurements: usize)
+                     -> Result<WeatherStation, io::Error>
+  where I: Iterator<Item = Result<L, io::Error>>,
+        L: AsRef<[u8]>
+{
   let parts = path::Path::new(filename)
     .file_stem()
     .unwrap()
@@ -124,6 +357,9 @@ fn parse(filename: &str,
   let mut station = WeatherStation {
     usaf: String::from(parts[0]),
     wban: String::from(parts[1]),
+    name: None,
+    country: None,
+    state: None,
 
     latitude: -1000.0,
     longitude: -1000.0,
@@ -133,8 +369,28 @@ fn parse(filename: &str,
   };
 
   let mut missing = collections::HashMap::<&str, i32>::new();
-  for maybe_line in reader.lines() {
-    let line = maybe_line.unwrap();
+  for (line_number, maybe_line) in lines.enumerate() {
+    // E.g. a truncated or corrupt compressed file.
+    let line = try!(maybe_line);
+    let line = match ascii_line(line.as_ref()) {
+      Some(line) => line,
+      None => {
+        warn!("{}:{}: skipping non-ASCII line", filename, line_number + 1);
+        *missing.entry("non_ascii_lines").or_insert(0) += 1;
+        continue;
+      }
+    };
+    let (line, additional) = match split_record(line) {
+      Some(sections) => sections,
+      None => {
+        warn!("{}:{}: skipping record shorter than its prefix says",
+              filename,
+              line_number + 1);
+        *missing.entry("malformed_lines").or_insert(0) += 1;
+        continue;
+      }
+    };
+    let at = || format!("{}:{}", filename, line_number + 1);
 
     // Data from https://www1.ncdc.noaa.gov/pub/data/noaa/
     // File format documentation:
@@ -142,10 +398,10 @@ fn parse(filename: &str,
 
     // Some sanity checking.
     let usaf = &line[4..10];
-    ret_check_eq!(station.usaf, usaf);
+    ret_check_eq!(station.usaf, usaf, at());
 
     let wban = &line[10..15];
-    ret_check_eq!(station.wban, wban);
+    ret_check_eq!(station.wban, wban, at());
 
     // Date and time.
     let date = &line[15..23];
@@ -161,17 +417,20 @@ fn parse(filename: &str,
 
     let datetime = utc_day.and_hms(hour, minute, 0);
 
+    let mut report_type = [0; 5];
+    report_type.copy_from_slice(line[41..46].as_bytes());
+
     // Location.
     let latitude = line[28..34].parse::<f32>().unwrap() / 1000.0;
-    ret_check_ge!(latitude, -90.0);
-    ret_check_le!(latitude, 90.0);
+    ret_check_ge!(latitude, -90.0, at());
+    ret_check_le!(latitude, 90.0, at());
     if station.measurements.is_empty() {
       station.latitude = latitude;
     }
 
     let longitude = line[34..41].parse::<f32>().unwrap() / 1000.0;
-    ret_check_ge!(longitude, -180.0);
-    ret_check_le!(longitude, 180.0);
+    ret_check_ge!(longitude, -180.0, at());
+    ret_check_le!(longitude, 180.0, at());
     if station.measurements.is_empty() {
       station.longitude = longitude;
     }
@@ -189,17 +448,25 @@ fn parse(filename: &str,
     let wind_speed = line[65..69].parse::<i32>().unwrap();
     let wind_type = &line[64..65];
 
+    // A direction of 999 means missing, which is distinct from a type V
+    // (variable) observation.
+    let maybe_wind_direction = if wind_direction >= 0 && wind_direction <= 360 {
+      Some(wind_direction)
+    } else {
+      None
+    };
+    let valid_wind_speed = wind_speed >= 0 && wind_speed <= 900;
+
     let wind_observation =
-      if wind_direction >= 0 && wind_direction <= 360 && wind_speed >= 0 &&
-         wind_speed <= 900 {
-        Some(WindMeasurement::Normal {
-          speed: wind_speed as f32 / 10.0,
-          direction: wind_direction,
-        })
+      if maybe_wind_direction.is_some() && valid_wind_speed {
+        Some(WindMeasurement::observed(wind_speed as f32 / 10.0,
+                                       maybe_wind_direction))
       } else if wind_type == "C" || (wind_type == "9" && wind_speed == 0) {
         Some(WindMeasurement::Calm)
       } else if wind_type == "V" {
         Some(WindMeasurement::Variable)
+      } else if valid_wind_speed {
+        Some(WindMeasurement::observed(wind_speed as f32 / 10.0, None))
       } else {
         *missing.entry("wind").or_insert(0) += 1;
         None
@@ -214,6 +481,13 @@ fn parse(filename: &str,
       None
     };
 
+    let dew_point = line[93..98].parse::<i32>().unwrap();
+    let maybe_dew_point = if dew_point >= -1000 && dew_point <= 1000 {
+      Some(dew_point as f32 / 10.0)
+    } else {
+      *missing.entry("dew_point").or_insert(0) += 1;
+      None
+    };
 
     let air_pressure = line[99..104].parse::<i32>().unwrap();
     let maybe_air_pressure = if air_pressure >= 0 && air_pressure <= 20000 {
@@ -230,9 +504,19 @@ fn parse(filename: &str,
 
     station.measurements.push(WeatherMeasurement {
       datetime: datetime,
+      report_type: report_type,
       wind: wind_observation,
       air_temperature: maybe_air_temperature,
+      dew_point: maybe_dew_point,
       air_pressure: maybe_air_pressure,
+      precip_type: parse_present_weather(additional),
+      source: None,
+      quality: QualityCodes {
+        wind_speed: line.as_bytes()[69],
+        air_temperature: line.as_bytes()[92],
+        dew_point: line.as_bytes()[98],
+        air_pressure: line.as_bytes()[104],
+      },
     });
 
     if station.measurements.len() > max_measurements {
@@ -255,16 +539,109 @@ fn parse(filename: &str,
 }
 
 fn parse_file(filename: &str,
+              max_measurements: usize,
+              use_mmap: bool)
+              -> Result<WeatherStation, io::Error> {
+  if use_mmap && !filename.ends_with(".gz") {
+    return parse_mmap(filename, max_measurements);
+  }
+  let f = try!(fs::File::open(filename));
+  parse_reader(filename, io::BufReader::new(f), max_measurements)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InputFormat {
+  // NOAA's fixed-width ISH format, one station per file.
+  Ish,
+  // See ndjson.rs.
+  Ndjson,
+}
+
+impl InputFormat {
+  fn parse(format: &str) -> Result<InputFormat, String> {
+    match format {
+      "ish" => Ok(InputFormat::Ish),
+      "ndjson" => Ok(InputFormat::Ndjson),
+      _ => Err(format!("unknown input format {:?}", format)),
+    }
+  }
+
+  // Extensions of the files loaded from --directory unless --extensions says
+  // otherwise.
+  fn default_extensions(&self) -> &'static str {
+    match *self {
+      InputFormat::Ish => "gz",
+      InputFormat::Ndjson => "ndjson,json,gz",
+    }
+  }
+}
+
+// Parses a file of either format; NDJSON files may hold any number of
+// stations.
+fn parse_input(filename: &str,
+               max_measurements: usize,
+               use_mmap: bool,
+               format: InputFormat)
+               -> Result<Vec<WeatherStation>, io::Error> {
+  match format {
+    InputFormat::Ish => {
+      parse_file(filename, max_measurements, use_mmap)
+        .map(|station| vec![station])
+    }
+    InputFormat::Ndjson => {
+      let f = try!(fs::File::open(filename));
+      if filename.ends_with(".gz") {
+        let decoder = try!(flate2::read::GzDecoder::new(f));
+        ndjson::parse(filename, io::BufReader::new(decoder), max_measurements)
+      } else {
+        ndjson::parse(filename, io::BufReader::new(f), max_measurements)
+      }
+    }
+  }
+}
+
+// Splits on newlines like BufRead::split, but without copying each line.
+fn split_lines<'a>(bytes: &'a [u8])
+                   -> Box<Iterator<Item = Result<&'a [u8], io::Error>> + 'a> {
+  let bytes = if bytes.last() == Some(&b'\n') {
+    &bytes[..bytes.len() - 1]
+  } else {
+    bytes
+  };
+  Box::new(bytes.split(|&b| b == b'\n').map(Ok))
+}
+
+fn parse_mmap(filename: &str,
               max_measurements: usize)
               -> Result<WeatherStation, io::Error> {
-  let f = fs::File::open(filename).unwrap();
-  let mut reader = io::BufReader::new(f);
-
-  match if filename.ends_with(".gz") {
-    parse(filename,
-          &mut io::BufReader::new(flate2::bufread::GzDecoder::new(reader)
-            .unwrap()),
-          max_measurements)
+  let map = try!(memmap::Mmap::open_path(filename, memmap::Protection::Read));
+  // Only sound as long as nobody truncates the file while we parse it.
+  let bytes = unsafe { map.as_slice() };
+  parse_lines(filename, split_lines(bytes), max_measurements).map_err(|error| {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("parsing {} failed: {}", filename, error))
+  })
+}
+
+const GZIP_MAGIC: &'static [u8] = &[0x1f, 0x8b];
+
+// Decompresses .gz files, unless they turn out not to be compressed at all
+// despite the name, which happens in hand-assembled archives.
+fn parse_reader<R: BufRead>(filename: &str,
+                            mut reader: R,
+                            max_measurements: usize)
+                            -> Result<WeatherStation, io::Error> {
+  let mut gzipped = filename.ends_with(".gz");
+  if gzipped && !try!(reader.fill_buf()).starts_with(GZIP_MAGIC) {
+    warn!("{} isn't gzip compressed, reading it as plain text", filename);
+    gzipped = false;
+  }
+  match if gzipped {
+    flate2::bufread::GzDecoder::new(reader).and_then(|decoder| {
+      parse(filename,
+            &mut io::BufReader::new(decoder),
+            max_measurements)
+    })
   } else {
     parse(filename, &mut reader, max_measurements)
   } {
@@ -278,270 +655,6090 @@ fn parse_file(filename: &str,
   }
 }
 
-// Applies the web-mercator projection to a latitude in degrees.
-// Following https://en.wikipedia.org/wiki/Web_Mercator#Formulas
-fn mercator(latitude: f32) -> f32 {
-  ((consts::PI as f32 / 4.0) + (latitude.to_radians() / 2.0)).tan().ln()
+// Station files are named <usaf>-<wban>-<year>, anything else in an archive
+// (readmes, checksums, ...) is not data.
+fn is_station_file(filename: &str) -> bool {
+  path::Path::new(filename)
+    .file_stem()
+    .and_then(|stem| stem.to_str())
+    .map(|stem| stem.split("-").count() >= 2)
+    .unwrap_or(false)
 }
 
-fn draw_stations(stations: &Vec<WeatherStation>,
-                 longitude_min: f32,
-                 longitude_max: f32,
-                 latitude_min: f32,
-                 latitude_max: f32,
-                 width: u32,
-                 height: u32,
-                 dot_radius: u32,
-                 start_time: DateTime<UTC>,
-                 end_time: DateTime<UTC>)
-                 -> image::RgbImage {
-  println!("requesting stations for longitude {} to {}, latitude {} to {}",
-           longitude_min,
-           longitude_max,
-           latitude_min,
-           latitude_max);
-
-  let mut img = image::ImageBuffer::new(width, height);
+// Parses every station file contained in a (optionally gzipped) tar archive,
+// streaming the members straight from the archive. Returns the number of
+// members that failed to parse.
+fn parse_tar(filename: &str,
+             max_measurements: usize,
+             stations: &mut Vec<WeatherStation>,
+             provenance: &mut Option<Provenance>,
+             cancelled: &sync::atomic::AtomicBool)
+             -> Result<usize, io::Error> {
+  let f = try!(fs::File::open(filename));
+  let reader: Box<io::Read> = if filename.ends_with(".gz") ||
+                                 filename.ends_with(".tgz") {
+    Box::new(try!(flate2::read::MultiGzDecoder::new(f)))
+  } else {
+    Box::new(f)
+  };
 
-  for station in stations {
-    if station.longitude < longitude_min || station.longitude > longitude_max ||
-       station.latitude < latitude_min ||
-       station.latitude > latitude_max {
-      continue;
+  let mut num_failed = 0;
+  try!(tar::for_each_file(reader, |name, entry| {
+    if !is_station_file(name) ||
+       cancelled.load(sync::atomic::Ordering::SeqCst) {
+      return;
+    }
+    match parse_reader(name, io::BufReader::new(entry), max_measurements) {
+      Ok(station) => {
+        stations.push(station);
+        if let Some(ref mut provenance) = *provenance {
+          let last = stations.len() - 1;
+          provenance.tag(name, &mut stations[last..]);
+        }
+      }
+      Err(error) => {
+        warn!("{}", error);
+        num_failed += 1;
+      }
     }
+  }));
+  Ok(num_failed)
+}
 
-    let x = ((station.longitude - longitude_min) /
-             (longitude_max - longitude_min) *
-             (width - 1) as f32) as i32;
-    check_ge!(x, 0);
-    check_lt!(x, width as i32);
+impl WeatherMeasurement {
+  fn is_synop(&self) -> bool {
+    &self.report_type == b"FM-12"
+  }
+}
 
-    let y = ((1.0 -
-              (mercator(station.latitude) - mercator(latitude_min)) /
-              (mercator(latitude_max) - mercator(latitude_min))) *
-             (height - 1) as f32) as i32;
-    check_ge!(y, 0);
-    check_lt!(y, height as i32);
+// With --track-provenance, the names of the files the measurements were loaded
+// from, which their source indexes.
+#[derive(Default)]
+struct Provenance {
+  files: Vec<String>,
+  // Files loaded after the u16 indices ran out.
+  untracked: usize,
+}
 
-    let pixel = if station.measurements.is_empty() {
-      image::Rgb([0u8, 0u8, 0u8])
-    } else {
-      let start = match station.measurements
-        .binary_search_by(|m| m.datetime.cmp(&start_time)) {
-        Ok(index) => index,
-        Err(index) => index,
-      };
-      let (_, after) = station.measurements.split_at(start);
+impl Provenance {
+  // Records the file the stations were parsed from.
+  fn tag(&mut self, filename: &str, stations: &mut [WeatherStation]) {
+    if self.files.len() > u16::max_value() as usize {
+      self.untracked += 1;
+      return;
+    }
+    let index = self.files.len() as u16;
+    self.files.push(String::from(filename));
+    for m in stations.iter_mut().flat_map(|s| s.measurements.iter_mut()) {
+      m.source = Some(index);
+    }
+  }
+}
 
-      let end = match after.binary_search_by(|m| m.datetime.cmp(&end_time)) {
-        Ok(index) => index,
-        Err(index) => index,
-      };
-      let (measurements, _) = after.split_at(end);
-
-
-      match measurements.iter()
-        .filter(|m| m.air_temperature.is_some())
-        .next() {
-        Some(m) => {
-          let t = m.air_temperature.unwrap();
-          let t_min = -30.0f32;
-          let t_max = 40.0f32;
-          let scaled = (t_max.min(t_min.max(t)) - t_min) / (t_max - t_min);
-          image::Rgb([(255.0 * scaled) as u8,
-                      127u8,
-                      (255.0 * (1.0 - scaled)) as u8])
+// Which of several measurements at the same instant to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DupPolicy {
+  First,
+  Last,
+  // The first SYNOP report, falling back to the first.
+  PreferSynop,
+  // The first, with temperatures and pressure averaged over all of them.
+  Mean,
+}
+
+impl DupPolicy {
+  fn parse(policy: &str) -> Result<DupPolicy, String> {
+    match policy {
+      "first" => Ok(DupPolicy::First),
+      "last" => Ok(DupPolicy::Last),
+      "prefer-synop" => Ok(DupPolicy::PreferSynop),
+      "mean" => Ok(DupPolicy::Mean),
+      _ => Err(format!("unknown duplicate policy {:?}", policy)),
+    }
+  }
+
+  // Reduces a non-empty group of measurements in file order to one.
+  fn resolve(&self, mut group: Vec<WeatherMeasurement>) -> WeatherMeasurement {
+    match *self {
+      DupPolicy::First => group.swap_remove(0),
+      DupPolicy::Last => group.pop().unwrap(),
+      DupPolicy::PreferSynop => {
+        let i = group.iter().position(|m| m.is_synop()).unwrap_or(0);
+        group.swap_remove(i)
+      }
+      DupPolicy::Mean => {
+        fn mean<F>(group: &[WeatherMeasurement], value: F) -> Option<f32>
+          where F: Fn(&WeatherMeasurement) -> Option<f32>
+        {
+          let values = group.iter().filter_map(value).collect::<Vec<_>>();
+          if values.is_empty() {
+            None
+          } else {
+            Some(values.iter().sum::<f32>() / values.len() as f32)
+          }
         }
-        None => image::Rgb([0u8, 0u8, 0u8]),
+        let air_temperature = mean(&group, |m| m.air_temperature);
+        let dew_point = mean(&group, |m| m.dew_point);
+        let air_pressure = mean(&group, |m| m.air_pressure);
+        let mut first = group.swap_remove(0);
+        first.air_temperature = air_temperature;
+        first.dew_point = dew_point;
+        first.air_pressure = air_pressure;
+        first
       }
-    };
+    }
+  }
+}
 
-    for dx in 0..dot_radius {
-      for dy in 0..dot_radius {
-        let (px, py) = (x + (dx as i32 - dot_radius as i32 / 2),
-                        y + (dy as i32 - dot_radius as i32 / 2));
-        if px >= 0 && px < width as i32 && py >= 0 && py < height as i32 {
-          img.put_pixel(px as u32, py as u32, pixel);
-        }
+// Collapses runs of measurements at the same instant, which must be sorted by
+// time, according to `policy`. Returns the number of measurements dropped.
+fn dedup_measurements(measurements: &mut Vec<WeatherMeasurement>,
+                      policy: DupPolicy)
+                      -> usize {
+  let num_measurements = measurements.len();
+  let mut deduped = Vec::with_capacity(num_measurements);
+  let mut group: Vec<WeatherMeasurement> = vec![];
+  for m in measurements.drain(..) {
+    if group.last().map_or(false, |last| last.datetime != m.datetime) {
+      deduped.push(policy.resolve(mem::replace(&mut group, vec![])));
+    }
+    group.push(m);
+  }
+  if !group.is_empty() {
+    deduped.push(policy.resolve(group));
+  }
+  *measurements = deduped;
+  num_measurements - measurements.len()
+}
+
+// Merges stations sharing a (usaf, wban) id, e.g. from per-year files, into
+// the first of them. Measurements are re-sorted and those at the same instant
+// deduplicated according to `policy`.
+fn merge_stations(stations: Vec<WeatherStation>,
+                  policy: DupPolicy)
+                  -> Vec<WeatherStation> {
+  let mut merged: Vec<WeatherStation> = vec![];
+  let mut index = collections::HashMap::new();
+  for station in stations {
+    let id = (station.usaf.clone(), station.wban.clone());
+    let i = match index.get(&id) {
+      Some(&i) => i,
+      None => {
+        index.insert(id, merged.len());
+        merged.push(station);
+        continue;
       }
+    };
+    let existing = &mut merged[i];
+    if existing.latitude < -90.0 {
+      existing.latitude = station.latitude;
+      existing.longitude = station.longitude;
     }
+    existing.elevation = existing.elevation.or(station.elevation);
+    existing.measurements.extend(station.measurements);
   }
 
-  return img;
+  for station in &mut merged {
+    station.measurements.sort_by_key(|m| m.datetime);
+    let num_dropped = dedup_measurements(&mut station.measurements, policy);
+    if num_dropped > 0 {
+      debug!("{}-{}: dropped {} duplicate measurements",
+             station.usaf,
+             station.wban,
+             num_dropped);
+    }
+  }
+  merged
 }
 
-fn draw_stations_to_file(stations: &Vec<WeatherStation>,
-                         start_time: DateTime<UTC>,
-                         end_time: DateTime<UTC>,
-                         image_path: &path::Path) {
-  let img = draw_stations(stations,
-                          -180.0,
-                          180.0,
-                          -90.0,
-                          90.0,
-                          1024,
-                          512,
-                          1,
-                          start_time,
-                          end_time);
-  let _ = img.save(image_path);
+// Applies the web-mercator projection to a latitude in degrees.
+// Following https://en.wikipedia.org/wiki/Web_Mercator#Formulas
+fn mercator(latitude: f32) -> f32 {
+  ((consts::PI as f32 / 4.0) + (latitude.to_radians() / 2.0)).tan().ln()
 }
 
-fn coordinates_to_degrees(zoom: u32, x: u32, y: u32) -> (f32, f32) {
-  let n = 2f32.powi(zoom as i32);
-  let longitude = (x as f32) / n * 360.0 - 180.0;
-  let latitude = (consts::PI as f32 * (1.0 - 2.0 * (y as f32) / n))
-    .sinh()
-    .atan()
-    .to_degrees();
-  (longitude, latitude)
+// Beyond this latitude web-mercator maps are cut off, the projection itself
+// diverges at the poles.
+const MERCATOR_MAX_LATITUDE: f32 = 85.0511;
+
+fn clamp_latitude(latitude: f32) -> f32 {
+  latitude.max(-MERCATOR_MAX_LATITUDE).min(MERCATOR_MAX_LATITUDE)
 }
 
-#[get("/")]
-fn index() -> rocket_contrib::Template {
-  let context = collections::HashMap::<&str, &str>::new();
-  rocket_contrib::Template::render("index", &context)
+// The projected extent of a latitude range, clamped to where web-mercator
+// maps are cut off so that ranges reaching the poles stay finite. None if no
+// part of the range is left after clamping, e.g. when it is empty or lies
+// beyond the cut-off.
+fn mercator_bounds(latitude_min: f32,
+                   latitude_max: f32)
+                   -> Option<(f32, f32)> {
+  let latitude_min = clamp_latitude(latitude_min);
+  let latitude_max = clamp_latitude(latitude_max);
+  if !(latitude_min < latitude_max) {
+    return None;
+  }
+  Some((mercator(latitude_min), mercator(latitude_max)))
 }
 
-#[get("/static/<filename>")]
-fn static_file(filename: &str)
-               -> Result<rocket::response::NamedFile, io::Error> {
-  rocket::response::NamedFile::open(path::Path::new("static").join(filename))
+// A scalar quantity of a measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Field {
+  Temperature,
+  DewPoint,
+  Pressure,
+  WindSpeed,
+  // Relative humidity in percent, derived from temperature and dew point.
+  Humidity,
 }
 
-#[get("/api/map/<zoom>/<x>/<y>/tile.png")]
-fn map_tile<'a>(zoom: u32,
-                x: u32,
-                y: u32,
-                stations: rocket::State<Vec<WeatherStation>>)
-                -> Result<rocket::Response<'a>, io::Error> {
-  let (long_min, lat_top) = coordinates_to_degrees(zoom, x, y);
-  let (long_max, lat_bot) = coordinates_to_degrees(zoom, x + 1, y + 1);
+impl Field {
+  fn parse(field: &str) -> Result<Field, String> {
+    match field {
+      "temperature" => Ok(Field::Temperature),
+      "dew_point" => Ok(Field::DewPoint),
+      "pressure" => Ok(Field::Pressure),
+      "wind_speed" | "wind" => Ok(Field::WindSpeed),
+      "humidity" => Ok(Field::Humidity),
+      _ => Err(format!("unknown field {:?}", field)),
+    }
+  }
 
-  let dot_radius = if zoom < 5 { 1 } else { zoom - 3 };
+  // Typical range of the field, used to scale it onto colors.
+  fn range(&self) -> (f32, f32) {
+    match *self {
+      Field::Temperature => (-30.0, 40.0),
+      Field::DewPoint => (-30.0, 30.0),
+      Field::Pressure => (950.0, 1050.0),
+      Field::WindSpeed => (0.0, 20.0),
+      Field::Humidity => (0.0, 100.0),
+    }
+  }
 
-  let size = 256;
-  let mut img = draw_stations(stations.inner(),
-                              long_min,
-                              long_max,
-                              lat_bot,
-                              lat_top,
-                              size,
-                              size,
-                              dot_radius,
-                              UTC.ymd(1900, 1, 1).and_hms(0, 0, 0),
-                              UTC.ymd(2100, 1, 1).and_hms(0, 0, 0));
+  // Converts a value from the internal units to the requested ones.
+  fn convert(&self,
+             value: f32,
+             temperature: units::TemperatureUnit,
+             wind_speed: units::WindUnit)
+             -> f32 {
+    match *self {
+      Field::Temperature | Field::DewPoint => temperature.from_celsius(value),
+      Field::WindSpeed => wind_speed.from_ms(value),
+      _ => value,
+    }
+  }
 
-  // Debug borders:
-  // for i in 0..size {
-  //   img.put_pixel(i, 0, image::Rgb([0u8, 255u8, 0u8]));
-  //   img.put_pixel(i, size - 1, image::Rgb([0u8, 255u8, 0u8]));
-  //   img.put_pixel(0, i, image::Rgb([0u8, 255u8, 0u8]));
-  //   img.put_pixel(size - 1, i, image::Rgb([0u8, 255u8, 0u8]));
-  // }
-  // img.put_pixel(size / 2, size / 2, image::Rgb([255u8, 0u8, 0u8]));
+  // Where a value lies within range(), clamped to [0, 1].
+  fn normalize(&self, value: f32) -> f32 {
+    let (min, max) = self.range();
+    ((value - min) / (max - min)).max(0.0).min(1.0)
+  }
 
-  let mut buf = Vec::<u8>::new();
-  {
-    let encoder = image::png::PNGEncoder::new(&mut buf);
-    try!(encoder.encode(&img.into_raw(), size, size, image::ColorType::RGB(8)));
+  // Whether normalize() saturates the value to an end of the color ramp.
+  fn clipped(&self, value: f32) -> bool {
+    let (min, max) = self.range();
+    value < min || value > max
   }
 
-  rocket::Response::build().sized_body(io::Cursor::new(buf)).ok()
+  fn value(&self, m: &WeatherMeasurement) -> Option<f32> {
+    match *self {
+      Field::Temperature => m.air_temperature,
+      Field::DewPoint => m.dew_point,
+      Field::Pressure => m.air_pressure,
+      Field::WindSpeed => {
+        match m.wind {
+          Some(WindMeasurement::Normal { speed, .. }) => Some(speed),
+          Some(WindMeasurement::Calm) => Some(0.0),
+          _ => None,
+        }
+      }
+      Field::Humidity => {
+        match (m.air_temperature, m.dew_point) {
+          (Some(t), Some(d)) => Some(meteo::relative_humidity(t, d)),
+          _ => None,
+        }
+      }
+    }
+  }
 }
 
-fn main() {
-  let args = clap::App::new("parser")
-    .arg(clap::Arg::with_name("file").long("file").takes_value(true))
-    .arg(clap::Arg::with_name("directory").long("directory").takes_value(true))
-    .arg(clap::Arg::with_name("render_dir")
-      .long("render_dir")
-      .takes_value(true))
-    .arg(clap::Arg::with_name("max_stations")
-      .long("max_stations")
-      .takes_value(true))
-    .arg(clap::Arg::with_name("max_measurements")
-      .long("max_measurements")
-      .takes_value(true))
-    .arg(clap::Arg::with_name("threads")
-      .long("threads")
-      .takes_value(true)
-      .default_value("8"))
-    .get_matches();
+// Radius of the sphere used by EPSG:3857.
+const WEB_MERCATOR_RADIUS: f64 = 6378137.0;
 
-  let max_measurements = args.value_of("max_measurements")
-    .and_then(|n| n.parse::<usize>().ok())
-    .unwrap_or(usize::max_value());
+// Converts EPSG:3857 meters to (longitude, latitude) in degrees.
+fn mercator_meters_to_degrees(x: f64, y: f64) -> (f32, f32) {
+  let longitude = (x / WEB_MERCATOR_RADIUS).to_degrees();
+  let latitude = (2.0 * (y / WEB_MERCATOR_RADIUS).exp().atan() -
+                  consts::FRAC_PI_2)
+    .to_degrees();
+  (longitude as f32, latitude as f32)
+}
 
-  let mut stations = Vec::new();
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DrawMode {
+  Temperature,
+  // "Feels like" temperature, see meteo::apparent_temperature.
+  ApparentTemp,
+  // Field a as hue and field b as brightness.
+  Bivariate { a: Field, b: Field },
+  // Any single field, on the temperature palette scaled to its range.
+  Scalar(Field),
+  // Air temperature minus the --baseline grid at the station, on a diverging
+  // scale.
+  Anomaly,
+  // Wind barbs colored by wind speed.
+  Barbs,
+  // A color per kind of precipitation; stations without any aren't drawn.
+  PrecipType,
+  // White for stations with any measurement in the window, black otherwise.
+  Coverage,
+  // How many stations with measurements in the window are nearby, spread
+  // out by RenderOptions::density.
+  Density,
+}
 
-  cpuprofiler::PROFILER.lock().unwrap().start("prof.profile").unwrap();
+impl DrawMode {
+  fn parse(mode: &str) -> Result<DrawMode, String> {
+    match mode {
+      "temperature" => Ok(DrawMode::Temperature),
+      "apparent" => Ok(DrawMode::ApparentTemp),
+      "anomaly" => Ok(DrawMode::Anomaly),
+      "barbs" => Ok(DrawMode::Barbs),
+      "precip" => Ok(DrawMode::PrecipType),
+      "coverage" => Ok(DrawMode::Coverage),
+      "density" => Ok(DrawMode::Density),
+      "bivariate" => {
+        Err(String::from("mode=bivariate requires fields a and b"))
+      }
+      _ => Err(format!("unknown draw mode {:?}", mode)),
+    }
+  }
 
-  args.value_of("directory").map(|directory| {
-    let max_stations = args.value_of("max_stations")
-      .and_then(|n| n.parse::<usize>().ok())
-      .unwrap_or(usize::max_value());
-    let n_threads = args.value_of("threads").unwrap().parse::<usize>().unwrap();
-    let pool = threadpool::ThreadPool::new(n_threads);
-    let (tx, rx) = sync::mpsc::channel();
+  // The value a measurement contributes in this mode, if it has one.
+  fn value(&self, m: &WeatherMeasurement) -> Option<f32> {
+    match *self {
+      DrawMode::Temperature => m.air_temperature,
+      DrawMode::ApparentTemp => {
+        m.air_temperature.map(|t| {
+          meteo::apparent_temperature(t,
+                                      Field::WindSpeed.value(m),
+                                      m.dew_point)
+        })
+      }
+      DrawMode::Bivariate { a, .. } => a.value(m),
+      DrawMode::Scalar(field) => field.value(m),
+      DrawMode::Anomaly => m.air_temperature,
+      DrawMode::Barbs => Field::WindSpeed.value(m),
+      // Categorical, see PrecipType::color.
+      DrawMode::PrecipType |
+      DrawMode::Coverage |
+      DrawMode::Density => None,
+    }
+  }
 
-    let mut num_files = 0;
-    for path in fs::read_dir(directory).unwrap().take(max_stations) {
-      let tx = tx.clone();
-      pool.execute(move || {
-        tx.send(parse_file(path.unwrap().path().to_str().unwrap(),
-                           max_measurements))
-          .unwrap();
-      });
-      num_files += 1;
+  // Normalized values are raised to the power of gamma before picking a
+  // color, which stretches one end of the ramp.
+  fn color(&self, value: f32, gamma: f32) -> image::Rgb<u8> {
+    let field = match *self {
+      DrawMode::Scalar(field) => field,
+      DrawMode::Barbs => Field::WindSpeed,
+      DrawMode::Anomaly => return anomaly_color(value, gamma),
+      _ => Field::Temperature,
+    };
+    palette_color(field.normalize(value).powf(gamma))
+  }
+
+  // The values color() spans before saturating, or None for modes that
+  // aren't colored along a single ramp.
+  fn ramp(&self) -> Option<(f32, f32)> {
+    match *self {
+      DrawMode::Anomaly => Some((-ANOMALY_RANGE, ANOMALY_RANGE)),
+      DrawMode::Bivariate { .. } |
+      DrawMode::PrecipType |
+      DrawMode::Coverage |
+      DrawMode::Density => None,
+      mode => Some(mode.field().range()),
     }
+  }
 
-    let start = time::now();
-    let mut last_update = time::now();
-    let mut num_processed = 0;
-    for result in rx.iter().take(num_files) {
-      match result {
-        Ok(station) => {
-          stations.push(station);
+  // Converts a value() to the requested units. Anomalies are differences,
+  // so they are only scaled.
+  fn convert(&self,
+             value: f32,
+             unit: units::TemperatureUnit,
+             wind_unit: units::WindUnit)
+             -> f32 {
+    let field = self.field();
+    if *self == DrawMode::Anomaly {
+      field.convert(value, unit, wind_unit) -
+      field.convert(0.0, unit, wind_unit)
+    } else {
+      field.convert(value, unit, wind_unit)
+    }
+  }
 
-          num_processed += 1;
-          if time::now() - last_update > time::Duration::seconds(1) {
-            last_update = time::now();
-            let elapsed = time::now() - start;
-            let elapsed_secs = elapsed.num_milliseconds() as f64 / 1.0e3;
-            println!("processed {} files in {} - {} files / second",
-                     num_processed,
-                     elapsed_secs,
-                     num_processed as f64 / elapsed_secs);
-          }
-        }
-        Err(error) => {
-          println!("{}", error);
-        }
-      }
+  // The kind of quantity value() returns. PrecipType and Coverage have no
+  // values, so any field will do.
+  fn field(&self) -> Field {
+    match *self {
+      DrawMode::Temperature |
+      DrawMode::ApparentTemp |
+      DrawMode::Anomaly |
+      DrawMode::PrecipType |
+      DrawMode::Coverage |
+      DrawMode::Density => Field::Temperature,
+      DrawMode::Bivariate { a, .. } => a,
+      DrawMode::Scalar(field) => field,
+      DrawMode::Barbs => Field::WindSpeed,
     }
-  });
+  }
+}
 
-  args.value_of("file")
-    .map(|f| parse_file(f, max_measurements))
-    .map(|result| { stations.push(result.unwrap()); });
+// Which of a station's measurements determines its color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Selection {
+  // The first measurement with a value inside the time window.
+  First,
+  // Linear interpolation between the measurements bracketing the instant;
+  // stations without a sample on both sides are not drawn.
+  Interpolate(DateTime<UTC>),
+}
 
-  cpuprofiler::PROFILER.lock().unwrap().stop().unwrap();
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MarkerShape {
+  Square,
+  Circle,
+  // A plus sign, which stays readable where filled markers would overlap.
+  Cross,
+}
 
-  args.value_of("render_dir").map(|directory| {
-    let start = UTC.ymd(2016, 1, 1).and_hms(0, 0, 0);
-    for i in 0..(52) {
-      draw_stations_to_file(&stations,
-                            start + time::Duration::weeks(i),
-                            start + time::Duration::weeks(i + 1),
-                            &path::Path::new(directory)
-                              .join(format!("weather-{:04}.png", i)));
+impl MarkerShape {
+  fn parse(shape: &str) -> Result<MarkerShape, String> {
+    match shape {
+      "square" => Ok(MarkerShape::Square),
+      "circle" => Ok(MarkerShape::Circle),
+      "cross" => Ok(MarkerShape::Cross),
+      _ => Err(format!("unknown marker shape {:?}", shape)),
     }
-  });
+  }
 
+  // Whether the pixel at (dx, dy) within a size x size marker box is painted.
+  fn covers(&self, dx: u32, dy: u32, size: u32) -> bool {
+    let center = (size - 1) as f32 / 2.0;
+    let (ox, oy) = (dx as f32 - center, dy as f32 - center);
+    match *self {
+      MarkerShape::Square => true,
+      MarkerShape::Circle => ox * ox + oy * oy <= (size * size) as f32 / 4.0,
+      MarkerShape::Cross => ox.abs() < 1.0 || oy.abs() < 1.0,
+    }
+  }
+}
 
-  rocket::ignite()
-    .mount("/", routes![index, static_file, map_tile])
-    .manage(stations)
-    .launch();
+// The server's notion of the current time, which --fixed-now pins so that
+// relative queries are reproducible.
+#[derive(Debug, Clone, Copy, Default)]
+struct Clock {
+  fixed: Option<DateTime<UTC>>,
+}
+
+impl Clock {
+  fn now(&self) -> DateTime<UTC> {
+    self.fixed.unwrap_or_else(UTC::now)
+  }
+}
+
+// Text burnt into rendered images, e.g. a data attribution.
+#[derive(Debug, Clone, Copy)]
+struct Attribution {
+  text: &'static str,
+  // Otherwise only tiles in the bottom row carry it.
+  every_tile: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RenderOptions {
+  mode: DrawMode,
+  selection: Selection,
+  marker: MarkerShape,
+  // Average each station's values over this window around the selected time.
+  smooth: Option<time::Duration>,
+  // Color of pixels no station is drawn on.
+  background: image::Rgb<u8>,
+  // Encode as an indexed-color PNG when the tile has at most 256 colors.
+  indexed: bool,
+  // Loaded once at startup and never freed, which keeps the options Copy.
+  baseline: Option<&'static grid::Grid>,
+  attribution: Option<Attribution>,
+  // Like the baseline, loaded once and never freed.
+  highlights: Option<&'static highlight::Highlights>,
+  // Only measurements in [start, end) are drawn; all of them if None.
+  window: Option<(DateTime<UTC>, DateTime<UTC>)>,
+  // Takes the place of window when a request doesn't set one.
+  default_window: Option<(DateTime<UTC>, DateTime<UTC>)>,
+  // Interpret the window and selection instant in local solar time.
+  localtime: bool,
+  clock: Clock,
+  // Ring stations whose value lies outside the color ramp's range.
+  clip_markers: bool,
+  // The time window is [start, end] instead of [start, end).
+  inclusive_end: bool,
+  // Draw basemap's coastlines under the stations.
+  basemap: bool,
+  // Stations without a value take one interpolated from the stations within
+  // this many km.
+  spatial_fill: Option<f32>,
+  // Only measurements taken around this time of day are drawn.
+  hours: Option<HourFilter>,
+  // Merge the stations close to each other into a single marker.
+  cluster: Option<Clustering>,
+  density: DensityKernel,
+  // Whether air temperatures are reduced to sea level.
+  sea_level: SeaLevel,
+  // Color of a one pixel border around every dot marker.
+  outline: Option<image::Rgb<u8>>,
+  // Draw wind barb lines antialiased rather than with hard pixel steps.
+  antialias: bool,
+  // Exponent applied to values normalized to [0, 1] before coloring them.
+  gamma: f32,
+  // Markers of stations reporting calm or variable wind in the wind modes.
+  calm_color: image::Rgb<u8>,
+  variable_color: image::Rgb<u8>,
+}
+
+impl Default for RenderOptions {
+  fn default() -> RenderOptions {
+    RenderOptions {
+      mode: DrawMode::Temperature,
+      selection: Selection::First,
+      marker: MarkerShape::Square,
+      smooth: None,
+      background: image::Rgb([0, 0, 0]),
+      indexed: false,
+      baseline: None,
+      attribution: None,
+      highlights: None,
+      window: None,
+      default_window: None,
+      localtime: false,
+      clock: Clock::default(),
+      clip_markers: false,
+      inclusive_end: false,
+      basemap: false,
+      spatial_fill: None,
+      hours: None,
+      cluster: None,
+      density: DensityKernel {
+        radius: 8,
+        shape: KernelShape::Gaussian,
+      },
+      sea_level: SeaLevel::Off,
+      outline: None,
+      antialias: false,
+      gamma: 1.0,
+      calm_color: image::Rgb([192, 192, 192]),
+      variable_color: image::Rgb([255, 255, 0]),
+    }
+  }
+}
+
+impl RenderOptions {
+  fn window(&self) -> (DateTime<UTC>, DateTime<UTC>) {
+    self.window
+      .or(self.default_window)
+      .unwrap_or((UTC.ymd(1900, 1, 1).and_hms(0, 0, 0),
+                  UTC.ymd(2100, 1, 1).and_hms(0, 0, 0)))
+  }
+
+  // Whether the measurement passes the hour of day filter, if any.
+  fn selects(&self, m: &WeatherMeasurement) -> bool {
+    self.hours.map_or(true, |hours| hours.matches(m.datetime))
+  }
+
+  // The station's values in mode, reduced to sea level if asked to, or None
+  // if the station isn't drawn for lack of an elevation.
+  fn reduce_to_sea_level(&self,
+                         station: &WeatherStation,
+                         value: Option<f32>)
+                         -> Option<Option<f32>> {
+    match self.mode {
+      DrawMode::Temperature |
+      DrawMode::Scalar(Field::Temperature) => {}
+      _ => return Some(value),
+    }
+    match (self.sea_level, station.elevation) {
+      (SeaLevel::Off, _) => Some(value),
+      (_, Some(elevation)) => {
+        Some(value.map(|t| meteo::sea_level_temperature(t, elevation as f32)))
+      }
+      (SeaLevel::KeepMissing, None) => Some(value),
+      (SeaLevel::SkipMissing, None) => None,
+    }
+  }
+}
+
+// Reduction of air temperatures to what they would be at sea level, so that
+// stations at different elevations can be compared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SeaLevel {
+  Off,
+  // Stations without an elevation keep their measured temperatures.
+  KeepMissing,
+  // Stations without an elevation aren't drawn.
+  SkipMissing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KernelShape {
+  Box,
+  Gaussian,
+}
+
+impl KernelShape {
+  fn parse(shape: &str) -> Result<KernelShape, String> {
+    match shape {
+      "box" => Ok(KernelShape::Box),
+      "gaussian" => Ok(KernelShape::Gaussian),
+      _ => Err(format!("unknown density kernel {:?}", shape)),
+    }
+  }
+}
+
+// What mode=density spreads each station's count with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DensityKernel {
+  // In pixels; 0 leaves every count on its own pixel.
+  radius: u32,
+  shape: KernelShape,
+}
+
+impl DensityKernel {
+  // Weights from -radius to radius along one axis. The center one is 1, so
+  // a station on its own peaks at 1.
+  fn weights(&self) -> Vec<f32> {
+    // The radius spans two standard deviations.
+    let sigma = (self.radius as f32 / 2.0).max(0.5);
+    let r = self.radius as i32;
+    (-r..r + 1)
+      .map(|i| match self.shape {
+        KernelShape::Box => 1.0,
+        KernelShape::Gaussian => {
+          (-(i * i) as f32 / (2.0 * sigma * sigma)).exp()
+        }
+      })
+      .collect()
+  }
+}
+
+// How stations sharing a cluster contribute to its value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClusterWeight {
+  Equal,
+  // By the number of measurements in the window with a value.
+  Count,
+}
+
+impl ClusterWeight {
+  fn parse(weight: &str) -> Result<ClusterWeight, String> {
+    match weight {
+      "equal" => Ok(ClusterWeight::Equal),
+      "count" => Ok(ClusterWeight::Count),
+      _ => Err(format!("unknown cluster weight {:?}", weight)),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Clustering {
+  // Side of the square cells of the image whose stations are merged, in
+  // pixels.
+  cell: u32,
+  weight: ClusterWeight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HourFilter {
+  // UTC hour of the day, fractional and possibly outside [0, 24) once
+  // shifted to a station's solar time.
+  hour: f32,
+  // How many hours before or after it measurements may be.
+  window: f32,
+}
+
+impl HourFilter {
+  fn matches(&self, datetime: DateTime<UTC>) -> bool {
+    let hour = datetime.hour() as f32 + datetime.minute() as f32 / 60.0;
+    let distance = (hour - self.hour).abs() % 24.0;
+    distance.min(24.0 - distance) <= self.window
+  }
+}
+
+// How far a station's local solar time is ahead of UTC, from its longitude
+// as there is no time zone data.
+fn solar_offset(longitude: f32) -> time::Duration {
+  time::Duration::seconds((longitude / 15.0 * 3600.0) as i64)
+}
+
+// The measurements (which are sorted by time) inside [start_time, end_time).
+fn measurements_in(measurements: &[WeatherMeasurement],
+                   start_time: DateTime<UTC>,
+                   end_time: DateTime<UTC>)
+                   -> &[WeatherMeasurement] {
+  measurements_within(measurements, start_time, end_time, false)
+}
+
+// Like measurements_in, but [start_time, end_time] with inclusive_end.
+fn measurements_within(measurements: &[WeatherMeasurement],
+                       start_time: DateTime<UTC>,
+                       end_time: DateTime<UTC>,
+                       inclusive_end: bool)
+                       -> &[WeatherMeasurement] {
+  // The comparators never return Equal, so the searches find the first
+  // measurement past each bound however many sit exactly on it.
+  let before = |is_before: bool| if is_before {
+    cmp::Ordering::Less
+  } else {
+    cmp::Ordering::Greater
+  };
+  let start = measurements.binary_search_by(|m| before(m.datetime < start_time))
+    .unwrap_err();
+  let (_, after) = measurements.split_at(start);
+
+  let end = after.binary_search_by(|m| {
+      before(m.datetime < end_time ||
+             inclusive_end && m.datetime == end_time)
+    })
+    .unwrap_err();
+  let (measurements, _) = after.split_at(end);
+  measurements
+}
+
+// Mean of `value` over the measurements within +-window/2 of `at`.
+fn mean_around<F>(measurements: &[WeatherMeasurement],
+                  at: DateTime<UTC>,
+                  window: time::Duration,
+                  value: F)
+                  -> Option<f32>
+  where F: Fn(&WeatherMeasurement) -> Option<f32>
+{
+  let half = window / 2;
+  let values = measurements_in(measurements, at - half, at + half)
+    .iter()
+    .filter_map(|m| value(m))
+    .collect::<Vec<_>>();
+  if values.is_empty() {
+    None
+  } else {
+    Some(values.iter().sum::<f32>() / values.len() as f32)
+  }
+}
+
+// Linearly interpolates `value` at `at` between the closest measurements
+// before and after it that have a value.
+fn interpolate_at<F>(measurements: &[WeatherMeasurement],
+                     at: DateTime<UTC>,
+                     value: F)
+                     -> Option<f32>
+  where F: Fn(&WeatherMeasurement) -> Option<f32>
+{
+  let index = match measurements.binary_search_by(|m| m.datetime.cmp(&at)) {
+    Ok(index) => {
+      if let Some(v) = value(&measurements[index]) {
+        return Some(v);
+      }
+      index
+    }
+    Err(index) => index,
+  };
+
+  let before = measurements[..index]
+    .iter()
+    .rev()
+    .filter_map(|m| value(m).map(|v| (m.datetime, v)))
+    .next();
+  let after = measurements[index..]
+    .iter()
+    .filter_map(|m| value(m).map(|v| (m.datetime, v)))
+    .next();
+
+  match (before, after) {
+    (Some((t0, v0)), Some((t1, v1))) => {
+      let span = (t1 - t0).num_seconds() as f32;
+      if span <= 0.0 {
+        return Some(v0);
+      }
+      let fraction = (at - t0).num_seconds() as f32 / span;
+      Some(v0 + (v1 - v0) * fraction)
+    }
+    _ => None,
+  }
+}
+
+// Parses a hex RRGGBB color, with or without a leading #.
+fn parse_color(s: &str) -> Result<image::Rgb<u8>, String> {
+  let hex = s.trim_left_matches('#');
+  if hex.len() != 6 || !hex.chars().all(|c| c.is_digit(16)) {
+    return Err(format!("expected a RRGGBB color, got {:?}", s));
+  }
+  let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+  Ok(image::Rgb([channel(0), channel(2), channel(4)]))
+}
+
+// Converts a hue in degrees and saturation and value in [0, 1] to RGB.
+fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> image::Rgb<u8> {
+  let c = value * saturation;
+  let h = (hue % 360.0) / 60.0;
+  let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+  let (r, g, b) = match h as u32 {
+    0 => (c, x, 0.0),
+    1 => (x, c, 0.0),
+    2 => (0.0, c, x),
+    3 => (0.0, x, c),
+    4 => (x, 0.0, c),
+    _ => (c, 0.0, x),
+  };
+  let m = value - c;
+  let channel = |v: f32| (255.0 * (v + m)).round() as u8;
+  image::Rgb([channel(r), channel(g), channel(b)])
+}
+
+// Field a picks the hue from blue (low) to red (high), field b the
+// brightness.
+fn bivariate_color(a: Field,
+                   value_a: f32,
+                   b: Field,
+                   value_b: f32)
+                   -> image::Rgb<u8> {
+  hsv_to_rgb(240.0 * (1.0 - a.normalize(value_a)),
+             1.0,
+             0.25 + 0.75 * b.normalize(value_b))
+}
+
+// Blue to red via purple, for a value already scaled to [0, 1].
+fn palette_color(scaled: f32) -> image::Rgb<u8> {
+  image::Rgb([(255.0 * scaled) as u8, 127u8, (255.0 * (1.0 - scaled)) as u8])
+}
+
+// Anomalies of this many degrees or more get the most saturated colors.
+const ANOMALY_RANGE: f32 = 10.0;
+
+// Blue below zero, red above, fading to white at zero.
+// Gamma applies to the magnitude, so both sides stretch alike.
+fn anomaly_color(difference: f32, gamma: f32) -> image::Rgb<u8> {
+  let scaled = (difference / ANOMALY_RANGE).max(-1.0).min(1.0);
+  let fade = (255.0 * (1.0 - scaled.abs().powf(gamma))) as u8;
+  if scaled < 0.0 {
+    image::Rgb([fade, fade, 255])
+  } else {
+    image::Rgb([255, fade, fade])
+  }
+}
+
+// What draw_stations actually painted.
+#[derive(Debug, Default)]
+struct DrawStats {
+  // Stations inside the bounds that had a value to color them by.
+  stations_drawn: usize,
+  min: Option<f32>,
+  max: Option<f32>,
+}
+
+impl DrawStats {
+  fn add(&mut self, value: f32) {
+    self.stations_drawn += 1;
+    self.min = Some(self.min.map_or(value, |min| min.min(value)));
+    self.max = Some(self.max.map_or(value, |max| max.max(value)));
+  }
+}
+
+// The value a station is drawn with, following the selection and smoothing
+// options.
+fn station_value<F>(measurements: &[WeatherMeasurement],
+                    start_time: DateTime<UTC>,
+                    end_time: DateTime<UTC>,
+                    options: &RenderOptions,
+                    value: F)
+                    -> Option<f32>
+  where F: Fn(&WeatherMeasurement) -> Option<f32>
+{
+  let selected = match options.selection {
+    Selection::Interpolate(at) => {
+      interpolate_at(measurements, at, &value).map(|v| (at, v))
+    }
+    Selection::First => {
+      measurements_within(measurements,
+                          start_time,
+                          end_time,
+                          options.inclusive_end)
+        .iter()
+        .filter(|m| options.selects(m))
+        .filter_map(|m| value(m).map(|v| (m.datetime, v)))
+        .next()
+    }
+  };
+  selected.map(|(at, v)| match options.smooth {
+    Some(window) => mean_around(measurements, at, window, &value).unwrap_or(v),
+    None => v,
+  })
+}
+
+// The window and options to select a station's measurements with. With
+// localtime the window and instant are in each station's solar time, so they
+// are shifted back to UTC per station.
+fn local_window(station: &WeatherStation,
+                start_time: DateTime<UTC>,
+                end_time: DateTime<UTC>,
+                options: &RenderOptions)
+                -> (DateTime<UTC>, DateTime<UTC>, RenderOptions) {
+  let mut options = *options;
+  if !options.localtime {
+    return (start_time, end_time, options);
+  }
+  let offset = solar_offset(station.longitude);
+  if let Selection::Interpolate(at) = options.selection {
+    options.selection = Selection::Interpolate(at - offset);
+  }
+  if let Some(ref mut hours) = options.hours {
+    hours.hour -= offset.num_seconds() as f32 / 3600.0;
+  }
+  (start_time - offset, end_time - offset, options)
+}
+
+// Where and in which color a station is drawn.
+struct Marker {
+  x: i32,
+  y: i32,
+  color: image::Rgb<u8>,
+  // Size relative to regular markers.
+  scale: u32,
+  // The value is beyond the color ramp, see RenderOptions::clip_markers.
+  clipped: bool,
+  // Only when drawing wind: calm, or a speed with a direction.
+  wind: Option<WindMeasurement>,
+  // The value the color stands for, if on the mode's ramp and not
+  // highlighted, and its weight within a cluster.
+  value: Option<f32>,
+  weight: f32,
+}
+
+impl Marker {
+  // Calls `pixel` with every pixel the marker's dot covers, which may lie
+  // outside the image.
+  fn dot_pixels(&self,
+                dot_radius: u32,
+                shape: MarkerShape,
+                pixel: &mut FnMut(i32, i32)) {
+    let size = dot_radius * self.scale;
+    for dx in 0..size {
+      for dy in 0..size {
+        if shape.covers(dx, dy, size) {
+          pixel(self.x + (dx as i32 - size as i32 / 2),
+                self.y + (dy as i32 - size as i32 / 2));
+        }
+      }
+    }
+  }
+}
+
+// The area an image shows and its size in pixels, to project locations onto
+// the image.
+struct Viewport {
+  longitude_min: f32,
+  longitude_max: f32,
+  // Clamped to what web mercator can show.
+  latitude_min: f32,
+  latitude_max: f32,
+  mercator_min: f32,
+  mercator_max: f32,
+  width: u32,
+  height: u32,
+}
+
+impl Viewport {
+  // None if the latitudes leave nothing to show, see mercator_bounds.
+  fn new(longitude_min: f32,
+         longitude_max: f32,
+         latitude_min: f32,
+         latitude_max: f32,
+         width: u32,
+         height: u32)
+         -> Option<Viewport> {
+    let (mercator_min, mercator_max) =
+      match mercator_bounds(latitude_min, latitude_max) {
+        Some(bounds) => bounds,
+        None => return None,
+      };
+    Some(Viewport {
+      longitude_min: longitude_min,
+      longitude_max: longitude_max,
+      latitude_min: clamp_latitude(latitude_min),
+      latitude_max: clamp_latitude(latitude_max),
+      mercator_min: mercator_min,
+      mercator_max: mercator_max,
+      width: width,
+      height: height,
+    })
+  }
+
+  // Image coordinates of a location, which may lie outside the image.
+  fn project(&self, longitude: f32, latitude: f32) -> (f32, f32) {
+    ((longitude - self.longitude_min) /
+     (self.longitude_max - self.longitude_min) *
+     (self.width - 1) as f32,
+     (1.0 -
+      (mercator(clamp_latitude(latitude)) - self.mercator_min) /
+      (self.mercator_max - self.mercator_min)) *
+     (self.height - 1) as f32)
+  }
+
+  // The pixel a location is drawn at, or None if it is outside the bounds.
+  fn pixel(&self, longitude: f32, latitude: f32) -> Option<(i32, i32)> {
+    if longitude < self.longitude_min || longitude > self.longitude_max ||
+       latitude < self.latitude_min || latitude > self.latitude_max {
+      return None;
+    }
+    let (x, y) = self.project(longitude, latitude);
+    let (x, y) = (x as i32, y as i32);
+    check_ge!(x, 0);
+    check_lt!(x, self.width as i32);
+    check_ge!(y, 0);
+    check_lt!(y, self.height as i32);
+    Some((x, y))
+  }
+}
+
+// A station and the pixel it is drawn at.
+type Placed<'a> = (&'a WeatherStation, i32, i32);
+
+// The stations within the viewport, with their pixels.
+fn position_stations<'a, I>(stations: I, viewport: &Viewport) -> Vec<Placed<'a>>
+  where I: IntoIterator<Item = &'a WeatherStation>
+{
+  debug!("requesting stations for longitude {} to {}, latitude {} to {}",
+         viewport.longitude_min,
+         viewport.longitude_max,
+         viewport.latitude_min,
+         viewport.latitude_max);
+  stations.into_iter()
+    .filter_map(|station| {
+      viewport.pixel(station.longitude, station.latitude)
+        .map(|(x, y)| (station, x, y))
+    })
+    .collect()
+}
+
+// With options.spatial_fill, the values of the stations that those without
+// one can take theirs from, typically including some just outside the
+// viewport. Empty otherwise.
+fn neighbor_samples<'a, I>(stations: I,
+                           start_time: DateTime<UTC>,
+                           end_time: DateTime<UTC>,
+                           options: &RenderOptions)
+                           -> Vec<grid::Sample>
+  where I: IntoIterator<Item = &'a WeatherStation>
+{
+  let mut neighbors = vec![];
+  if options.spatial_fill.is_none() {
+    return neighbors;
+  }
+  for station in stations {
+    let (start_time, end_time, options) =
+      local_window(station, start_time, end_time, options);
+    let value = station_value(&station.measurements,
+                              start_time,
+                              end_time,
+                              &options,
+                              |m| options.mode.value(m));
+    if let Some(Some(value)) = options.reduce_to_sea_level(station, value) {
+      neighbors.push(grid::Sample {
+        latitude: station.latitude,
+        longitude: station.longitude,
+        value: value,
+      });
+    }
+  }
+  neighbors
+}
+
+// Colors the placed stations according to the options; shared by the raster
+// and vector renderers.
+fn place_stations(placed: &[Placed],
+                  neighbors: &[grid::Sample],
+                  start_time: DateTime<UTC>,
+                  end_time: DateTime<UTC>,
+                  options: &RenderOptions)
+                  -> (Vec<Marker>, DrawStats) {
+  let mut markers = vec![];
+  let mut stats = DrawStats::default();
+
+  for &(station, x, y) in placed {
+
+    let (start_time, end_time, station_options) =
+      local_window(station, start_time, end_time, options);
+    let options = &station_options;
+
+    let sample = |value: &Fn(&WeatherMeasurement) -> Option<f32>| {
+      station_value(&station.measurements,
+                    start_time,
+                    end_time,
+                    options,
+                    value)
+    };
+    // Highlighted stations are drawn even without a value.
+    let highlight = options.highlights
+      .and_then(|h| h.get(&station.usaf, &station.wban));
+    let mut clipped = false;
+    let mut colored = None;
+    let color = match options.mode {
+      DrawMode::Bivariate { a, b } => {
+        match (sample(&|m| a.value(m)), sample(&|m| b.value(m))) {
+          (Some(value_a), Some(value_b)) => {
+            stats.add(value_a);
+            bivariate_color(a, value_a, b, value_b)
+          }
+          _ => image::Rgb([128u8, 128u8, 128u8]),
+        }
+      }
+      DrawMode::Anomaly => {
+        let baseline = options.baseline
+          .and_then(|b| b.sample(station.latitude, station.longitude));
+        match (sample(&|m| m.air_temperature), baseline) {
+          (Some(t), Some(baseline)) => {
+            stats.add(t - baseline);
+            colored = Some(t - baseline);
+            anomaly_color(t - baseline, options.gamma)
+          }
+          _ => image::Rgb([128u8, 128u8, 128u8]),
+        }
+      }
+      DrawMode::PrecipType => {
+        let measurements = measurements_within(&station.measurements,
+                                               start_time,
+                                               end_time,
+                                               options.inclusive_end);
+        let reported = match options.selection {
+          // Categories can't be interpolated, so whatever was last reported.
+          Selection::Interpolate(at) => {
+            measurements.iter()
+              .rev()
+              .find(|m| m.datetime <= at)
+              .and_then(|m| m.precip_type)
+          }
+          Selection::First => {
+            measurements.iter()
+              .filter(|m| options.selects(m))
+              .filter_map(|m| m.precip_type)
+              .next()
+          }
+        };
+        match reported {
+          Some(precip_type) => {
+            stats.stations_drawn += 1;
+            precip_type.color()
+          }
+          None if highlight.is_some() => image::Rgb([0u8, 0u8, 0u8]),
+          None => continue,
+        }
+      }
+      DrawMode::Coverage => {
+        let measurements = measurements_within(&station.measurements,
+                                               start_time,
+                                               end_time,
+                                               options.inclusive_end);
+        if !measurements.iter().any(|m| options.selects(m)) {
+          image::Rgb([0u8, 0u8, 0u8])
+        } else {
+          stats.stations_drawn += 1;
+          image::Rgb([255u8, 255u8, 255u8])
+        }
+      }
+      DrawMode::Density => {
+        let measurements = measurements_within(&station.measurements,
+                                               start_time,
+                                               end_time,
+                                               options.inclusive_end);
+        if !measurements.iter().any(|m| options.selects(m)) {
+          continue;
+        }
+        stats.stations_drawn += 1;
+        image::Rgb([255u8, 255u8, 255u8])
+      }
+      mode => {
+        let fill = |km| {
+          grid::idw(neighbors, station.latitude, station.longitude, km, 1)
+        };
+        let value = sample(&|m| mode.value(m));
+        let value = match options.reduce_to_sea_level(station, value) {
+          Some(value) => value,
+          None => continue,
+        };
+        // Neighbors were reduced to sea level already.
+        match value.or_else(|| options.spatial_fill.and_then(&fill)) {
+          Some(t) => {
+            stats.add(t);
+            clipped = options.clip_markers && mode.field().clipped(t);
+            colored = Some(t);
+            mode.color(t, options.gamma)
+          }
+          // Stations without samples on both sides of the instant.
+          None if options.selection != Selection::First &&
+                  highlight.is_none() => continue,
+          None => image::Rgb([0u8, 0u8, 0u8]),
+        }
+      }
+    };
+
+    let wind = match options.mode {
+      DrawMode::Scalar(Field::WindSpeed) |
+      DrawMode::Barbs => {
+        measurements_within(&station.measurements,
+                            start_time,
+                            end_time,
+                            options.inclusive_end)
+          .iter()
+          .filter(|m| options.selects(m))
+          .filter_map(|m| match m.wind {
+            Some(WindMeasurement::Normal { direction: Some(_), .. }) |
+            Some(WindMeasurement::Calm) |
+            Some(WindMeasurement::Variable) => m.wind,
+            _ => None,
+          })
+          .next()
+      }
+      _ => None,
+    };
+    // Neither has a speed on the ramp or a direction to draw.
+    let color = match wind {
+      Some(WindMeasurement::Calm) => options.calm_color,
+      Some(WindMeasurement::Variable) => options.variable_color,
+      _ => color,
+    };
+    let weight = match options.cluster {
+      Some(Clustering { weight: ClusterWeight::Count, .. }) => {
+        measurements_within(&station.measurements,
+                            start_time,
+                            end_time,
+                            options.inclusive_end)
+          .iter()
+          .filter(|m| options.selects(m) && options.mode.value(m).is_some())
+          .count() as f32
+      }
+      _ => 1.0,
+    };
+
+    markers.push(Marker {
+      x: x,
+      y: y,
+      color: highlight.unwrap_or(color),
+      scale: match (highlight, options.highlights) {
+        (Some(_), Some(highlights)) => highlights.scale,
+        _ => 1,
+      },
+      clipped: clipped,
+      wind: wind,
+      value: if highlight.is_some() { None } else { colored },
+      weight: weight,
+    });
+  }
+
+  match options.cluster {
+    Some(clustering) => (cluster_markers(markers, clustering, options), stats),
+    None => (markers, stats),
+  }
+}
+
+// Merges the markers with a value in each cell into one at their mean
+// position, colored by the weighted mean of their values. Where none of
+// them carries any weight, every marker counts the same.
+fn cluster_markers(markers: Vec<Marker>,
+                   clustering: Clustering,
+                   options: &RenderOptions)
+                   -> Vec<Marker> {
+  #[derive(Default)]
+  struct Cluster {
+    x: i64,
+    y: i64,
+    n: usize,
+    sum: f32,
+    weighted_sum: f32,
+    weight: f32,
+  }
+
+  let cell = clustering.cell as f32;
+  let mut clusters = collections::BTreeMap::new();
+  let mut unclustered = vec![];
+  for marker in markers {
+    let value = match marker.value {
+      Some(value) => value,
+      None => {
+        unclustered.push(marker);
+        continue;
+      }
+    };
+    let key = ((marker.x as f32 / cell).floor() as i32,
+               (marker.y as f32 / cell).floor() as i32);
+    let cluster = clusters.entry(key).or_insert_with(Cluster::default);
+    cluster.x += marker.x as i64;
+    cluster.y += marker.y as i64;
+    cluster.n += 1;
+    cluster.sum += value;
+    cluster.weighted_sum += value * marker.weight;
+    cluster.weight += marker.weight;
+  }
+
+  let mode = options.mode;
+  let mut clustered = clusters.values()
+    .map(|cluster| {
+      let value = if cluster.weight > 0.0 {
+        cluster.weighted_sum / cluster.weight
+      } else {
+        cluster.sum / cluster.n as f32
+      };
+      Marker {
+        x: (cluster.x / cluster.n as i64) as i32,
+        y: (cluster.y / cluster.n as i64) as i32,
+        color: mode.color(value, options.gamma),
+        scale: 1,
+        clipped: options.clip_markers && mode.field().clipped(value),
+        wind: None,
+        value: Some(value),
+        weight: cluster.weight,
+      }
+    })
+    .collect::<Vec<_>>();
+  // Highlights and markers without a value are drawn on top.
+  clustered.extend(unclustered);
+  clustered
+}
+
+// A one pixel wide circle around (x, y).
+fn draw_ring(img: &mut image::RgbImage,
+             x: i32,
+             y: i32,
+             radius: u32,
+             color: image::Rgb<u8>) {
+  let (width, height) = img.dimensions();
+  let r = radius as i32;
+  for dx in -r..r + 1 {
+    for dy in -r..r + 1 {
+      let distance = ((dx * dx + dy * dy) as f32).sqrt();
+      let (px, py) = (x + dx, y + dy);
+      if (distance - radius as f32).abs() < 0.5 && px >= 0 &&
+         px < width as i32 && py >= 0 && py < height as i32 {
+        img.put_pixel(px as u32, py as u32, color);
+      }
+    }
+  }
+}
+
+// The part of the line from `from` to `to` inside the image, if any.
+fn clip_line(img: &image::RgbImage,
+             from: (f32, f32),
+             to: (f32, f32))
+             -> Option<((f32, f32), (f32, f32))> {
+  let (width, height) = img.dimensions();
+  let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+  // Liang-Barsky clipping: only the part t0..t1 of the line is inside.
+  let (mut t0, mut t1) = (0.0f32, 1.0f32);
+  for &(p, q) in &[(-dx, from.0),
+                   (dx, (width - 1) as f32 - from.0),
+                   (-dy, from.1),
+                   (dy, (height - 1) as f32 - from.1)] {
+    if p == 0.0 {
+      if q < 0.0 {
+        return None;
+      }
+    } else if p < 0.0 {
+      t0 = t0.max(q / p);
+    } else {
+      t1 = t1.min(q / p);
+    }
+  }
+  if t0 > t1 {
+    return None;
+  }
+  Some(((from.0 + t0 * dx, from.1 + t0 * dy),
+        (from.0 + t1 * dx, from.1 + t1 * dy)))
+}
+
+// A one pixel wide line from `from` to `to`, clipped to the image.
+fn draw_line(img: &mut image::RgbImage,
+             from: (f32, f32),
+             to: (f32, f32),
+             color: image::Rgb<u8>) {
+  let (from, to) = match clip_line(img, from, to) {
+    Some(line) => line,
+    None => return,
+  };
+  let (width, height) = img.dimensions();
+  let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+  let steps = dx.abs().max(dy.abs()).ceil().max(1.0);
+  for i in 0..steps as u32 + 1 {
+    let t = i as f32 / steps;
+    let (x, y) = ((from.0 + t * dx).round() as i32,
+                  (from.1 + t * dy).round() as i32);
+    if x >= 0 && x < width as i32 && y >= 0 && y < height as i32 {
+      img.put_pixel(x as u32, y as u32, color);
+    }
+  }
+}
+
+// Mixes `coverage` (0 to 1) of color into the pixel, if it's in the image.
+fn blend_pixel(img: &mut image::RgbImage,
+               x: i32,
+               y: i32,
+               color: image::Rgb<u8>,
+               coverage: f32) {
+  let (width, height) = img.dimensions();
+  if x < 0 || x >= width as i32 || y < 0 || y >= height as i32 {
+    return;
+  }
+  let pixel = img.get_pixel_mut(x as u32, y as u32);
+  for (c, &target) in pixel.data.iter_mut().zip(color.data.iter()) {
+    *c = (*c as f32 + (target as f32 - *c as f32) * coverage).round() as u8;
+  }
+}
+
+// Like draw_line, but antialiased with Xiaolin Wu's algorithm: each step
+// along the major axis splits the color between the two pixels straddling
+// the line, in proportion to how close it passes to each.
+fn draw_line_aa(img: &mut image::RgbImage,
+                from: (f32, f32),
+                to: (f32, f32),
+                color: image::Rgb<u8>) {
+  let ((mut x0, mut y0), (mut x1, mut y1)) = match clip_line(img, from, to) {
+    Some(line) => line,
+    None => return,
+  };
+  // Steep lines are walked along y, with the coordinates swapped.
+  let steep = (y1 - y0).abs() > (x1 - x0).abs();
+  if steep {
+    mem::swap(&mut x0, &mut y0);
+    mem::swap(&mut x1, &mut y1);
+  }
+  if x0 > x1 {
+    mem::swap(&mut x0, &mut x1);
+    mem::swap(&mut y0, &mut y1);
+  }
+  let gradient = if x1 > x0 { (y1 - y0) / (x1 - x0) } else { 0.0 };
+  for x in x0.round() as i32..x1.round() as i32 + 1 {
+    let y = y0 + gradient * (x as f32 - x0);
+    let (y_floor, fraction) = (y.floor() as i32, y - y.floor());
+    for &(y, coverage) in &[(y_floor, 1.0 - fraction),
+                            (y_floor + 1, fraction)] {
+      if steep {
+        blend_pixel(img, y, x, color, coverage);
+      } else {
+        blend_pixel(img, x, y, color, coverage);
+      }
+    }
+  }
+}
+
+// Blurred station counts at which mode=density reaches the end of the ramp.
+const DENSITY_SATURATION: f32 = 5.0;
+
+// Counts the markers per pixel and blurs the counts with options.density,
+// one axis after the other, then colors the pixels any station spread to.
+// Stations on other tiles don't spread onto this one.
+fn draw_density(img: &mut image::RgbImage,
+                markers: &[Marker],
+                options: &RenderOptions) {
+  let (width, height) = img.dimensions();
+  let (w, h) = (width as usize, height as usize);
+  let weights = options.density.weights();
+  let r = options.density.radius as i32;
+
+  let mut counts = vec![0.0f32; w * h];
+  for marker in markers {
+    if marker.x >= 0 && marker.x < w as i32 && marker.y >= 0 &&
+       marker.y < h as i32 {
+      counts[marker.y as usize * w + marker.x as usize] += 1.0;
+    }
+  }
+  let mut rows = vec![0.0f32; w * h];
+  for y in 0..h {
+    for x in 0..w {
+      let count = counts[y * w + x];
+      if count == 0.0 {
+        continue;
+      }
+      for (i, &weight) in weights.iter().enumerate() {
+        let nx = x as i32 + i as i32 - r;
+        if nx >= 0 && nx < w as i32 {
+          rows[y * w + nx as usize] += count * weight;
+        }
+      }
+    }
+  }
+  let mut density = vec![0.0f32; w * h];
+  for y in 0..h {
+    for x in 0..w {
+      let count = rows[y * w + x];
+      if count == 0.0 {
+        continue;
+      }
+      for (i, &weight) in weights.iter().enumerate() {
+        let ny = y as i32 + i as i32 - r;
+        if ny >= 0 && ny < h as i32 {
+          density[ny as usize * w + x] += count * weight;
+        }
+      }
+    }
+  }
+
+  for (i, &value) in density.iter().enumerate() {
+    // Gaussian tails too faint to tell from the background.
+    if value < 0.01 {
+      continue;
+    }
+    let scaled = (value / DENSITY_SATURATION).min(1.0).powf(options.gamma);
+    img.put_pixel((i % w) as u32, (i / w) as u32, palette_color(scaled));
+  }
+}
+
+fn draw_stations(placed: &[Placed],
+                 neighbors: &[grid::Sample],
+                 viewport: &Viewport,
+                 dot_radius: u32,
+                 start_time: DateTime<UTC>,
+                 end_time: DateTime<UTC>,
+                 options: &RenderOptions)
+                 -> (image::RgbImage, DrawStats) {
+  let (markers, stats) =
+    place_stations(placed, neighbors, start_time, end_time, options);
+  (draw_markers(&markers, viewport, dot_radius, options), stats)
+}
+
+fn draw_markers(markers: &[Marker],
+                viewport: &Viewport,
+                dot_radius: u32,
+                options: &RenderOptions)
+                -> image::RgbImage {
+  let (width, height) = (viewport.width, viewport.height);
+  let mut img =
+    image::ImageBuffer::from_pixel(width, height, options.background);
+  if options.basemap {
+    basemap::draw(&mut img,
+                  &|longitude, latitude| viewport.project(longitude, latitude),
+                  basemap::COLOR);
+  }
+  if options.mode == DrawMode::Density {
+    draw_density(&mut img, markers, options);
+    return img;
+  }
+  for marker in markers {
+    if options.mode == DrawMode::Barbs {
+      match marker.wind {
+        Some(WindMeasurement::Normal { speed, direction: Some(direction) }) => {
+          let barb = barbs::barb(marker.x as f32,
+                                 marker.y as f32,
+                                 direction,
+                                 speed);
+          barbs::draw(&mut img, &barb, marker.color, options.antialias);
+          continue;
+        }
+        Some(WindMeasurement::Calm) => {
+          barbs::draw_calm(&mut img, marker.x, marker.y, marker.color);
+          continue;
+        }
+        _ => {}
+      }
+    }
+    if let Some(outline) = options.outline {
+      // Everything next to the dot; the dot itself is filled over it below.
+      marker.dot_pixels(dot_radius, options.marker, &mut |px, py| {
+        for &(nx, ny) in &[(px - 1, py - 1), (px, py - 1), (px + 1, py - 1),
+                           (px - 1, py), (px + 1, py),
+                           (px - 1, py + 1), (px, py + 1), (px + 1, py + 1)] {
+          if nx >= 0 && nx < width as i32 && ny >= 0 && ny < height as i32 {
+            img.put_pixel(nx as u32, ny as u32, outline);
+          }
+        }
+      });
+    }
+    marker.dot_pixels(dot_radius, options.marker, &mut |px, py| {
+      if px >= 0 && px < width as i32 && py >= 0 && py < height as i32 {
+        img.put_pixel(px as u32, py as u32, marker.color);
+      }
+    });
+    if marker.clipped {
+      let size = dot_radius * marker.scale;
+      draw_ring(&mut img, marker.x, marker.y, size / 2 + 2, marker.color);
+    }
+  }
+
+  img
+}
+
+// Turns an image draw_markers drew from `previous` into the one it would
+// draw from `markers` by repainting only the pixels under markers whose
+// color changed. Every marker covering such a pixel repaints it in drawing
+// order, so where markers overlap the last one still wins. Returns false
+// without touching the image if the markers can't be compared that way, for
+// instance because they moved.
+fn repaint_changed(img: &mut image::RgbImage,
+                   previous: &[Marker],
+                   markers: &[Marker],
+                   dot_radius: u32,
+                   options: &RenderOptions)
+                   -> bool {
+  let moved = previous.len() != markers.len() ||
+              previous.iter()
+    .zip(markers)
+    .any(|(a, b)| (a.x, a.y, a.scale) != (b.x, b.y, b.scale));
+  // Barbs, clipping rings and outlines reach beyond the dots.
+  if moved || options.mode == DrawMode::Barbs || options.clip_markers ||
+     options.outline.is_some() {
+    return false;
+  }
+  let mut dirty = collections::HashSet::new();
+  for (before, after) in previous.iter().zip(markers) {
+    if before.color != after.color {
+      after.dot_pixels(dot_radius,
+                       options.marker,
+                       &mut |x, y| { dirty.insert((x, y)); });
+    }
+  }
+  if dirty.is_empty() {
+    return true;
+  }
+  let (width, height) = img.dimensions();
+  for marker in markers {
+    marker.dot_pixels(dot_radius, options.marker, &mut |x, y| {
+      if dirty.contains(&(x, y)) && x >= 0 && x < width as i32 && y >= 0 &&
+         y < height as i32 {
+        img.put_pixel(x as u32, y as u32, marker.color);
+      }
+    });
+  }
+  true
+}
+
+// Writes text into the bottom right corner on a black backdrop, which keeps
+// it readable over any colors.
+fn draw_attribution(img: &mut image::RgbImage, text: &str) {
+  let (width, height) = img.dimensions();
+  let margin = 2;
+  let text_width = font::text_width(text);
+  if text_width + 2 * margin > width ||
+     font::GLYPH_HEIGHT + 2 * margin > height {
+    return;
+  }
+  let left = width - text_width - 2 * margin;
+  let top = height - font::GLYPH_HEIGHT - 2 * margin;
+  for x in left..width {
+    for y in top..height {
+      img.put_pixel(x, y, image::Rgb([0u8, 0u8, 0u8]));
+    }
+  }
+  font::draw_text(img,
+                  (left + margin) as i32,
+                  (top + margin) as i32,
+                  text,
+                  image::Rgb([255u8, 255u8, 255u8]));
+}
+
+// An animation frame and the markers it was drawn from.
+struct Frame {
+  img: image::RgbImage,
+  markers: Vec<Marker>,
+}
+
+// Draws a frame of the --render_dir animation. Given the previous frame, only
+// the stations whose color changed since are repainted onto it.
+fn render_frame(stations: &Vec<WeatherStation>,
+                latitude_min: f32,
+                latitude_max: f32,
+                start_time: DateTime<UTC>,
+                end_time: DateTime<UTC>,
+                previous: Option<Frame>)
+                -> Frame {
+  let options = RenderOptions::default();
+  // render_frames checked that the latitudes leave something to show.
+  let viewport =
+    Viewport::new(-180.0, 180.0, latitude_min, latitude_max, 1024, 512)
+      .unwrap();
+  let (markers, _) = place_stations(&position_stations(stations, &viewport),
+                                    &neighbor_samples(stations,
+                                                      start_time,
+                                                      end_time,
+                                                      &options),
+                                    start_time,
+                                    end_time,
+                                    &options);
+  if let Some(mut previous) = previous {
+    if repaint_changed(&mut previous.img,
+                       &previous.markers,
+                       &markers,
+                       1,
+                       &options) {
+      return Frame {
+        img: previous.img,
+        markers: markers,
+      };
+    }
+  }
+  Frame {
+    img: draw_markers(&markers, &viewport, 1, &options),
+    markers: markers,
+  }
+}
+
+// Accepts either a full RFC 3339 timestamp or a plain YYYY-MM-DD date, which
+// is taken to mean midnight UTC.
+fn parse_datetime(s: &str) -> Result<DateTime<UTC>, String> {
+  DateTime::parse_from_rfc3339(s)
+    .map(|datetime| datetime.with_timezone(&UTC))
+    .or_else(|_| {
+      NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| UTC.from_utc_date(&date).and_hms(0, 0, 0))
+    })
+    .map_err(|e| format!("invalid date {:?}: {}", s, e))
+}
+
+// A field that can be exported to gridded formats.
+struct ExportField {
+  name: &'static str,
+  standard_name: &'static str,
+  units: &'static str,
+  mode: DrawMode,
+}
+
+const EXPORT_FIELDS: &'static [ExportField] = &[
+  ExportField {
+    name: "air_temperature",
+    standard_name: "air_temperature",
+    units: "degC",
+    mode: DrawMode::Temperature,
+  },
+];
+
+const EXPORT_FILL_VALUE: f32 = -9999.0;
+
+// The mean of every station's values within the window, skipping stations
+// without any.
+fn window_means(stations: &[WeatherStation],
+                start_time: DateTime<UTC>,
+                end_time: DateTime<UTC>,
+                mode: DrawMode)
+                -> Vec<grid::Sample> {
+  stations.iter()
+    .filter_map(|station| {
+      let values = measurements_in(&station.measurements, start_time, end_time)
+        .iter()
+        .filter_map(|m| mode.value(m))
+        .collect::<Vec<_>>();
+      if values.is_empty() {
+        return None;
+      }
+      Some(grid::Sample {
+        latitude: station.latitude,
+        longitude: station.longitude,
+        value: values.iter().sum::<f32>() / values.len() as f32,
+      })
+    })
+    .collect()
+}
+
+// Interpolates the window mean of every export field onto a regular lat/lon
+// grid and writes it as a CF-compliant NetCDF file.
+fn export_netcdf(stations: &Vec<WeatherStation>,
+                 start_time: DateTime<UTC>,
+                 end_time: DateTime<UTC>,
+                 resolution: f32,
+                 max_distance_km: f32,
+                 min_neighbors: usize,
+                 path: &path::Path)
+                 -> Result<(), io::Error> {
+  let latitudes = grid::axis(-90.0, 90.0, resolution);
+  let longitudes = grid::axis(-180.0, 180.0, resolution);
+  let hours_since_epoch = |t: DateTime<UTC>| t.timestamp() as f32 / 3600.0;
+
+  let text = |s: &str| netcdf::Attribute::Text(String::from(s));
+  let mut file = netcdf::File {
+    dimensions: vec![(String::from("time"), 1),
+                     (String::from("lat"), latitudes.len()),
+                     (String::from("lon"), longitudes.len())],
+    attributes: vec![(String::from("Conventions"), text("CF-1.6")),
+                     (String::from("source"),
+                      text("NOAA Integrated Surface Database")),
+                     (String::from("time_coverage_start"),
+                      text(&start_time.to_rfc3339())),
+                     (String::from("time_coverage_end"),
+                      text(&end_time.to_rfc3339()))],
+    variables: vec![netcdf::Variable {
+                      name: String::from("time"),
+                      dimensions: vec![0],
+                      attributes: vec![(String::from("standard_name"),
+                                        text("time")),
+                                       (String::from("units"),
+                                        text("hours since 1970-01-01 \
+                                              00:00:00"))],
+                      data: vec![(hours_since_epoch(start_time) +
+                                  hours_since_epoch(end_time)) /
+                                 2.0],
+                    },
+                    netcdf::Variable {
+                      name: String::from("lat"),
+                      dimensions: vec![1],
+                      attributes: vec![(String::from("standard_name"),
+                                        text("latitude")),
+                                       (String::from("units"),
+                                        text("degrees_north"))],
+                      data: latitudes.clone(),
+                    },
+                    netcdf::Variable {
+                      name: String::from("lon"),
+                      dimensions: vec![2],
+                      attributes: vec![(String::from("standard_name"),
+                                        text("longitude")),
+                                       (String::from("units"),
+                                        text("degrees_east"))],
+                      data: longitudes.clone(),
+                    }],
+  };
+
+  for field in EXPORT_FIELDS {
+    let samples = window_means(stations, start_time, end_time, field.mode);
+    let values = grid::interpolate(&samples,
+                                   &latitudes,
+                                   &longitudes,
+                                   max_distance_km,
+                                   min_neighbors);
+    file.variables.push(netcdf::Variable {
+      name: String::from(field.name),
+      dimensions: vec![0, 1, 2],
+      attributes: vec![(String::from("standard_name"),
+                        text(field.standard_name)),
+                       (String::from("units"), text(field.units)),
+                       (String::from("_FillValue"),
+                        netcdf::Attribute::Float(vec![EXPORT_FILL_VALUE]))],
+      data: values.iter().map(|v| v.unwrap_or(EXPORT_FILL_VALUE)).collect(),
+    });
+  }
+
+  let mut f = io::BufWriter::new(try!(fs::File::create(path)));
+  file.write(&mut f)
+}
+
+fn coordinates_to_degrees(zoom: u32, x: u32, y: u32) -> (f32, f32) {
+  let n = 2f32.powi(zoom as i32);
+  let longitude = (x as f32) / n * 360.0 - 180.0;
+  let latitude = (consts::PI as f32 * (1.0 - 2.0 * (y as f32) / n))
+    .sinh()
+    .atan()
+    .to_degrees();
+  (longitude, latitude)
+}
+
+#[get("/")]
+fn index() -> rocket_contrib::Template {
+  let context = collections::HashMap::<&str, &str>::new();
+  rocket_contrib::Template::render("index", &context)
+}
+
+// Directory the /static route serves from.
+struct StaticDir(path::PathBuf);
+
+fn static_content_type(extension: &str) -> rocket::http::ContentType {
+  let (top, sub) = match extension {
+    "css" => ("text", "css"),
+    "html" => ("text", "html"),
+    "js" => ("application", "javascript"),
+    "json" => ("application", "json"),
+    "png" => ("image", "png"),
+    "svg" => ("image", "svg+xml"),
+    _ => ("application", "octet-stream"),
+  };
+  rocket::http::ContentType::new(top, sub)
+}
+
+// The file `filename` names in the static directory, or None if the name
+// could escape it.
+fn static_path(static_dir: &path::Path,
+               filename: &str)
+               -> Option<path::PathBuf> {
+  if filename.contains("..") || filename.contains("/") ||
+     filename.contains("\\") {
+    return None;
+  }
+  Some(static_dir.join(filename))
+}
+
+#[get("/static/<filename>")]
+fn static_file<'a>(filename: &str,
+                   static_dir: rocket::State<StaticDir>)
+                   -> Result<rocket::Response<'a>, Failure> {
+  let path = try!(static_path(&static_dir.0, filename)
+    .ok_or(Failure(Status::BadRequest)));
+  let file = try!(fs::File::open(&path).map_err(|_| Failure(Status::NotFound)));
+  let extension = path.extension()
+    .and_then(|e| e.to_str())
+    .unwrap_or("")
+    .to_lowercase();
+  rocket::Response::build()
+    .header(static_content_type(&extension))
+    .sized_body(file)
+    .ok()
+}
+
+fn bad_request<'a>(message: String)
+                   -> Result<rocket::Response<'a>, io::Error> {
+  rocket::Response::build()
+    .status(Status::BadRequest)
+    .sized_body(io::Cursor::new(message))
+    .ok()
+}
+
+#[derive(FromForm, Default)]
+struct TileParams {
+  mode: Option<String>,
+  // The hue and brightness fields of mode=bivariate.
+  a: Option<String>,
+  b: Option<String>,
+  // Colors by this field instead of a mode.
+  field: Option<String>,
+  // first (default) or interp, which requires `at`.
+  select: Option<String>,
+  at: Option<String>,
+  // xyz (default) or tms, which numbers rows from the bottom.
+  scheme: Option<String>,
+  marker: Option<String>,
+  // Smoothing window in hours.
+  smooth: Option<f32>,
+  // Temperature unit of values in JSON responses, c (default) or f.
+  unit: Option<String>,
+  // Wind speed unit of values in JSON responses, ms (default), kn or mph.
+  wind_unit: Option<String>,
+  // Background color as RRGGBB.
+  bg: Option<String>,
+  // 24 (default) for RGB or 8idx for indexed color where possible.
+  depth: Option<String>,
+  // Select measurements by the stations' local solar time instead of UTC.
+  localtime: Option<bool>,
+  // Only draw measurements from this many hours before now.
+  freshness: Option<f32>,
+  // Ring stations whose value is beyond the color ramp.
+  clip_markers: Option<bool>,
+  // Include measurements exactly at the end of the time window.
+  inclusive_end: Option<bool>,
+  // Draw coastlines under the stations.
+  basemap: Option<bool>,
+  // Fill in stations without a value from neighbors within this many km.
+  spatial_fill: Option<f32>,
+  // Only draw measurements within hour_window (default 1) hours of this
+  // hour of the day.
+  hour: Option<u32>,
+  hour_window: Option<f32>,
+  // Antialias wind barbs.
+  aa: Option<bool>,
+  // Merge the stations within cells of this many pixels, weighting them
+  // equally (default) or by their measurement counts.
+  cluster: Option<u32>,
+  cluster_weight: Option<String>,
+  // Kernel radius in pixels and shape, box or gaussian, of mode=density.
+  density_radius: Option<u32>,
+  density_kernel: Option<String>,
+  // Reduce temperatures to sea level, and whether to keep (default) or skip
+  // stations without an elevation.
+  mslt: Option<bool>,
+  mslt_missing: Option<String>,
+  // Outline color of the markers as RRGGBB.
+  outline: Option<String>,
+  // Exponent for the normalized value before it is colored (default 1).
+  gamma: Option<f32>,
+  // Marker colors for calm and variable wind as RRGGBB.
+  calm_color: Option<String>,
+  variable_color: Option<String>,
+}
+
+impl TileParams {
+  // Applies the request's overrides to the server-wide defaults.
+  fn render_options(&self,
+                    defaults: &RenderOptions)
+                    -> Result<RenderOptions, String> {
+    let mut options = *defaults;
+    if let Some(ref field) = self.field {
+      if self.mode.is_some() {
+        return Err(String::from("mode and field can't be used together"));
+      }
+      options.mode = DrawMode::Scalar(try!(Field::parse(field)));
+    }
+    if let Some(ref mode) = self.mode {
+      options.mode = match (mode.as_str(), &self.a, &self.b) {
+        ("bivariate", &Some(ref a), &Some(ref b)) => {
+          DrawMode::Bivariate {
+            a: try!(Field::parse(a)),
+            b: try!(Field::parse(b)),
+          }
+        }
+        _ => try!(DrawMode::parse(mode)),
+      };
+      if options.mode == DrawMode::Anomaly && options.baseline.is_none() {
+        return Err(String::from("mode=anomaly needs the server to be \
+                                 started with --baseline"));
+      }
+    }
+    if let Some(ref marker) = self.marker {
+      options.marker = try!(MarkerShape::parse(marker));
+    }
+    if let Some(ref bg) = self.bg {
+      options.background = try!(parse_color(bg));
+    }
+    if let Some(ref outline) = self.outline {
+      options.outline = Some(try!(parse_color(outline)));
+    }
+    if let Some(ref calm) = self.calm_color {
+      options.calm_color = try!(parse_color(calm));
+    }
+    if let Some(ref variable) = self.variable_color {
+      options.variable_color = try!(parse_color(variable));
+    }
+    options.indexed = match self.depth.as_ref().map(|d| d.as_str()) {
+      None => options.indexed,
+      Some("24") => false,
+      Some("8idx") => true,
+      Some(depth) => return Err(format!("unknown color depth {:?}", depth)),
+    };
+    if let Some(gamma) = self.gamma {
+      if !(gamma > 0.0) {
+        return Err(format!("gamma must be positive, got {}", gamma));
+      }
+      options.gamma = gamma;
+    }
+    if let Some(hours) = self.smooth {
+      if !(hours > 0.0) {
+        return Err(format!("smoothing window must be positive, got {}", hours));
+      }
+      options.smooth = Some(time::Duration::seconds((hours * 3600.0) as i64));
+    }
+    options.selection = match self.select.as_ref().map(|s| s.as_str()) {
+      None | Some("first") => Selection::First,
+      Some("interp") => {
+        match self.at {
+          Some(ref at) => Selection::Interpolate(try!(parse_datetime(at))),
+          None => return Err(String::from("select=interp requires at")),
+        }
+      }
+      Some(select) => return Err(format!("unknown selection {:?}", select)),
+    };
+    options.localtime = self.localtime.unwrap_or(options.localtime);
+    options.clip_markers = self.clip_markers.unwrap_or(options.clip_markers);
+    options.inclusive_end =
+      self.inclusive_end.unwrap_or(options.inclusive_end);
+    options.basemap = self.basemap.unwrap_or(options.basemap);
+    options.antialias = self.aa.unwrap_or(options.antialias);
+    options.sea_level = match (self.mslt,
+                               self.mslt_missing.as_ref().map(|s| s.as_str())) {
+      (None, None) => options.sea_level,
+      (Some(false), None) => SeaLevel::Off,
+      (Some(true), None) |
+      (Some(true), Some("keep")) => SeaLevel::KeepMissing,
+      (Some(true), Some("skip")) => SeaLevel::SkipMissing,
+      (Some(true), Some(missing)) => {
+        return Err(format!("unknown mslt_missing {:?}", missing))
+      }
+      (_, Some(_)) => return Err(String::from("mslt_missing requires mslt")),
+    };
+    if let Some(km) = self.spatial_fill {
+      if !(km > 0.0 && km <= MAX_SPATIAL_FILL_KM) {
+        return Err(format!("spatial_fill radius must be positive and at \
+                            most {} km, got {}",
+                           MAX_SPATIAL_FILL_KM,
+                           km));
+      }
+      options.spatial_fill = Some(km);
+    }
+    options.hours = match (self.hour, self.hour_window) {
+      (Some(hour), _) if hour >= 24 => {
+        return Err(format!("hour must be 0 to 23, got {}", hour))
+      }
+      (_, Some(window)) if !(window >= 0.0) => {
+        return Err(format!("hour_window must not be negative, got {}",
+                           window))
+      }
+      (Some(hour), window) => {
+        Some(HourFilter {
+          hour: hour as f32,
+          window: window.unwrap_or(1.0),
+        })
+      }
+      (None, Some(_)) => {
+        return Err(String::from("hour_window requires hour"));
+      }
+      (None, None) => options.hours,
+    };
+    if options.hours.is_some() && options.selection != Selection::First {
+      // An interpolated instant has a single time of day already.
+      return Err(String::from("hour can't be used with select=interp"));
+    }
+    options.cluster = match (self.cluster, &self.cluster_weight) {
+      (Some(0), _) => {
+        return Err(String::from("cluster cells must be at least 1 pixel"))
+      }
+      (Some(cell), weight) => {
+        Some(Clustering {
+          cell: cell,
+          weight: match *weight {
+            Some(ref weight) => try!(ClusterWeight::parse(weight)),
+            None => ClusterWeight::Equal,
+          },
+        })
+      }
+      (None, &Some(_)) => {
+        return Err(String::from("cluster_weight requires cluster"));
+      }
+      (None, &None) => options.cluster,
+    };
+    if options.cluster.is_some() &&
+       (options.mode.ramp().is_none() || options.mode == DrawMode::Barbs) {
+      return Err(String::from("cluster needs a mode colored along a ramp"));
+    }
+    if (self.density_radius.is_some() || self.density_kernel.is_some()) &&
+       options.mode != DrawMode::Density {
+      return Err(String::from("density_radius and density_kernel require \
+                               mode=density"));
+    }
+    if let Some(radius) = self.density_radius {
+      if radius > MAX_DENSITY_RADIUS {
+        return Err(format!("density_radius must be at most {}, got {}",
+                           MAX_DENSITY_RADIUS,
+                           radius));
+      }
+      options.density.radius = radius;
+    }
+    if let Some(ref shape) = self.density_kernel {
+      options.density.shape = try!(KernelShape::parse(shape));
+    }
+    if let Some(hours) = self.freshness {
+      if !(hours > 0.0) {
+        return Err(format!("freshness must be positive, got {}", hours));
+      }
+      if options.window.is_some() {
+        return Err(String::from("freshness can't be used with a date"));
+      }
+      // Whole minutes, so that tiles stay cacheable for a while.
+      let now = options.clock.now().with_second(0).unwrap();
+      let age = time::Duration::seconds((hours * 3600.0) as i64);
+      options.window = Some((now - age, now));
+    }
+    Ok(options)
+  }
+
+  // The XYZ row of tile row `y`, as coordinates_to_degrees expects it. Every
+  // tile route goes through here, so it also rejects tiles check_tile does.
+  fn xyz_y(&self, zoom: u32, x: u32, y: u32) -> Result<u32, String> {
+    try!(check_tile(zoom, x, y));
+    match self.scheme.as_ref().map(|s| s.as_str()) {
+      None | Some("xyz") => Ok(y),
+      Some("tms") => Ok((1u32 << zoom) - 1 - y),
+      Some(scheme) => Err(format!("unknown tile scheme {:?}", scheme)),
+    }
+  }
+}
+
+// Width and height of map tiles in pixels.
+const TILE_SIZE: u32 = 256;
+
+// Largest ?density_radius, which bounds the cost of the blur.
+const MAX_DENSITY_RADIUS: u32 = 64;
+
+// Largest ?spatial_fill radius; beyond it the neighbors of a tile are a good
+// part of the globe, and interpolating from that far is meaningless anyway.
+const MAX_SPATIAL_FILL_KM: f32 = 500.0;
+
+// Deepest zoom served; far beyond where stations stop sharing tiles, and
+// shallow enough that even the tiles at the mercator cut-off still span a
+// latitude range in f32.
+const MAX_ZOOM: u32 = 18;
+
+// Rejects zooms beyond MAX_ZOOM and tiles outside the 2^zoom by 2^zoom grid,
+// in either numbering.
+fn check_tile(zoom: u32, x: u32, y: u32) -> Result<(), String> {
+  if zoom > MAX_ZOOM {
+    return Err(format!("zoom must be at most {}, got {}", MAX_ZOOM, zoom));
+  }
+  let n = 1u32 << zoom;
+  if x >= n || y >= n {
+    return Err(format!("tile {}/{} out of range at zoom {}", x, y, zoom));
+  }
+  Ok(())
+}
+
+// None for tiles check_tile rejects. Every other tile spans a latitude range
+// within the cut-off.
+fn tile_viewport(zoom: u32, x: u32, y: u32) -> Option<Viewport> {
+  if check_tile(zoom, x, y).is_err() {
+    return None;
+  }
+  let (long_min, lat_top) = coordinates_to_degrees(zoom, x, y);
+  let (long_max, lat_bot) = coordinates_to_degrees(zoom, x + 1, y + 1);
+  Viewport::new(long_min, long_max, lat_bot, lat_top, TILE_SIZE, TILE_SIZE)
+}
+
+// The stations on a tile, from the store's precomputed positions if it has
+// them for this zoom, and with options.spatial_fill the samples to fill in
+// those without a value from. Neighbors may be up to the fill radius beyond
+// the tile, with the longitude margin sized for the tile edge furthest from
+// the equator.
+fn tile_stations<'a>(store: &'a store::StationStore,
+                     zoom: u32,
+                     x: u32,
+                     y: u32,
+                     viewport: &Viewport,
+                     options: &RenderOptions)
+                     -> (Vec<Placed<'a>>, Vec<grid::Sample>) {
+  let placed = match store.tile(zoom, x, y) {
+    Some(placed) => placed,
+    None => {
+      position_stations(store.near(viewport.longitude_min,
+                                   viewport.longitude_max,
+                                   viewport.latitude_min,
+                                   viewport.latitude_max),
+                        viewport)
+    }
+  };
+  let neighbors = match options.spatial_fill {
+    Some(_) => {
+      let (longitude_min, longitude_max, latitude_min, latitude_max) =
+        tile_area(viewport, options);
+      let (start, end) = options.window();
+      neighbor_samples(store.near(longitude_min,
+                                  longitude_max,
+                                  latitude_min,
+                                  latitude_max),
+                       start,
+                       end,
+                       options)
+    }
+    None => vec![],
+  };
+  (placed, neighbors)
+}
+
+// The bounds of the stations a tile is drawn from: the viewport, widened by
+// options.spatial_fill to take in the neighbors that fill in empty stations.
+fn tile_area(viewport: &Viewport,
+             options: &RenderOptions)
+             -> (f32, f32, f32, f32) {
+  let (long_margin, lat_margin) = match options.spatial_fill {
+    Some(km) => {
+      let lat_margin = km / grid::EARTH_RADIUS_KM.to_radians();
+      let latitude =
+        viewport.latitude_min.abs().max(viewport.latitude_max.abs());
+      // Near the poles a few hundred km span every longitude.
+      ((lat_margin / latitude.to_radians().cos()).min(180.0), lat_margin)
+    }
+    None => (0.0, 0.0),
+  };
+  (viewport.longitude_min - long_margin,
+   viewport.longitude_max + long_margin,
+   viewport.latitude_min - lat_margin,
+   viewport.latitude_max + lat_margin)
+}
+
+// Calls f with the stations source has for the tile, see tile_area.
+fn with_tile_stations<T, F>(source: &source::StationSource,
+                            zoom: u32,
+                            x: u32,
+                            y: u32,
+                            options: &RenderOptions,
+                            f: F)
+                            -> Result<T, io::Error>
+  where F: FnOnce(&store::StationStore) -> T
+{
+  // draw_tile draws tiles outside the grid empty, whatever the stations.
+  let (longitude_min, longitude_max, latitude_min, latitude_max) =
+    match tile_viewport(zoom, x, y) {
+      Some(viewport) => tile_area(&viewport, options),
+      None => (0.0, 0.0, 0.0, 0.0),
+    };
+  let (start, end) = options.window();
+  let mut f = Some(f);
+  let mut result = None;
+  try!(source.with_stations(longitude_min,
+                            longitude_max,
+                            latitude_min,
+                            latitude_max,
+                            start,
+                            end,
+                            &mut |store| {
+                              result = f.take().map(|f| f(store));
+                            }));
+  Ok(result.unwrap())
+}
+
+fn draw_tile(store: &store::StationStore,
+             zoom: u32,
+             x: u32,
+             y: u32,
+             options: &RenderOptions)
+             -> (image::RgbImage, DrawStats) {
+  let dot_radius = if zoom < 5 { 1 } else { zoom - 3 };
+
+  let (start, end) = options.window();
+  let viewport = match tile_viewport(zoom, x, y) {
+    Some(viewport) => viewport,
+    // The routes reject these before drawing.
+    None => {
+      let img = image::ImageBuffer::from_pixel(TILE_SIZE,
+                                               TILE_SIZE,
+                                               options.background);
+      return (img, DrawStats::default());
+    }
+  };
+  let (placed, neighbors) =
+    tile_stations(store, zoom, x, y, &viewport, options);
+  let (mut img, stats) = draw_stations(&placed,
+                                       &neighbors,
+                                       &viewport,
+                                       dot_radius,
+                                       start,
+                                       end,
+                                       options);
+  if let Some(attribution) = options.attribution {
+    // Zooms are at most MAX_ZOOM, so the row count fits.
+    if attribution.every_tile || y == (1u32 << zoom) - 1 {
+      draw_attribution(&mut img, attribution.text);
+    }
+  }
+
+  // Debug borders:
+  // for i in 0..TILE_SIZE {
+  //   img.put_pixel(i, 0, image::Rgb([0u8, 255u8, 0u8]));
+  //   img.put_pixel(i, TILE_SIZE - 1, image::Rgb([0u8, 255u8, 0u8]));
+  //   img.put_pixel(0, i, image::Rgb([0u8, 255u8, 0u8]));
+  //   img.put_pixel(TILE_SIZE - 1, i, image::Rgb([0u8, 255u8, 0u8]));
+  // }
+  // img.put_pixel(TILE_SIZE / 2, TILE_SIZE / 2,
+  //               image::Rgb([255u8, 0u8, 0u8]));
+
+  (img, stats)
+}
+
+// The tile as an SVG document of one circle per station, which unlike the
+// PNG stays crisp at any print size.
+fn draw_tile_svg(store: &store::StationStore,
+                 zoom: u32,
+                 x: u32,
+                 y: u32,
+                 options: &RenderOptions)
+                 -> (String, DrawStats) {
+  let dot_radius = if zoom < 5 { 1 } else { zoom - 3 };
+
+  let (start, end) = options.window();
+  let (placed, neighbors) = match tile_viewport(zoom, x, y) {
+    Some(viewport) => tile_stations(store, zoom, x, y, &viewport, options),
+    None => (vec![], vec![]),
+  };
+  let (markers, stats) =
+    place_stations(&placed, &neighbors, start, end, options);
+  let svg = svg::render(TILE_SIZE,
+                        TILE_SIZE,
+                        options.background,
+                        &markers,
+                        dot_radius as f32 / 2.0,
+                        options.mode == DrawMode::Barbs,
+                        options.outline);
+  (svg, stats)
+}
+
+fn encode_png(img: image::RgbImage,
+              indexed: bool)
+              -> Result<Vec<u8>, io::Error> {
+  let (width, height) = img.dimensions();
+  if indexed {
+    if let Some((palette, indices)) = indexed_png::index_colors(&img) {
+      return indexed_png::encode(width, height, &palette, &indices);
+    }
+  }
+  let mut buf = Vec::<u8>::new();
+  {
+    let encoder = image::png::PNGEncoder::new(&mut buf);
+    try!(encoder.encode(&img.into_raw(),
+                        width,
+                        height,
+                        image::ColorType::RGB(8)));
+  }
+  Ok(buf)
+}
+
+// Draws and encodes a tile; shared by the tile route and the pre-renderer.
+fn render_tile_png(source: &source::StationSource,
+                   zoom: u32,
+                   x: u32,
+                   y: u32,
+                   options: &RenderOptions)
+                   -> Result<cache::Tile, io::Error> {
+  let draw =
+    |store: &store::StationStore| draw_tile(store, zoom, x, y, options);
+  let (img, stats) =
+    try!(with_tile_stations(source, zoom, x, y, options, draw));
+  Ok(cache::Tile {
+    png: try!(encode_png(img, options.indexed)),
+    stations_drawn: stats.stations_drawn,
+  })
+}
+
+// Renders every non-empty tile up to max_zoom into dir/z/x/y.png and returns
+// how many were written. The children of an empty tile are empty as well, so
+// they are never rendered.
+fn prerender_tiles(store: store::SharedStore,
+                   options: RenderOptions,
+                   max_zoom: u32,
+                   dir: &path::Path,
+                   n_threads: usize)
+                   -> Result<usize, io::Error> {
+  let pool = threadpool::ThreadPool::new(n_threads);
+  let mut level = vec![(0u32, 0u32)];
+  let mut num_written = 0;
+
+  for zoom in 0..max_zoom + 1 {
+    let (tx, rx) = sync::mpsc::channel();
+    for &(x, y) in &level {
+      let tx = tx.clone();
+      let store = store.clone();
+      let tile_dir = dir.join(zoom.to_string()).join(x.to_string());
+      pool.execute(move || {
+        let result = render_tile_png(store.read().unwrap().source(),
+                                     zoom,
+                                     x,
+                                     y,
+                                     &options)
+          .and_then(|tile| {
+            if tile.stations_drawn == 0 {
+              return Ok(false);
+            }
+            try!(fs::create_dir_all(&tile_dir));
+            let mut f =
+              try!(fs::File::create(tile_dir.join(format!("{}.png", y))));
+            try!(f.write_all(&tile.png));
+            Ok(true)
+          });
+        tx.send((x, y, result)).unwrap();
+      });
+    }
+
+    let mut next_level = vec![];
+    for (x, y, result) in rx.iter().take(level.len()) {
+      if try!(result) {
+        num_written += 1;
+        next_level.extend_from_slice(&[(2 * x, 2 * y),
+                                       (2 * x + 1, 2 * y),
+                                       (2 * x, 2 * y + 1),
+                                       (2 * x + 1, 2 * y + 1)]);
+      }
+    }
+    level = next_level;
+  }
+
+  Ok(num_written)
+}
+
+fn tile_response<'a>(tile: cache::Tile)
+                     -> Result<rocket::Response<'a>, io::Error> {
+  rocket::Response::build()
+    .raw_header("X-Stations-Drawn", tile.stations_drawn.to_string())
+    .sized_body(io::Cursor::new(tile.png))
+    .ok()
+}
+
+enum RenderError {
+  BadRequest(String),
+  Io(io::Error),
+}
+
+impl From<io::Error> for RenderError {
+  fn from(error: io::Error) -> RenderError {
+    RenderError::Io(error)
+  }
+}
+
+fn error_response<'a>(error: RenderError)
+                      -> Result<rocket::Response<'a>, io::Error> {
+  match error {
+    RenderError::BadRequest(message) => bad_request(message),
+    RenderError::Io(error) => Err(error),
+  }
+}
+
+// Resolves the request parameters, then serves the tile from the cache or
+// renders it.
+fn get_tile(zoom: u32,
+            x: u32,
+            y: u32,
+            params: &TileParams,
+            store: &store::StationStore,
+            defaults: &RenderOptions,
+            tile_cache: &cache::TileCache)
+            -> Result<cache::Tile, RenderError> {
+  let options = try!(params.render_options(defaults)
+    .map_err(RenderError::BadRequest));
+  let y = try!(params.xyz_y(zoom, x, y).map_err(RenderError::BadRequest));
+
+  let key = cache::TileKey {
+    zoom: zoom,
+    x: x,
+    y: y,
+    version: store.version,
+    params: format!("{:?}", options),
+  };
+  if let Some(tile) = tile_cache.get(&key) {
+    return Ok(tile);
+  }
+
+  let tile = try!(render_tile_png(store.source(), zoom, x, y, &options));
+  tile_cache.insert(key, tile.clone());
+  Ok(tile)
+}
+
+#[get("/api/map/<zoom>/<x>/<y>/tile.png?<params>")]
+fn map_tile<'a>(zoom: u32,
+                x: u32,
+                y: u32,
+                params: TileParams,
+                stations: rocket::State<store::SharedStore>,
+                defaults: rocket::State<RenderOptions>,
+                tile_cache: rocket::State<cache::TileCache>,
+                _rate_limit: ratelimit::TileRateLimit)
+                -> Result<rocket::Response<'a>, io::Error> {
+  match get_tile(zoom,
+                 x,
+                 y,
+                 &params,
+                 &stations.read().unwrap(),
+                 defaults.inner(),
+                 tile_cache.inner()) {
+    Ok(tile) => tile_response(tile),
+    Err(error) => error_response(error),
+  }
+}
+
+#[get("/api/map/<zoom>/<x>/<y>/tile.png", rank = 2)]
+fn map_tile_default<'a>(zoom: u32,
+                        x: u32,
+                        y: u32,
+                        stations: rocket::State<store::SharedStore>,
+                        defaults: rocket::State<RenderOptions>,
+                        tile_cache: rocket::State<cache::TileCache>,
+                        rate_limit: ratelimit::TileRateLimit)
+                        -> Result<rocket::Response<'a>, io::Error> {
+  map_tile(zoom,
+           x,
+           y,
+           TileParams::default(),
+           stations,
+           defaults,
+           tile_cache,
+           rate_limit)
+}
+
+// The 24 hour UTC window of a YYYY-MM-DD date.
+fn day_window(date: &str)
+              -> Result<(DateTime<UTC>, DateTime<UTC>), String> {
+  let date = try!(NaiveDate::parse_from_str(date, "%Y-%m-%d")
+    .map_err(|e| format!("invalid date {:?}: {}", date, e)));
+  let start = UTC.from_utc_date(&date).and_hms(0, 0, 0);
+  Ok((start, start + time::Duration::days(1)))
+}
+
+// The tile drawn from only one day's measurements, for linking to days
+// without spelling out timestamps.
+#[get("/api/map/<date>/<zoom>/<x>/<y>/tile.png?<params>")]
+fn map_tile_on<'a>(date: String,
+                   zoom: u32,
+                   x: u32,
+                   y: u32,
+                   params: TileParams,
+                   stations: rocket::State<store::SharedStore>,
+                   defaults: rocket::State<RenderOptions>,
+                   tile_cache: rocket::State<cache::TileCache>,
+                   _rate_limit: ratelimit::TileRateLimit)
+                   -> Result<rocket::Response<'a>, io::Error> {
+  let mut options = *defaults.inner();
+  options.window = match day_window(&date) {
+    Ok(window) => Some(window),
+    Err(message) => return bad_request(message),
+  };
+  match get_tile(zoom,
+                 x,
+                 y,
+                 &params,
+                 &stations.read().unwrap(),
+                 &options,
+                 tile_cache.inner()) {
+    Ok(tile) => tile_response(tile),
+    Err(error) => error_response(error),
+  }
+}
+
+#[get("/api/map/<date>/<zoom>/<x>/<y>/tile.png", rank = 2)]
+fn map_tile_on_default<'a>(date: String,
+                           zoom: u32,
+                           x: u32,
+                           y: u32,
+                           stations: rocket::State<store::SharedStore>,
+                           defaults: rocket::State<RenderOptions>,
+                           tile_cache: rocket::State<cache::TileCache>,
+                           rate_limit: ratelimit::TileRateLimit)
+                           -> Result<rocket::Response<'a>, io::Error> {
+  map_tile_on(date,
+              zoom,
+              x,
+              y,
+              TileParams::default(),
+              stations,
+              defaults,
+              tile_cache,
+              rate_limit)
+}
+
+// The tile as a data URI, for inlining into HTML without a second request.
+#[get("/api/map/<zoom>/<x>/<y>/tile.txt?<params>")]
+fn map_tile_data_uri<'a>(zoom: u32,
+                         x: u32,
+                         y: u32,
+                         params: TileParams,
+                         stations: rocket::State<store::SharedStore>,
+                         defaults: rocket::State<RenderOptions>,
+                         tile_cache: rocket::State<cache::TileCache>,
+                         _rate_limit: ratelimit::TileRateLimit)
+                         -> Result<rocket::Response<'a>, io::Error> {
+  match get_tile(zoom,
+                 x,
+                 y,
+                 &params,
+                 &stations.read().unwrap(),
+                 defaults.inner(),
+                 tile_cache.inner()) {
+    Ok(tile) => {
+      let uri = format!("data:image/png;base64,{}", base64::encode(&tile.png));
+      rocket::Response::build()
+        .header(rocket::http::ContentType::Plain)
+        .raw_header("X-Stations-Drawn", tile.stations_drawn.to_string())
+        .sized_body(io::Cursor::new(uri))
+        .ok()
+    }
+    Err(error) => error_response(error),
+  }
+}
+
+#[get("/api/map/<zoom>/<x>/<y>/tile.txt", rank = 2)]
+fn map_tile_data_uri_default<'a>(zoom: u32,
+                                 x: u32,
+                                 y: u32,
+                                 stations: rocket::State<store::SharedStore>,
+                                 defaults: rocket::State<RenderOptions>,
+                                 tile_cache: rocket::State<cache::TileCache>,
+                                 rate_limit: ratelimit::TileRateLimit)
+                                 -> Result<rocket::Response<'a>, io::Error> {
+  map_tile_data_uri(zoom,
+                    x,
+                    y,
+                    TileParams::default(),
+                    stations,
+                    defaults,
+                    tile_cache,
+                    rate_limit)
+}
+
+#[get("/api/map/<zoom>/<x>/<y>/tile.svg?<params>")]
+fn map_tile_svg<'a>(zoom: u32,
+                    x: u32,
+                    y: u32,
+                    params: TileParams,
+                    stations: rocket::State<store::SharedStore>,
+                    defaults: rocket::State<RenderOptions>,
+                    _rate_limit: ratelimit::TileRateLimit)
+                    -> Result<rocket::Response<'a>, io::Error> {
+  let options = match params.render_options(defaults.inner()) {
+    Ok(options) => options,
+    Err(message) => return bad_request(message),
+  };
+  let y = match params.xyz_y(zoom, x, y) {
+    Ok(y) => y,
+    Err(message) => return bad_request(message),
+  };
+  if options.mode == DrawMode::Density {
+    return bad_request(String::from("mode=density is only drawn as PNG"));
+  }
+  let draw =
+    |store: &store::StationStore| draw_tile_svg(store, zoom, x, y, &options);
+  let (svg, stats) = try!(with_tile_stations(stations.read().unwrap().source(),
+                                             zoom,
+                                             x,
+                                             y,
+                                             &options,
+                                             draw));
+  rocket::Response::build()
+    .header(rocket::http::ContentType::new("image", "svg+xml"))
+    .raw_header("X-Stations-Drawn", stats.stations_drawn.to_string())
+    .sized_body(io::Cursor::new(svg))
+    .ok()
+}
+
+#[get("/api/map/<zoom>/<x>/<y>/tile.svg", rank = 2)]
+fn map_tile_svg_default<'a>(zoom: u32,
+                            x: u32,
+                            y: u32,
+                            stations: rocket::State<store::SharedStore>,
+                            defaults: rocket::State<RenderOptions>,
+                            rate_limit: ratelimit::TileRateLimit)
+                            -> Result<rocket::Response<'a>, io::Error> {
+  map_tile_svg(zoom,
+               x,
+               y,
+               TileParams::default(),
+               stations,
+               defaults,
+               rate_limit)
+}
+
+#[derive(Deserialize)]
+struct TileRequest {
+  zoom: u32,
+  x: u32,
+  y: u32,
+}
+
+// Upper bound on the number of tiles in one /api/tiles request.
+const MAX_BATCH_TILES: usize = 256;
+
+// Renders the tiles of /api/tiles requests, which may be many at once.
+struct RenderPool(sync::Mutex<threadpool::ThreadPool>);
+
+// Frames the PNGs of an /api/tiles response: a little-endian u32 tile count,
+// then a u32 offset from the start of the body and a u32 length per tile,
+// then the PNGs back to back, all in the order they were requested.
+fn frame_tiles(tiles: &[cache::Tile]) -> Result<Vec<u8>, io::Error> {
+  let header_len = 4 + 8 * tiles.len();
+  let mut body = vec![];
+  try!(body.write_u32::<LittleEndian>(tiles.len() as u32));
+  let mut offset = header_len;
+  for tile in tiles {
+    try!(body.write_u32::<LittleEndian>(offset as u32));
+    try!(body.write_u32::<LittleEndian>(tile.png.len() as u32));
+    offset += tile.png.len();
+  }
+  for tile in tiles {
+    body.extend_from_slice(&tile.png);
+  }
+  Ok(body)
+}
+
+// Several tiles with the same parameters in one response, rendered in
+// parallel and framed by frame_tiles.
+#[post("/api/tiles?<params>", data = "<requests>")]
+fn map_tiles<'a>(params: TileParams,
+                 requests: rocket_contrib::JSON<Vec<TileRequest>>,
+                 stations: rocket::State<store::SharedStore>,
+                 defaults: rocket::State<RenderOptions>,
+                 tile_cache: rocket::State<cache::TileCache>,
+                 pool: rocket::State<RenderPool>,
+                 _rate_limit: ratelimit::TileRateLimit)
+                 -> Result<rocket::Response<'a>, io::Error> {
+  let options = match params.render_options(defaults.inner()) {
+    Ok(options) => options,
+    Err(message) => return bad_request(message),
+  };
+  if requests.len() > MAX_BATCH_TILES {
+    return bad_request(format!("at most {} tiles per request, got {}",
+                               MAX_BATCH_TILES,
+                               requests.len()));
+  }
+  let version = stations.read().unwrap().version;
+  let mut keys = vec![];
+  for request in requests.iter() {
+    let y = match params.xyz_y(request.zoom, request.x, request.y) {
+      Ok(y) => y,
+      Err(message) => return bad_request(message),
+    };
+    keys.push(cache::TileKey {
+      zoom: request.zoom,
+      x: request.x,
+      y: y,
+      version: version,
+      params: format!("{:?}", options),
+    });
+  }
+
+  let mut tiles =
+    keys.iter().map(|key| tile_cache.get(key)).collect::<Vec<_>>();
+  let (tx, rx) = sync::mpsc::channel();
+  {
+    let pool = pool.0.lock().unwrap();
+    for (i, key) in keys.iter().enumerate() {
+      if tiles[i].is_some() {
+        continue;
+      }
+      let tx = tx.clone();
+      let store = stations.inner().clone();
+      let (zoom, x, y) = (key.zoom, key.x, key.y);
+      pool.execute(move || {
+        let tile =
+          render_tile_png(store.read().unwrap().source(), zoom, x, y, &options);
+        tx.send((i, tile)).unwrap();
+      });
+    }
+  }
+  // Once the workers are done, so that a panicking one ends the loop.
+  drop(tx);
+  for (i, tile) in rx.iter() {
+    let tile = try!(tile);
+    tile_cache.insert(keys[i].clone(), tile.clone());
+    tiles[i] = Some(tile);
+  }
+
+  if tiles.iter().any(|tile| tile.is_none()) {
+    return Err(io::Error::new(io::ErrorKind::Other,
+                              "rendering a tile failed"));
+  }
+  let tiles = tiles.into_iter().filter_map(|tile| tile).collect::<Vec<_>>();
+  let body = try!(frame_tiles(&tiles));
+  rocket::Response::build()
+    .header(rocket::http::ContentType::Binary)
+    .sized_body(io::Cursor::new(body))
+    .ok()
+}
+
+#[post("/api/tiles", data = "<requests>", rank = 2)]
+fn map_tiles_default<'a>(requests: rocket_contrib::JSON<Vec<TileRequest>>,
+                         stations: rocket::State<store::SharedStore>,
+                         defaults: rocket::State<RenderOptions>,
+                         tile_cache: rocket::State<cache::TileCache>,
+                         pool: rocket::State<RenderPool>,
+                         rate_limit: ratelimit::TileRateLimit)
+                         -> Result<rocket::Response<'a>, io::Error> {
+  map_tiles(TileParams::default(),
+            requests,
+            stations,
+            defaults,
+            tile_cache,
+            pool,
+            rate_limit)
+}
+
+#[derive(Serialize)]
+struct TileStats {
+  stations_drawn: usize,
+  min: Option<f32>,
+  max: Option<f32>,
+  units: units::Units,
+}
+
+#[get("/api/map/<zoom>/<x>/<y>/stats.json?<params>")]
+fn tile_stats(zoom: u32,
+              x: u32,
+              y: u32,
+              params: TileParams,
+              stations: rocket::State<store::SharedStore>,
+              defaults: rocket::State<RenderOptions>,
+              _rate_limit: ratelimit::TileRateLimit)
+              -> Result<JSON<TileStats>, Failure> {
+  let invalid = |_: String| Failure(Status::BadRequest);
+  let options = try!(params.render_options(defaults.inner())
+    .map_err(&invalid));
+  let y = try!(params.xyz_y(zoom, x, y).map_err(&invalid));
+  let unit = try!(units::TemperatureUnit::parse_option(&params.unit)
+    .map_err(&invalid));
+  let wind_unit = try!(units::WindUnit::parse_option(&params.wind_unit)
+    .map_err(&invalid));
+  let draw =
+    |store: &store::StationStore| draw_tile(store, zoom, x, y, &options);
+  let (_, stats) = try!(with_tile_stations(stations.read().unwrap().source(),
+                                           zoom,
+                                           x,
+                                           y,
+                                           &options,
+                                           draw)
+    .map_err(|_| Failure(Status::InternalServerError)));
+  let convert = |v: f32| options.mode.convert(v, unit, wind_unit);
+  Ok(JSON(TileStats {
+    stations_drawn: stats.stations_drawn,
+    min: stats.min.map(&convert),
+    max: stats.max.map(&convert),
+    units: units::Units::new(unit, wind_unit),
+  }))
+}
+
+#[get("/api/map/<zoom>/<x>/<y>/stats.json", rank = 2)]
+fn tile_stats_default(zoom: u32,
+                      x: u32,
+                      y: u32,
+                      stations: rocket::State<store::SharedStore>,
+                      defaults: rocket::State<RenderOptions>,
+                      rate_limit: ratelimit::TileRateLimit)
+                      -> Result<JSON<TileStats>, Failure> {
+  tile_stats(zoom,
+             x,
+             y,
+             TileParams::default(),
+             stations,
+             defaults,
+             rate_limit)
+}
+
+#[derive(FromForm)]
+struct ChangedParams {
+  // StationStore::version the client's copy of the tile was drawn from.
+  since: u64,
+}
+
+#[derive(Serialize)]
+struct TileChanged {
+  changed: bool,
+  version: u64,
+}
+
+// Whether any station drawn on the tile changed after the given version, so
+// clients can tell which of their cached tiles to fetch again.
+#[get("/api/map/<zoom>/<x>/<y>/changed?<params>")]
+fn tile_changed(zoom: u32,
+                x: u32,
+                y: u32,
+                params: ChangedParams,
+                stations: rocket::State<store::SharedStore>)
+                -> Result<JSON<TileChanged>, Failure> {
+  if check_tile(zoom, x, y).is_err() {
+    return Err(Failure(Status::BadRequest));
+  }
+  let store = stations.read().unwrap();
+  Ok(JSON(TileChanged {
+    changed: tile_changed_since(&store, zoom, x, y, params.since),
+    version: store.version,
+  }))
+}
+
+fn tile_changed_since(store: &store::StationStore,
+                      zoom: u32,
+                      x: u32,
+                      y: u32,
+                      version: u64)
+                      -> bool {
+  let placed = store.tile(zoom, x, y).unwrap_or_else(|| {
+    match tile_viewport(zoom, x, y) {
+      Some(viewport) => {
+        position_stations(store.near(viewport.longitude_min,
+                                     viewport.longitude_max,
+                                     viewport.latitude_min,
+                                     viewport.latitude_max),
+                          &viewport)
+      }
+      None => vec![],
+    }
+  });
+  placed.iter().any(|&(station, _, _)| store.changed_since(station, version))
+}
+
+// Upper bounds on the size of timeseries.json responses.
+const MAX_TIMESERIES_STEPS: usize = 500;
+const MAX_TIMESERIES_STATIONS: usize = 200;
+
+#[derive(FromForm)]
+struct TimeseriesParams {
+  start: String,
+  end: String,
+  // Number of evenly spaced times from start to end, both included.
+  steps: usize,
+  unit: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StationTimeseries {
+  usaf: String,
+  wban: String,
+  // One per time, None where the station has no measurement on either side.
+  temperatures: Vec<Option<f32>>,
+}
+
+#[derive(Serialize)]
+struct TileTimeseries {
+  // RFC 3339.
+  times: Vec<String>,
+  stations: Vec<StationTimeseries>,
+  // Whether stations beyond MAX_TIMESERIES_STATIONS were left out.
+  truncated: bool,
+  units: units::Units,
+}
+
+// The air temperature of every station on the tile, interpolated at evenly
+// spaced times, so that clients can scrub through time without fetching a
+// tile per step.
+fn timeseries_of(store: &store::StationStore,
+                 zoom: u32,
+                 x: u32,
+                 y: u32,
+                 params: &TimeseriesParams)
+                 -> Result<TileTimeseries, String> {
+  try!(check_tile(zoom, x, y));
+  let start = try!(parse_datetime(&params.start));
+  let end = try!(parse_datetime(&params.end));
+  let unit = try!(units::TemperatureUnit::parse_option(&params.unit));
+  if start > end {
+    return Err(String::from("start must not be after end"));
+  }
+  if params.steps < 1 || params.steps > MAX_TIMESERIES_STEPS {
+    return Err(format!("steps must be between 1 and {}, got {}",
+                       MAX_TIMESERIES_STEPS,
+                       params.steps));
+  }
+  let step = if params.steps > 1 {
+    (end - start) / (params.steps - 1) as i32
+  } else {
+    time::Duration::zero()
+  };
+  let times = (0..params.steps)
+    .map(|i| start + step * i as i32)
+    .collect::<Vec<_>>();
+
+  let placed = store.tile(zoom, x, y).unwrap_or_else(|| {
+    match tile_viewport(zoom, x, y) {
+      Some(viewport) => {
+        position_stations(store.near(viewport.longitude_min,
+                                     viewport.longitude_max,
+                                     viewport.latitude_min,
+                                     viewport.latitude_max),
+                          &viewport)
+      }
+      None => vec![],
+    }
+  });
+  Ok(TileTimeseries {
+    times: times.iter().map(|t| t.to_rfc3339()).collect(),
+    stations: placed.iter()
+      .take(MAX_TIMESERIES_STATIONS)
+      .map(|&(station, _, _)| {
+        StationTimeseries {
+          usaf: station.usaf.clone(),
+          wban: station.wban.clone(),
+          temperatures: times.iter()
+            .map(|&t| {
+              interpolate_at(&station.measurements,
+                             t,
+                             |m| m.air_temperature)
+                .map(|v| unit.from_celsius(v))
+            })
+            .collect(),
+        }
+      })
+      .collect(),
+    truncated: placed.len() > MAX_TIMESERIES_STATIONS,
+    units: units::Units::new(unit, units::WindUnit::MetersPerSecond),
+  })
+}
+
+#[get("/api/map/<zoom>/<x>/<y>/timeseries.json?<params>")]
+fn tile_timeseries(zoom: u32,
+                   x: u32,
+                   y: u32,
+                   params: TimeseriesParams,
+                   stations: rocket::State<store::SharedStore>,
+                   _rate_limit: ratelimit::TileRateLimit)
+                   -> Result<JSON<TileTimeseries>, Failure> {
+  timeseries_of(&stations.read().unwrap(), zoom, x, y, &params)
+    .map(JSON)
+    .map_err(|_| Failure(Status::BadRequest))
+}
+
+#[derive(Serialize)]
+struct Health {
+  // Whether stations are still being added, with --load-in-background.
+  loading: bool,
+  stations: usize,
+  version: u64,
+}
+
+#[get("/health")]
+fn health(stations: rocket::State<store::SharedStore>) -> JSON<Health> {
+  let store = stations.read().unwrap();
+  JSON(Health {
+    loading: store.loading,
+    stations: store.stations.len(),
+    version: store.version,
+  })
+}
+
+// Number of evenly spaced stops /api/colorscale describes a ramp with.
+const COLOR_STOPS: usize = 11;
+
+#[derive(Serialize)]
+struct ColorStop {
+  value: f32,
+  // #rrggbb.
+  color: String,
+}
+
+#[derive(Serialize)]
+struct ColorScale {
+  min: f32,
+  max: f32,
+  stops: Vec<ColorStop>,
+  units: units::Units,
+}
+
+// The ramp the options color values with, or None for modes without one.
+fn color_scale_of(options: &RenderOptions,
+                  unit: units::TemperatureUnit,
+                  wind_unit: units::WindUnit)
+                  -> Option<ColorScale> {
+  let mode = options.mode;
+  mode.ramp().map(|(min, max)| {
+    let stops = (0..COLOR_STOPS)
+      .map(|i| {
+        let value = min + (max - min) * i as f32 / (COLOR_STOPS - 1) as f32;
+        ColorStop {
+          value: mode.convert(value, unit, wind_unit),
+          color: svg::hex(mode.color(value, options.gamma)),
+        }
+      })
+      .collect();
+    ColorScale {
+      min: mode.convert(min, unit, wind_unit),
+      max: mode.convert(max, unit, wind_unit),
+      stops: stops,
+      units: units::Units::new(unit, wind_unit),
+    }
+  })
+}
+
+// The color ramp tiles with the same parameters are drawn with, for clients
+// drawing their own legends. Values beyond min and max get the end colors.
+#[get("/api/colorscale?<params>")]
+fn color_scale(params: TileParams,
+               defaults: rocket::State<RenderOptions>)
+               -> Result<JSON<ColorScale>, Failure> {
+  let invalid = |_: String| Failure(Status::BadRequest);
+  let options = try!(params.render_options(defaults.inner())
+    .map_err(&invalid));
+  let unit = try!(units::TemperatureUnit::parse_option(&params.unit)
+    .map_err(&invalid));
+  let wind_unit = try!(units::WindUnit::parse_option(&params.wind_unit)
+    .map_err(&invalid));
+  color_scale_of(&options, unit, wind_unit)
+    .map(JSON)
+    .ok_or(Failure(Status::BadRequest))
+}
+
+#[get("/api/colorscale", rank = 2)]
+fn color_scale_default(defaults: rocket::State<RenderOptions>)
+                       -> Result<JSON<ColorScale>, Failure> {
+  color_scale(TileParams::default(), defaults)
+}
+
+// Parses an image size given as WIDTHxHEIGHT.
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+  let parts = size.split("x").collect::<Vec<_>>();
+  if parts.len() != 2 {
+    return Err(format!("size {:?} is not WIDTHxHEIGHT", size));
+  }
+  let width = try!(parts[0].parse::<u32>().map_err(|e| e.to_string()));
+  let height = try!(parts[1].parse::<u32>().map_err(|e| e.to_string()));
+  if width == 0 || height == 0 {
+    return Err(format!("empty size {:?}", size));
+  }
+  Ok((width, height))
+}
+
+// Upper bound on width * height of on-demand renders, so that a single
+// request can't exhaust memory.
+struct MaxRenderPixels(u64);
+
+impl MaxRenderPixels {
+  fn check(&self, width: u32, height: u32) -> Result<(), String> {
+    if width as u64 * height as u64 > self.0 {
+      return Err(format!("{}x{} exceeds the limit of {} pixels",
+                         width,
+                         height,
+                         self.0));
+    }
+    Ok(())
+  }
+}
+
+#[derive(FromForm)]
+struct Render3857Params {
+  // xmin,ymin,xmax,ymax in EPSG:3857 meters.
+  bbox: String,
+  size: String,
+  mode: Option<String>,
+}
+
+// WMS-style rendering of an arbitrary Web Mercator extent.
+#[get("/api/render3857?<params>")]
+fn render_3857<'a>(params: Render3857Params,
+                   stations: rocket::State<store::SharedStore>,
+                   defaults: rocket::State<RenderOptions>,
+                   max_pixels: rocket::State<MaxRenderPixels>)
+                   -> Result<rocket::Response<'a>, io::Error> {
+  let bounds = match params.bbox
+    .split(",")
+    .map(|v| v.trim().parse::<f64>())
+    .collect::<Result<Vec<_>, _>>() {
+    Ok(ref bounds) if bounds.len() == 4 && bounds[0] < bounds[2] &&
+                      bounds[1] < bounds[3] => bounds.clone(),
+    _ => return bad_request(format!("invalid bbox {:?}", params.bbox)),
+  };
+  let (width, height) = match parse_size(&params.size) {
+    Ok(size) => size,
+    Err(message) => return bad_request(message),
+  };
+  if let Err(message) = max_pixels.check(width, height) {
+    return bad_request(message);
+  }
+  let mut options = *defaults.inner();
+  if let Some(ref mode) = params.mode {
+    options.mode = match DrawMode::parse(mode) {
+      Ok(mode) => mode,
+      Err(message) => return bad_request(message),
+    };
+  }
+
+  let (start, end) = options.window();
+  let (longitude_min, latitude_min) =
+    mercator_meters_to_degrees(bounds[0], bounds[1]);
+  let (longitude_max, latitude_max) =
+    mercator_meters_to_degrees(bounds[2], bounds[3]);
+  let viewport = match Viewport::new(longitude_min,
+                                     longitude_max,
+                                     latitude_min,
+                                     latitude_max,
+                                     width,
+                                     height) {
+    Some(viewport) => viewport,
+    None => {
+      return bad_request(format!("bbox {:?} lies beyond the mercator \
+                                  cut-off",
+                                 params.bbox))
+    }
+  };
+  let store = stations.read().unwrap();
+  let (mut img, _) = draw_stations(&position_stations(&store.stations,
+                                                      &viewport),
+                                   &neighbor_samples(&store.stations,
+                                                     start,
+                                                     end,
+                                                     &options),
+                                   &viewport,
+                                   2,
+                                   start,
+                                   end,
+                                   &options);
+  if let Some(attribution) = options.attribution {
+    draw_attribution(&mut img, attribution.text);
+  }
+  rocket::Response::build()
+    .header(rocket::http::ContentType::PNG)
+    .sized_body(io::Cursor::new(try!(encode_png(img, options.indexed))))
+    .ok()
+}
+
+// Interpolation settings of /api/field.tiff, the defaults of the matching
+// --netcdf-* flags.
+const FIELD_MAX_DISTANCE_KM: f32 = 500.0;
+const FIELD_MIN_NEIGHBORS: usize = 3;
+
+#[derive(FromForm)]
+struct FieldParams {
+  // longitude_min,latitude_min,longitude_max,latitude_max in degrees.
+  bbox: String,
+  size: String,
+  // temperature (default), dew_point, pressure, wind_speed or humidity.
+  field: Option<String>,
+  start: Option<String>,
+  end: Option<String>,
+}
+
+// The extent, pixel size and time window of a field export, checked.
+fn field_extent(bbox: &str,
+                size: &str,
+                start: &Option<String>,
+                end: &Option<String>,
+                defaults: &RenderOptions,
+                max_pixels: u64)
+                -> Result<(api::BoundingBox, u32, u32, DateTime<UTC>,
+                           DateTime<UTC>),
+                          String> {
+  let bbox = try!(api::BoundingBox::parse(bbox));
+  let (width, height) = try!(parse_size(size));
+  try!(MaxRenderPixels(max_pixels).check(width, height));
+  let (default_start, default_end) = defaults.window();
+  let parse_time = |time: &Option<String>, default: DateTime<UTC>| {
+    time.as_ref().map_or(Ok(default), |t| parse_datetime(t))
+  };
+  Ok((bbox,
+      width,
+      height,
+      try!(parse_time(start, default_start)),
+      try!(parse_time(end, default_end))))
+}
+
+// The interpolated window mean of a field at the center of every pixel of a
+// width x height grid over bbox, row by row from the north.
+fn interpolate_field(stations: &[WeatherStation],
+                     bbox: &api::BoundingBox,
+                     width: u32,
+                     height: u32,
+                     start: DateTime<UTC>,
+                     end: DateTime<UTC>,
+                     field: Field)
+                     -> Vec<Option<f32>> {
+  let longitude_step =
+    (bbox.longitude_max - bbox.longitude_min) / width as f32;
+  let latitude_step = (bbox.latitude_max - bbox.latitude_min) / height as f32;
+  let longitudes = (0..width)
+    .map(|i| bbox.longitude_min + (i as f32 + 0.5) * longitude_step)
+    .collect::<Vec<_>>();
+  let latitudes = (0..height)
+    .map(|j| bbox.latitude_max - (j as f32 + 0.5) * latitude_step)
+    .collect::<Vec<_>>();
+  let samples = window_means(stations, start, end, DrawMode::Scalar(field));
+  grid::interpolate(&samples,
+                    &latitudes,
+                    &longitudes,
+                    FIELD_MAX_DISTANCE_KM,
+                    FIELD_MIN_NEIGHBORS)
+}
+
+// The interpolated window mean of a field as a georeferenced float raster,
+// for GIS tools that want the values rather than colors.
+#[get("/api/field.tiff?<params>")]
+fn field_tiff<'a>(params: FieldParams,
+                  stations: rocket::State<store::SharedStore>,
+                  defaults: rocket::State<RenderOptions>,
+                  max_pixels: rocket::State<MaxRenderPixels>)
+                  -> Result<rocket::Response<'a>, io::Error> {
+  let (bbox, width, height, start, end) = match field_extent(&params.bbox,
+                                                             &params.size,
+                                                             &params.start,
+                                                             &params.end,
+                                                             defaults.inner(),
+                                                             max_pixels.0) {
+    Ok(extent) => extent,
+    Err(message) => return bad_request(message),
+  };
+  let field = match params.field.as_ref().map_or(Ok(Field::Temperature),
+                                                  |f| Field::parse(f)) {
+    Ok(field) => field,
+    Err(message) => return bad_request(message),
+  };
+
+  let values = interpolate_field(&stations.read().unwrap().stations,
+                                 &bbox,
+                                 width,
+                                 height,
+                                 start,
+                                 end,
+                                 field);
+  let raster = geotiff::Raster {
+    width: width,
+    height: height,
+    longitude_min: bbox.longitude_min as f64,
+    latitude_max: bbox.latitude_max as f64,
+    longitude_step: (bbox.longitude_max - bbox.longitude_min) as f64 /
+                    width as f64,
+    latitude_step: (bbox.latitude_max - bbox.latitude_min) as f64 /
+                   height as f64,
+    nodata: EXPORT_FILL_VALUE,
+    data: values.iter().map(|v| v.unwrap_or(EXPORT_FILL_VALUE)).collect(),
+  };
+  let mut data = vec![];
+  try!(raster.write(&mut data));
+  rocket::Response::build()
+    .header(rocket::http::ContentType::new("image", "tiff"))
+    .sized_body(io::Cursor::new(data))
+    .ok()
+}
+
+// The fields of /api/render/stack.png's red, green and blue channels.
+const STACK_FIELDS: [Field; 3] =
+  [Field::Temperature, Field::Pressure, Field::WindSpeed];
+
+#[derive(FromForm)]
+struct StackParams {
+  // longitude_min,latitude_min,longitude_max,latitude_max in degrees.
+  bbox: String,
+  size: String,
+  start: Option<String>,
+  end: Option<String>,
+}
+
+// Several interpolated fields in the channels of one PNG, as input for
+// tools that read images rather than rasters. Each channel is the field
+// normalized over its range (see Field::range) and scaled to 1 to 255;
+// 0 means no station was near enough to interpolate from.
+fn stack_image(stations: &[WeatherStation],
+               bbox: &api::BoundingBox,
+               width: u32,
+               height: u32,
+               start: DateTime<UTC>,
+               end: DateTime<UTC>)
+               -> image::RgbImage {
+  let mut img: image::RgbImage = image::ImageBuffer::new(width, height);
+  for (channel, &field) in STACK_FIELDS.iter().enumerate() {
+    let values =
+      interpolate_field(stations, bbox, width, height, start, end, field);
+    for (pixel, value) in img.pixels_mut().zip(values) {
+      pixel.data[channel] = value.map_or(0, |v| {
+        1 + (field.normalize(v) * 254.0).round() as u8
+      });
+    }
+  }
+  img
+}
+
+#[get("/api/render/stack.png?<params>")]
+fn render_stack<'a>(params: StackParams,
+                    stations: rocket::State<store::SharedStore>,
+                    defaults: rocket::State<RenderOptions>,
+                    max_pixels: rocket::State<MaxRenderPixels>)
+                    -> Result<rocket::Response<'a>, io::Error> {
+  let (bbox, width, height, start, end) = match field_extent(&params.bbox,
+                                                             &params.size,
+                                                             &params.start,
+                                                             &params.end,
+                                                             defaults.inner(),
+                                                             max_pixels.0) {
+    Ok(extent) => extent,
+    Err(message) => return bad_request(message),
+  };
+
+  let img = stack_image(&stations.read().unwrap().stations,
+                        &bbox,
+                        width,
+                        height,
+                        start,
+                        end);
+  rocket::Response::build()
+    .header(rocket::http::ContentType::PNG)
+    .sized_body(io::Cursor::new(try!(encode_png(img, false))))
+    .ok()
+}
+
+// A line chart of the temperatures, scaled to fill the image, with each
+// segment in the color of the temperature it ends at.
+fn draw_sparkline(temperatures: &[(DateTime<UTC>, f32)],
+                  width: u32,
+                  height: u32,
+                  background: image::Rgb<u8>)
+                  -> image::RgbImage {
+  let mut img = image::ImageBuffer::from_pixel(width, height, background);
+  let (first, last) = match (temperatures.first(), temperatures.last()) {
+    (Some(&(first, _)), Some(&(last, _))) => (first, last),
+    _ => return img,
+  };
+  let values = temperatures.iter().map(|&(_, t)| t);
+  let min = values.clone().fold(::std::f32::MAX, f32::min);
+  let max = values.fold(::std::f32::MIN, f32::max);
+  // A flat line runs through the middle.
+  let (min, max) = if max > min { (min, max) } else { (min - 1.0, max + 1.0) };
+  let seconds = cmp::max((last - first).num_seconds(), 1) as f32;
+  let point = |time: DateTime<UTC>, t: f32| {
+    ((time - first).num_seconds() as f32 / seconds * (width - 1) as f32,
+     (max - t) / (max - min) * (height - 1) as f32)
+  };
+  let mut previous = point(first, temperatures[0].1);
+  for &(time, t) in temperatures {
+    let next = point(time, t);
+    draw_line(&mut img, previous, next, DrawMode::Temperature.color(t, 1.0));
+    previous = next;
+  }
+  img
+}
+
+#[derive(FromForm, Default)]
+struct SparklineParams {
+  start: Option<String>,
+  end: Option<String>,
+  // WIDTHxHEIGHT, 120x30 by default.
+  size: Option<String>,
+}
+
+// A small chart of a station's air temperature, for embedding next to its
+// name. Rocket segments can't mix a parameter with text, so the .png is
+// taken off the last one by hand.
+#[get("/api/station/<usaf>/<file>?<params>")]
+fn station_sparkline<'a>(usaf: String,
+                         file: String,
+                         params: SparklineParams,
+                         stations: rocket::State<store::SharedStore>,
+                         defaults: rocket::State<RenderOptions>,
+                         max_pixels: rocket::State<MaxRenderPixels>)
+                         -> Result<rocket::Response<'a>, io::Error> {
+  let not_found = || {
+    rocket::Response::build()
+      .status(Status::NotFound)
+      .ok()
+  };
+  if !file.ends_with(".png") {
+    return not_found();
+  }
+  let wban = &file[..file.len() - ".png".len()];
+  let (width, height) = match params.size
+    .as_ref()
+    .map_or(Ok((120, 30)), |size| parse_size(size)) {
+    Ok(size) => size,
+    Err(message) => return bad_request(message),
+  };
+  if let Err(message) = max_pixels.check(width, height) {
+    return bad_request(message);
+  }
+  let (default_start, default_end) = defaults.window();
+  let parse_time = |time: &Option<String>, default: DateTime<UTC>| {
+    time.as_ref().map_or(Ok(default), |t| parse_datetime(t))
+  };
+  let (start, end) = match (parse_time(&params.start, default_start),
+                            parse_time(&params.end, default_end)) {
+    (Ok(start), Ok(end)) => (start, end),
+    (Err(message), _) | (_, Err(message)) => return bad_request(message),
+  };
+
+  let store = stations.read().unwrap();
+  let station = match store.get(&usaf, wban) {
+    Some(station) => station,
+    None => return not_found(),
+  };
+  let temperatures = measurements_in(&station.measurements, start, end)
+    .iter()
+    .filter_map(|m| m.air_temperature.map(|t| (m.datetime, t)))
+    .collect::<Vec<_>>();
+  let img = draw_sparkline(&temperatures, width, height, defaults.background);
+  rocket::Response::build()
+    .header(rocket::http::ContentType::PNG)
+    .sized_body(io::Cursor::new(try!(encode_png(img, false))))
+    .ok()
+}
+
+#[get("/api/station/<usaf>/<file>", rank = 2)]
+fn station_sparkline_default<'a>(usaf: String,
+                                 file: String,
+                                 stations: rocket::State<store::SharedStore>,
+                                 defaults: rocket::State<RenderOptions>,
+                                 max_pixels: rocket::State<MaxRenderPixels>)
+                                 -> Result<rocket::Response<'a>, io::Error> {
+  station_sparkline(usaf,
+                    file,
+                    SparklineParams::default(),
+                    stations,
+                    defaults,
+                    max_pixels)
+}
+
+const ALLOWED_ARGS: &'static str = "allowed combinations: at most one \
+  input (--file, --directory or --tar), and none with --sqlite; render, \
+  convert and validate need exactly one, render also --render_dir and \
+  convert --netcdf-out; --tar only with --format ish";
+
+// Rejects flag combinations whose meaning would be ambiguous. `command` is
+// the subcommand, or empty for the deprecated flat flags.
+fn check_args(command: &str, args: &clap::ArgMatches) -> Result<(), String> {
+  let inputs = ["file", "directory", "tar"]
+    .iter()
+    .filter(|name| args.is_present(name))
+    .map(|name| format!("--{}", name))
+    .collect::<Vec<_>>();
+  if inputs.len() > 1 {
+    return Err(format!("{} can't be used together", inputs.join(" and ")));
+  }
+  if inputs.is_empty() && command != "serve" && command != "" {
+    return Err(format!("{} needs an input", command));
+  }
+  if args.is_present("validate") {
+    if inputs.is_empty() {
+      return Err(String::from("--validate needs an input to check"));
+    }
+    if args.is_present("render_dir") {
+      return Err(String::from("--validate can't be used with --render_dir"));
+    }
+  }
+  if command == "render" && !args.is_present("render_dir") {
+    return Err(String::from("render needs --render_dir"));
+  }
+  if command == "convert" && !args.is_present("netcdf_out") {
+    return Err(String::from("convert needs --netcdf-out"));
+  }
+  if args.is_present("tar") && args.value_of("format") != Some("ish") {
+    return Err(String::from("--tar only supports --format ish"));
+  }
+  if args.is_present("sqlite") {
+    if !inputs.is_empty() {
+      return Err(format!("--sqlite can't be used with {}",
+                         inputs.join(" or ")));
+    }
+    if args.is_present("load_in_background") {
+      return Err(String::from("--sqlite doesn't load anything in the \
+                               background"));
+    }
+  }
+  if args.is_present("load_in_background") {
+    if command != "serve" {
+      return Err(String::from("--load-in-background only applies to serve"));
+    }
+    if args.is_present("prerender_tiles") {
+      return Err(String::from("--prerender-tiles needs the whole input, \
+                               which --load-in-background doesn't wait for"));
+    }
+  }
+  if args.is_present("max_stations") && !args.is_present("directory") {
+    return Err(String::from("--max_stations only applies to --directory"));
+  }
+  if args.is_present("station_cache") {
+    if !args.is_present("directory") {
+      return Err(String::from("--station-cache only applies to --directory"));
+    }
+    if args.is_present("track_provenance") {
+      return Err(String::from("--station-cache doesn't keep the provenance \
+                               --track-provenance needs"));
+    }
+  }
+  for name in &["prerender_maxzoom", "precompute_tiles"] {
+    if let Some(zoom) = args.value_of(name) {
+      match zoom.parse::<u32>() {
+        Ok(zoom) if zoom <= MAX_ZOOM => {}
+        _ => {
+          return Err(format!("--{} must be a zoom of at most {}, got {}",
+                             name.replace("_", "-"),
+                             MAX_ZOOM,
+                             zoom))
+        }
+      }
+    }
+  }
+  let channel_bound = args.value_of("channel_bound").unwrap_or("1");
+  match channel_bound.parse::<usize>() {
+    Ok(bound) if bound > 0 => {}
+    _ => {
+      return Err(format!("--channel-bound must be a positive number, got {}",
+                         channel_bound))
+    }
+  }
+  Ok(())
+}
+
+// Where to load stations from and how to clean them up; shared by every
+// subcommand.
+fn input_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+  vec![clap::Arg::with_name("file").long("file").takes_value(true),
+       clap::Arg::with_name("directory").long("directory").takes_value(true),
+       clap::Arg::with_name("tar").long("tar").takes_value(true),
+       clap::Arg::with_name("format")
+         .long("format")
+         .help("Input format: ish (NOAA fixed-width) or ndjson")
+         .takes_value(true)
+         .default_value("ish"),
+       clap::Arg::with_name("mmap")
+         .long("mmap")
+         .help("Memory-map uncompressed input files instead of reading them"),
+       clap::Arg::with_name("track_provenance")
+         .long("track-provenance")
+         .help("Record which input file each measurement was loaded from"),
+       clap::Arg::with_name("max_stations")
+         .long("max_stations")
+         .takes_value(true),
+       clap::Arg::with_name("max_measurements")
+         .long("max_measurements")
+         .takes_value(true),
+       clap::Arg::with_name("extensions")
+         .long("extensions")
+         .help("Comma separated extensions of the files to load from \
+                --directory, ignoring case; defaults to gz for ish and \
+                ndjson,json,gz for ndjson")
+         .takes_value(true),
+       clap::Arg::with_name("channel_bound")
+         .long("channel-bound")
+         .help("Maximum number of parsed stations buffered between workers \
+                and the loader")
+         .takes_value(true)
+         .default_value("64"),
+       clap::Arg::with_name("threads")
+         .long("threads")
+         .help("Worker threads for parsing and rendering --render_dir \
+                frames")
+         .takes_value(true)
+         .default_value("8"),
+       clap::Arg::with_name("stations_history")
+         .long("stations-history")
+         .help("NOAA isd-history.csv to look up station names in")
+         .takes_value(true),
+       clap::Arg::with_name("stats_out")
+         .long("stats-out")
+         .help("Write a JSON summary of the loaded data to this file")
+         .takes_value(true),
+       clap::Arg::with_name("dup_policy")
+         .long("dup-policy")
+         .help("Which of several measurements at the same time to keep: \
+                first, last, prefer-synop or mean")
+         .takes_value(true)
+         .default_value("first"),
+       clap::Arg::with_name("station_cache")
+         .long("station-cache")
+         .help("Load the stations parsed from --directory from this file \
+                if it was written for the same input files, and write it \
+                otherwise")
+         .takes_value(true),
+       clap::Arg::with_name("require_elevation")
+         .long("require-elevation")
+         .help("Drop stations whose elevation is unknown"),
+       clap::Arg::with_name("min_quality")
+         .long("min-quality")
+         .help("Drop measurements with a value that didn't pass all quality \
+                checks"),
+       clap::Arg::with_name("drop_inconsistent")
+         .long("drop-inconsistent")
+         .help("Drop measurements with a dew point above the air \
+                temperature"),
+       clap::Arg::with_name("sigma_clip")
+         .long("sigma-clip")
+         .help("Drop air temperatures more than this many standard deviations \
+                from their station's mean")
+         .takes_value(true),
+       clap::Arg::with_name("fill_gaps")
+         .long("fill-gaps")
+         .help("Interpolate missing air temperatures across gaps of at most \
+                this many hours")
+         .takes_value(true),
+       clap::Arg::with_name("verbose")
+         .long("verbose")
+         .help("Also log per-request details")
+         .conflicts_with("quiet"),
+       clap::Arg::with_name("quiet")
+         .long("quiet")
+         .help("Only log warnings")]
+}
+
+fn render_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+  vec![clap::Arg::with_name("render_dir")
+         .long("render_dir")
+         .takes_value(true),
+       clap::Arg::with_name("render_start")
+         .long("render-start")
+         .takes_value(true)
+         .default_value("2016-01-01"),
+       clap::Arg::with_name("render_end")
+         .long("render-end")
+         .takes_value(true)
+         .default_value("2016-12-30"),
+       clap::Arg::with_name("render_frames")
+         .long("render-frames")
+         .takes_value(true)
+         .default_value("52"),
+       clap::Arg::with_name("render_delta")
+         .long("render-delta")
+         .help("Draw each frame by repainting the stations that changed \
+                since the previous one instead of from scratch"),
+       clap::Arg::with_name("render_latitude_min")
+         .long("render-latitude-min")
+         .help("Southern edge of the rendered frames, in degrees")
+         .takes_value(true)
+         .allow_hyphen_values(true)
+         .default_value("-90"),
+       clap::Arg::with_name("render_latitude_max")
+         .long("render-latitude-max")
+         .help("Northern edge of the rendered frames, in degrees")
+         .takes_value(true)
+         .allow_hyphen_values(true)
+         .default_value("90")]
+}
+
+fn convert_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+  vec![clap::Arg::with_name("netcdf_out")
+         .long("netcdf-out")
+         .takes_value(true)
+         .requires_all(&["netcdf_start", "netcdf_end"]),
+       clap::Arg::with_name("netcdf_start")
+         .long("netcdf-start")
+         .takes_value(true),
+       clap::Arg::with_name("netcdf_end")
+         .long("netcdf-end")
+         .takes_value(true),
+       clap::Arg::with_name("netcdf_resolution")
+         .long("netcdf-resolution")
+         .takes_value(true)
+         .default_value("1.0"),
+       clap::Arg::with_name("netcdf_max_distance")
+         .long("netcdf-max-distance")
+         .help("Only interpolate from stations within this many km")
+         .takes_value(true)
+         .default_value("500"),
+       clap::Arg::with_name("netcdf_min_neighbors")
+         .long("netcdf-min-neighbors")
+         .help("Cells with fewer stations within --netcdf-max-distance take \
+                the nearest station's value instead of interpolating")
+         .takes_value(true)
+         .default_value("3")]
+}
+
+fn serve_args<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
+  vec![clap::Arg::with_name("static_dir")
+         .long("static-dir")
+         .takes_value(true)
+         .default_value("static"),
+       clap::Arg::with_name("sqlite")
+         .long("sqlite")
+         .help("Draw tiles from the stations in this SQLite database, \
+                queried per tile instead of loaded into memory; see \
+                source::SCHEMA for its tables")
+         .takes_value(true),
+       clap::Arg::with_name("pretty_json")
+         .long("pretty-json")
+         .help("Indent JSON responses, for reading them while debugging"),
+       clap::Arg::with_name("marker")
+         .long("marker")
+         .help("Default station marker: square, circle or cross")
+         .takes_value(true)
+         .default_value("square"),
+       clap::Arg::with_name("baseline")
+         .long("baseline")
+         .help("CSV of latitude,longitude,temperature on a regular grid to \
+                compare against in mode=anomaly")
+         .takes_value(true),
+       clap::Arg::with_name("tile_background")
+         .long("tile-background")
+         .help("Default tile background color as RRGGBB")
+         .takes_value(true)
+         .default_value("000000"),
+       clap::Arg::with_name("calm_color")
+         .long("calm-color")
+         .help("Default marker color of stations reporting calm wind in the \
+                wind modes, as RRGGBB")
+         .takes_value(true)
+         .default_value("c0c0c0"),
+       clap::Arg::with_name("variable_color")
+         .long("variable-color")
+         .help("Default marker color of stations reporting variable wind in \
+                the wind modes, as RRGGBB")
+         .takes_value(true)
+         .default_value("ffff00"),
+       clap::Arg::with_name("highlight")
+         .long("highlight")
+         .help("File of <usaf>-<wban> <RRGGBB> lines; these stations are \
+                drawn in their color in every mode")
+         .takes_value(true),
+       clap::Arg::with_name("highlight_scale")
+         .long("highlight-scale")
+         .help("Size of highlighted markers relative to the others")
+         .takes_value(true)
+         .default_value("1"),
+       clap::Arg::with_name("tile_attribution")
+         .long("tile-attribution")
+         .help("Text, e.g. a data attribution, to draw into the corner of \
+                tiles and renders")
+         .takes_value(true),
+       clap::Arg::with_name("attribution_tiles")
+         .long("attribution-tiles")
+         .help("Which tiles carry --tile-attribution: bottom (row) or all")
+         .takes_value(true)
+         .possible_values(&["bottom", "all"])
+         .default_value("bottom"),
+       clap::Arg::with_name("fixed_now")
+         .long("fixed-now")
+         .help("Use this RFC 3339 time as the current time, e.g. for \
+                reproducible freshness queries")
+         .takes_value(true),
+       clap::Arg::with_name("default_window")
+         .long("default-window")
+         .help("Draw only the last this many hours of measurements, up to \
+                --fixed-now or the latest one, unless a request asks for \
+                another window")
+         .takes_value(true),
+       clap::Arg::with_name("max_render_pixels")
+         .long("max-render-pixels")
+         .help("Largest width * height /api/render3857 will draw")
+         .takes_value(true)
+         .default_value("16777216"),
+       clap::Arg::with_name("tile_cache_size")
+         .long("tile-cache-size")
+         .takes_value(true)
+         .default_value("1000"),
+       clap::Arg::with_name("tile_cache_ttl")
+         .long("tile-cache-ttl")
+         .takes_value(true)
+         .default_value("300"),
+       clap::Arg::with_name("tile_rate")
+         .long("tile-rate")
+         .help("Per-client limit on /api/map requests per second, unlimited if \
+                unset")
+         .takes_value(true),
+       clap::Arg::with_name("tile_burst")
+         .long("tile-burst")
+         .help("Requests a client may make at once before --tile-rate applies")
+         .takes_value(true)
+         .default_value("20"),
+       clap::Arg::with_name("trust_proxy")
+         .long("trust-proxy")
+         .help("Rate limit by X-Forwarded-For instead of the peer address"),
+       clap::Arg::with_name("prerender_tiles")
+         .long("prerender-tiles")
+         .help("Write all non-empty tiles up to --prerender-maxzoom to this \
+                directory as z/x/y.png")
+         .takes_value(true),
+       clap::Arg::with_name("prerender_maxzoom")
+         .long("prerender-maxzoom")
+         .takes_value(true)
+         .default_value("6"),
+       clap::Arg::with_name("load_in_background")
+         .long("load-in-background")
+         .help("Start serving right away and show stations as they load; \
+                with --directory, parsed stations appear about every \
+                second, at the cost of holding them twice until loading is \
+                done"),
+       clap::Arg::with_name("precompute_tiles")
+         .long("precompute-tiles")
+         .help("Precompute which tiles of zoom levels up to this one each \
+                station is drawn on, and where, instead of finding the \
+                stations of every tile on request")
+         .takes_value(true)]
+}
+
+// What load_stations ended up with, logged and optionally written as JSON.
+#[derive(Serialize)]
+struct LoadSummary {
+  stations: usize,
+  measurements: usize,
+  // RFC 3339 times of the earliest and latest measurement.
+  first: Option<String>,
+  last: Option<String>,
+  load_seconds: f64,
+  failed: usize,
+  // Stations without a known elevation.
+  missing_elevation: usize,
+  // Measurements with a dew point above the air temperature, counted before
+  // --drop-inconsistent.
+  dew_gt_temp: usize,
+}
+
+impl LoadSummary {
+  fn new(stations: &[WeatherStation],
+         num_failed: usize,
+         dew_gt_temp: usize,
+         elapsed: time::Duration)
+         -> LoadSummary {
+    let first = stations.iter()
+      .filter_map(|s| s.measurements.first())
+      .map(|m| m.datetime)
+      .min();
+    let last = stations.iter()
+      .filter_map(|s| s.measurements.last())
+      .map(|m| m.datetime)
+      .max();
+    LoadSummary {
+      stations: stations.len(),
+      measurements: stations.iter().map(|s| s.measurements.len()).sum(),
+      first: first.map(|t| t.to_rfc3339()),
+      last: last.map(|t| t.to_rfc3339()),
+      load_seconds: elapsed.num_milliseconds() as f64 / 1.0e3,
+      failed: num_failed,
+      missing_elevation: stations.iter()
+        .filter(|s| s.elevation.is_none())
+        .count(),
+      dew_gt_temp: dew_gt_temp,
+    }
+  }
+}
+
+// Adds stations to the store's, as a new store.
+fn publish_partial(shared: &store::SharedStore, new: &[WeatherStation]) {
+  let mut store = shared.write().unwrap();
+  let version = store.version + 1;
+  let previous = mem::replace(&mut *store, store::StationStore::new(vec![], 0));
+  *store = previous.extend(new, version);
+  store.loading = true;
+}
+
+// Loads and cleans up the stations. Returns them with the files their
+// measurements came from with --track-provenance, and the number of input
+// files that failed to parse. Stations parsed from a directory are copied
+// into `partial` as they come in, before any clean up.
+fn load_stations(args: &clap::ArgMatches,
+                 partial: Option<&store::SharedStore>)
+                 -> (Vec<WeatherStation>, Vec<String>, usize) {
+  load_stations_until(args, partial, cancel::flag())
+}
+
+// Like load_stations, but stops parsing further files once `cancelled` is
+// set, keeping what was parsed so far.
+fn load_stations_until(args: &clap::ArgMatches,
+                       partial: Option<&store::SharedStore>,
+                       cancelled: &'static sync::atomic::AtomicBool)
+                       -> (Vec<WeatherStation>, Vec<String>, usize) {
+  let is_cancelled = || cancelled.load(sync::atomic::Ordering::SeqCst);
+  let load_start = time::now();
+  let max_measurements = args.value_of("max_measurements")
+    .and_then(|n| n.parse::<usize>().ok())
+    .unwrap_or(usize::max_value());
+
+  let use_mmap = args.is_present("mmap");
+  let format = InputFormat::parse(args.value_of("format").unwrap()).unwrap();
+
+  let mut stations = Vec::new();
+  let mut num_failed = 0;
+  let mut provenance = if args.is_present("track_provenance") {
+    Some(Provenance::default())
+  } else {
+    None
+  };
+
+  cancel::set_loading(true);
+  cpuprofiler::PROFILER.lock().unwrap().start("prof.profile").unwrap();
+
+  args.value_of("directory").map(|directory| {
+    let max_stations = args.value_of("max_stations")
+      .and_then(|n| n.parse::<usize>().ok())
+      .unwrap_or(usize::max_value());
+    let n_threads = args.value_of("threads").unwrap().parse::<usize>().unwrap();
+    let pool = threadpool::ThreadPool::new(n_threads);
+    // Workers block once this many parsed stations are waiting to be
+    // consumed, so memory stays bounded no matter how large the directory.
+    let channel_bound =
+      args.value_of("channel_bound").unwrap().parse::<usize>().unwrap();
+    let (tx, rx) = sync::mpsc::sync_channel(channel_bound);
+
+    let extensions = args.value_of("extensions")
+      .unwrap_or(format.default_extensions())
+      .split(',')
+      .map(|e| e.trim().to_lowercase())
+      .collect::<Vec<_>>();
+    let (paths, ignored): (Vec<_>, Vec<_>) = fs::read_dir(directory)
+      .unwrap()
+      .map(|entry| entry.unwrap().path())
+      .partition(|path| has_extension(path, &extensions));
+    let paths = paths.into_iter().take(max_stations).collect::<Vec<_>>();
+
+    let cache = args.value_of("station_cache").and_then(|cache| {
+      let parse_options = format!("{:?} {}", format, max_measurements);
+      match station_cache::fingerprint(&paths, &parse_options) {
+        Ok(fingerprint) => Some((path::Path::new(cache), fingerprint)),
+        Err(error) => {
+          warn!("not caching stations: {}", error);
+          None
+        }
+      }
+    });
+    if let Some((cache, fingerprint)) = cache {
+      match station_cache::read(cache, fingerprint) {
+        Ok(cached) => {
+          info!("loaded {} stations from {}", cached.len(), cache.display());
+          stations.extend(cached);
+          return;
+        }
+        Err(reason) => {
+          warn!("ignoring station cache {}: {}", cache.display(), reason)
+        }
+      }
+    }
+
+    let mut num_files = 0;
+    for path in paths {
+      if is_cancelled() {
+        break;
+      }
+      let tx = tx.clone();
+      pool.execute(move || {
+        // Files still queued when loading is cancelled are skipped.
+        if cancelled.load(sync::atomic::Ordering::SeqCst) {
+          return;
+        }
+        let filename = path.to_str().unwrap();
+        tx.send((String::from(filename),
+                 parse_input(filename, max_measurements, use_mmap, format)))
+          .unwrap();
+      });
+      num_files += 1;
+    }
+    if num_files == 0 && !ignored.is_empty() {
+      warn!("none of the {} files in {} have one of the extensions {}",
+            ignored.len(),
+            directory,
+            extensions.join(","));
+    }
+    // Lets the loop below end early once skipped files dropped their
+    // senders.
+    drop(tx);
+
+    let start = time::now();
+    let mut last_update = time::now();
+    let mut num_processed = 0;
+    let mut num_published = 0;
+    for (filename, result) in rx.iter().take(num_files) {
+      match result {
+        Ok(mut parsed) => {
+          if let Some(ref mut provenance) = provenance {
+            provenance.tag(&filename, &mut parsed);
+          }
+          stations.extend(parsed);
+
+          num_processed += 1;
+          if time::now() - last_update > time::Duration::seconds(1) {
+            last_update = time::now();
+            let elapsed = time::now() - start;
+            let elapsed_secs = elapsed.num_milliseconds() as f64 / 1.0e3;
+            info!("processed {} files in {} - {} files / second",
+                  num_processed,
+                  elapsed_secs,
+                  num_processed as f64 / elapsed_secs);
+            if let Some(shared) = partial {
+              publish_partial(shared, &stations[num_published..]);
+              num_published = stations.len();
+            }
+          }
+        }
+        Err(error) => {
+          warn!("{}", error);
+          num_failed += 1;
+        }
+      }
+    }
+    if is_cancelled() {
+      warn!("cancelled after {} of {} files",
+            num_processed + num_failed,
+            num_files);
+    } else if let Some((cache, fingerprint)) = cache {
+      if let Err(error) = station_cache::write(cache, fingerprint, &stations) {
+        warn!("writing station cache {} failed: {}", cache.display(), error);
+      }
+    }
+  });
+
+  args.value_of("file").map(|f| {
+    if is_cancelled() {
+      return;
+    }
+    let mut parsed = parse_input(f, max_measurements, use_mmap, format)
+      .unwrap();
+    if let Some(ref mut provenance) = provenance {
+      provenance.tag(f, &mut parsed);
+    }
+    stations.extend(parsed);
+  });
+
+  args.value_of("tar")
+    .map(|archive| {
+      parse_tar(archive,
+                max_measurements,
+                &mut stations,
+                &mut provenance,
+                cancelled)
+    })
+    .map(|result| { num_failed += result.unwrap(); });
+
+  cpuprofiler::PROFILER.lock().unwrap().stop().unwrap();
+  cancel::set_loading(false);
+
+  let sources = provenance.map_or(vec![], |provenance| {
+    if provenance.untracked > 0 {
+      warn!("provenance not tracked for {} files beyond the first {}",
+            provenance.untracked,
+            provenance.files.len());
+    }
+    provenance.files
+  });
+
+  if args.is_present("min_quality") {
+    let num_dropped = stations.iter_mut()
+      .map(|station| qc::drop_unverified(station))
+      .sum::<usize>();
+    info!("quality filtering dropped {} measurements", num_dropped);
+  }
+
+  let dew_gt_temp = stations.iter()
+    .map(|station| qc::count_inconsistent(station))
+    .sum::<usize>();
+  if dew_gt_temp > 0 {
+    warn!("{} measurements have a dew point above the air temperature",
+          dew_gt_temp);
+  }
+  if args.is_present("drop_inconsistent") {
+    let num_dropped = stations.iter_mut()
+      .map(|station| qc::drop_inconsistent(station))
+      .sum::<usize>();
+    info!("consistency filtering dropped {} measurements", num_dropped);
+  }
+
+  let num_loaded = stations.len();
+  let dup_policy =
+    DupPolicy::parse(args.value_of("dup_policy").unwrap()).unwrap();
+  stations = merge_stations(stations, dup_policy);
+  if stations.len() < num_loaded {
+    info!("merged {} station files into {} stations",
+          num_loaded,
+          stations.len());
+  }
+
+  args.value_of("stations_history").map(|path| {
+    let entries = gazetteer::load(path).unwrap();
+    let num_unknown = gazetteer::apply(&entries, &mut stations);
+    info!("{} of {} stations not in {}", num_unknown, stations.len(), path);
+  });
+
+  if args.is_present("require_elevation") {
+    let num_merged = stations.len();
+    stations.retain(|station| station.elevation.is_some());
+    info!("dropped {} stations without an elevation",
+          num_merged - stations.len());
+  }
+
+  args.value_of("sigma_clip").map(|k| {
+    let k = k.parse::<f32>().unwrap();
+    let num_dropped = stations.iter_mut()
+      .map(|station| qc::sigma_clip(station, k))
+      .sum::<usize>();
+    info!("sigma clipping at {} dropped {} temperatures", k, num_dropped);
+  });
+
+  args.value_of("fill_gaps").map(|hours| {
+    let hours = hours.parse::<f64>().unwrap();
+    let max_gap = time::Duration::seconds((hours * 3600.0) as i64);
+    let num_filled = stations.iter_mut()
+      .map(|station| qc::fill_gaps(station, max_gap))
+      .sum::<usize>();
+    info!("gap filling up to {}h filled {} temperatures", hours, num_filled);
+  });
+
+  let summary =
+    LoadSummary::new(&stations,
+                     num_failed,
+                     dew_gt_temp,
+                     time::now() - load_start);
+  info!("loaded stations={} measurements={} first={} last={} seconds={} \
+         failed={} missing_elevation={} dew_gt_temp={}",
+        summary.stations,
+        summary.measurements,
+        summary.first.as_ref().map_or("-", |t| t.as_str()),
+        summary.last.as_ref().map_or("-", |t| t.as_str()),
+        summary.load_seconds,
+        summary.failed,
+        summary.missing_elevation,
+        summary.dew_gt_temp);
+  args.value_of("stats_out").map(|path| {
+    let mut f = fs::File::create(path).unwrap();
+    f.write_all(serde_json::to_string_pretty(&summary).unwrap().as_bytes())
+      .unwrap();
+  });
+
+  (stations, sources, num_failed)
+}
+
+// Whether the path's extension is one of `extensions`, which are lowercase.
+fn has_extension(path: &path::Path, extensions: &[String]) -> bool {
+  path.extension()
+    .and_then(|e| e.to_str())
+    .map_or(false, |e| extensions.contains(&e.to_lowercase()))
+}
+
+fn validate(stations: &Vec<WeatherStation>, num_failed: usize) {
+  println!("parsed {} stations, {} files failed",
+           stations.len(),
+           num_failed);
+  std::process::exit(if num_failed > 0 { 1 } else { 0 });
+}
+
+// Renders the frames in parallel on --threads threads and hands the stations
+// back once all of them are written.
+fn render_frames(args: &clap::ArgMatches,
+                 stations: Vec<WeatherStation>)
+                 -> Vec<WeatherStation> {
+  let directory = match args.value_of("render_dir") {
+    Some(directory) => path::PathBuf::from(directory),
+    None => return stations,
+  };
+  let start = parse_datetime(args.value_of("render_start").unwrap()).unwrap();
+  let end = parse_datetime(args.value_of("render_end").unwrap()).unwrap();
+  let frames = args.value_of("render_frames").unwrap().parse::<i32>().unwrap();
+  let latitude_min = args.value_of("render_latitude_min")
+    .unwrap()
+    .parse::<f32>()
+    .unwrap();
+  let latitude_max = args.value_of("render_latitude_max")
+    .unwrap()
+    .parse::<f32>()
+    .unwrap();
+  check_lt!(start, end);
+  check_ge!(frames, 1);
+  check_ge!(latitude_min, -90.0);
+  check_le!(latitude_max, 90.0);
+  check_lt!(latitude_min, latitude_max);
+  check_lt!(clamp_latitude(latitude_min), clamp_latitude(latitude_max));
+
+  let n_threads = args.value_of("threads").unwrap().parse::<usize>().unwrap();
+  let pool = threadpool::ThreadPool::new(n_threads);
+  let stations = sync::Arc::new(stations);
+  let (tx, rx) = sync::mpsc::channel();
+
+  // With --render-delta each job draws a run of consecutive frames, each but
+  // the first from the one before, and otherwise a single frame.
+  let delta = args.is_present("render_delta");
+  let run = if delta {
+    (frames + n_threads as i32 - 1) / n_threads as i32
+  } else {
+    1
+  };
+  let jobs = (frames + run - 1) / run;
+
+  // Frame i covers [start + i * step, start + (i + 1) * step).
+  let step = (end - start) / frames;
+  for job in 0..jobs {
+    let stations = stations.clone();
+    let tx = tx.clone();
+    let directory = directory.clone();
+    pool.execute(move || {
+      let mut previous = None;
+      for i in job * run..cmp::min((job + 1) * run, frames) {
+        let frame = render_frame(&stations,
+                                 latitude_min,
+                                 latitude_max,
+                                 start + step * i,
+                                 start + step * (i + 1),
+                                 previous);
+        let image_path = directory.join(format!("weather-{:04}.png", i));
+        let _ = frame.img.save(&image_path);
+        previous = if delta { Some(frame) } else { None };
+      }
+      // Dropped before reporting back, so the last job done leaves a single
+      // reference that can be unwrapped.
+      drop(stations);
+      tx.send(()).unwrap();
+    });
+  }
+  check_eq!(rx.iter().take(jobs as usize).count(), jobs as usize);
+  sync::Arc::try_unwrap(stations).ok().expect("render jobs still running")
+}
+
+fn convert(args: &clap::ArgMatches, stations: &Vec<WeatherStation>) {
+  args.value_of("netcdf_out").map(|out| {
+    export_netcdf(stations,
+                  parse_datetime(args.value_of("netcdf_start").unwrap())
+                    .unwrap(),
+                  parse_datetime(args.value_of("netcdf_end").unwrap())
+                    .unwrap(),
+                  args.value_of("netcdf_resolution")
+                    .unwrap()
+                    .parse::<f32>()
+                    .unwrap(),
+                  args.value_of("netcdf_max_distance")
+                    .unwrap()
+                    .parse::<f32>()
+                    .unwrap(),
+                  args.value_of("netcdf_min_neighbors")
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap(),
+                  path::Path::new(out))
+      .unwrap();
+  });
+}
+
+// The store serve() starts with, or swaps in for `previous` once
+// --load-in-background is done.
+fn build_store(args: &clap::ArgMatches,
+               stations: Vec<WeatherStation>,
+               sources: Vec<String>,
+               version: u64,
+               previous: Option<&store::StationStore>)
+               -> store::StationStore {
+  let mut store = store::StationStore::new(stations, version);
+  if let Some(previous) = previous {
+    store.inherit_updated(previous);
+  }
+  store.sources = sources;
+  store.database = args.value_of("sqlite").map(|filename| {
+    source::SqliteSource::open(path::Path::new(filename)).unwrap()
+  });
+  if let Some(max_zoom) = args.value_of("precompute_tiles") {
+    store.precompute_tiles(max_zoom.parse::<u32>().unwrap());
+    info!("precomputed tile positions up to zoom {}", max_zoom);
+  }
+  store
+}
+
+// Starts serving an empty store right away and fills it in from a
+// background thread as the input loads.
+fn serve_while_loading(args: clap::ArgMatches<'static>) {
+  let mut store = store::StationStore::new(vec![], 0);
+  store.loading = true;
+  let store = store.shared();
+  let loader_args = args.clone();
+  let loader_store = store.clone();
+  std::thread::spawn(move || {
+    let (stations, sources, _) = load_stations(&loader_args,
+                                               Some(&loader_store));
+    if cancel::requested() {
+      warn!("loading cancelled, serving the stations loaded so far");
+    }
+    // Readers keep the partial store while the complete one is built.
+    let complete = {
+      let partial = loader_store.read().unwrap();
+      build_store(&loader_args,
+                  stations,
+                  sources,
+                  partial.version + 1,
+                  Some(&*partial))
+    };
+    *loader_store.write().unwrap() = complete;
+    info!("loading done, serving all stations");
+  });
+  serve(&args, store);
+}
+
+// The last `hours` up to the fixed now if there is one, and otherwise up to
+// the latest measurement.
+fn default_window(hours: f64,
+                  clock: &Clock,
+                  last: Option<DateTime<UTC>>)
+                  -> (DateTime<UTC>, DateTime<UTC>) {
+  // Windows exclude their end, which would leave out the latest measurement.
+  let end = clock.fixed
+    .or(last.map(|last| last + time::Duration::seconds(1)))
+    .unwrap_or_else(UTC::now);
+  (end - time::Duration::seconds((hours * 3600.0) as i64), end)
+}
+
+fn serve(args: &clap::ArgMatches, store: store::SharedStore) {
+  let n_threads = args.value_of("threads").unwrap().parse::<usize>().unwrap();
+  let last = store.read()
+    .unwrap()
+    .stations
+    .iter()
+    .filter_map(|s| s.measurements.last())
+    .map(|m| m.datetime)
+    .max();
+  json::set_pretty(args.is_present("pretty_json"));
+  let tile_cache = cache::TileCache::new(
+    args.value_of("tile_cache_size").unwrap().parse::<usize>().unwrap(),
+    std::time::Duration::from_secs(
+      args.value_of("tile_cache_ttl").unwrap().parse::<u64>().unwrap()));
+
+  let mut render_defaults = RenderOptions::default();
+  render_defaults.marker =
+    MarkerShape::parse(args.value_of("marker").unwrap()).unwrap();
+  render_defaults.background =
+    parse_color(args.value_of("tile_background").unwrap()).unwrap();
+  render_defaults.calm_color =
+    parse_color(args.value_of("calm_color").unwrap()).unwrap();
+  render_defaults.variable_color =
+    parse_color(args.value_of("variable_color").unwrap()).unwrap();
+  render_defaults.baseline = args.value_of("baseline").map(|path| {
+    let baseline = grid::Grid::load_csv(path).unwrap();
+    let baseline: &'static grid::Grid =
+      unsafe { &*Box::into_raw(Box::new(baseline)) };
+    baseline
+  });
+  render_defaults.highlights = args.value_of("highlight").map(|path| {
+    let scale =
+      args.value_of("highlight_scale").unwrap().parse::<u32>().unwrap();
+    let highlights = highlight::Highlights::load(path, scale).unwrap();
+    let highlights: &'static highlight::Highlights =
+      unsafe { &*Box::into_raw(Box::new(highlights)) };
+    highlights
+  });
+  render_defaults.clock = Clock {
+    fixed: args.value_of("fixed_now").map(|now| parse_datetime(now).unwrap()),
+  };
+  if let Some(hours) = args.value_of("default_window") {
+    let hours = hours.parse::<f64>().unwrap();
+    let (start, end) = default_window(hours, &render_defaults.clock, last);
+    render_defaults.default_window = Some((start, end));
+    info!("drawing {} to {} by default",
+          start.to_rfc3339(),
+          end.to_rfc3339());
+  }
+  render_defaults.attribution = args.value_of("tile_attribution").map(|text| {
+    Attribution {
+      text: unsafe { &*Box::into_raw(String::from(text).into_boxed_str()) },
+      every_tile: args.value_of("attribution_tiles") == Some("all"),
+    }
+  });
+
+  if let Some(dir) = args.value_of("prerender_tiles") {
+    let max_zoom =
+      args.value_of("prerender_maxzoom").unwrap().parse::<u32>().unwrap();
+    let num_tiles = prerender_tiles(store.clone(),
+                                    render_defaults,
+                                    max_zoom,
+                                    path::Path::new(dir),
+                                    n_threads)
+      .unwrap();
+    info!("pre-rendered {} tiles to {}", num_tiles, dir);
+  }
+
+  let render_pool =
+    RenderPool(sync::Mutex::new(threadpool::ThreadPool::new(n_threads)));
+
+  let rate_limiter =
+    ratelimit::RateLimiter::new(args.value_of("tile_rate")
+                                  .map(|r| r.parse::<f64>().unwrap()),
+                                args.value_of("tile_burst")
+                                  .unwrap()
+                                  .parse::<f64>()
+                                  .unwrap(),
+                                args.is_present("trust_proxy"));
+
+  rocket::ignite()
+    .mount("/",
+           routes![index,
+                    static_file,
+                    map_tile,
+                    map_tile_default,
+                    map_tile_on,
+                    map_tile_on_default,
+                    map_tile_data_uri,
+                    map_tile_data_uri_default,
+                    map_tile_svg,
+                    map_tile_svg_default,
+                    map_tiles,
+                    map_tiles_default,
+                    tile_stats,
+                    tile_stats_default,
+                    tile_changed,
+                    tile_timeseries,
+                    health,
+                    color_scale,
+                    color_scale_default,
+                    render_3857,
+                    field_tiff,
+                    render_stack,
+                    station_sparkline,
+                    station_sparkline_default,
+                    api::measurement_histogram_route,
+                    api::measurement_histogram_default,
+                    api::gaps,
+                    api::field_range,
+                    api::station_aggregate,
+                    api::station_aggregate_default,
+                    api::station_measurements,
+                    api::station_measurements_default,
+                    api::station_extremes,
+                    api::station_extremes_default,
+                    api::station_parquet,
+                    api::station_list,
+                    api::station_list_default,
+                    api::alerts,
+                    openapi::openapi])
+    .manage(store)
+    .manage(render_defaults)
+    .manage(StaticDir(path::PathBuf::from(args.value_of("static_dir")
+      .unwrap())))
+    .manage(tile_cache)
+    .manage(MaxRenderPixels(args.value_of("max_render_pixels")
+      .unwrap()
+      .parse::<u64>()
+      .unwrap()))
+    .manage(api::FieldRanges::new())
+    .manage(render_pool)
+    .manage(rate_limiter)
+    .launch();
+}
+
+fn main() {
+  let matches = clap::App::new("tenki")
+    .subcommand(clap::SubCommand::with_name("serve")
+      .about("Serves map tiles and the API")
+      .args(&input_args())
+      .args(&serve_args()))
+    .subcommand(clap::SubCommand::with_name("render")
+      .about("Renders frames of all stations to --render_dir")
+      .args(&input_args())
+      .args(&render_args()))
+    .subcommand(clap::SubCommand::with_name("validate")
+      .about("Only parses the input and reports errors")
+      .args(&input_args()))
+    .subcommand(clap::SubCommand::with_name("convert")
+      .about("Exports gridded fields as NetCDF")
+      .args(&input_args())
+      .args(&convert_args()))
+    // Deprecated: the flat flags from before subcommands existed.
+    .arg(clap::Arg::with_name("validate").long("validate"))
+    .args(&input_args())
+    .args(&render_args())
+    .args(&convert_args())
+    .args(&serve_args())
+    .get_matches();
+
+  let (command, args) = match matches.subcommand() {
+    (command, Some(args)) => (command, args),
+    _ => {
+      warn!("flags without a subcommand are deprecated, use tenki serve, \
+             render, validate or convert");
+      ("", &matches)
+    }
+  };
+
+  if let Err(message) = check_args(command, args) {
+    clap::Error::with_description(&format!("{}\n\n{}", message, ALLOWED_ARGS),
+                                  clap::ErrorKind::ArgumentConflict)
+      .exit();
+  }
+
+  if args.is_present("verbose") {
+    logging::set_level(logging::Level::Debug);
+  } else if args.is_present("quiet") {
+    logging::set_level(logging::Level::Warn);
+  }
+
+  cancel::install();
+  if command == "serve" && args.is_present("load_in_background") {
+    return serve_while_loading(args.clone());
+  }
+  let (stations, sources, num_failed) = load_stations(args, None);
+  if cancel::requested() {
+    // The partial load was summarized already.
+    std::process::exit(130);
+  }
+  match command {
+    "serve" => {
+      serve(args, build_store(args, stations, sources, 0, None).shared())
+    }
+    "render" => {
+      render_frames(args, stations);
+    }
+    "validate" => validate(&stations, num_failed),
+    "convert" => convert(args, &stations),
+    _ => {
+      if args.is_present("validate") {
+        validate(&stations, num_failed);
+      }
+      let stations = render_frames(args, stations);
+      convert(args, &stations);
+      serve(args, build_store(args, stations, sources, 0, None).shared());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Read;
+  use testing;
+
+  #[test]
+  fn interpolates_between_neighbors() {
+    let measurements = testing::series(&[Some(10.0), None, Some(20.0)]);
+    let value = interpolate_at(&measurements,
+                               testing::time(1),
+                               |m| m.air_temperature);
+    assert_eq!(value, Some(15.0));
+  }
+
+  // The hours since testing::time(0) of measurements.
+  fn hours_of(measurements: &[WeatherMeasurement]) -> Vec<i64> {
+    measurements.iter()
+      .map(|m| (m.datetime - testing::time(0)).num_hours())
+      .collect()
+  }
+
+  #[test]
+  fn window_includes_its_end_only_when_asked() {
+    let mut measurements = testing::series(&[Some(0.0),
+                                             Some(1.0),
+                                             Some(2.0),
+                                             Some(3.0),
+                                             Some(4.0)]);
+    // Two measurements exactly on the end.
+    measurements.insert(3, testing::measurement(testing::time(3), None));
+    let (start, end) = (testing::time(1), testing::time(3));
+    assert_eq!(hours_of(measurements_within(&measurements, start, end, false)),
+               vec![1, 2]);
+    assert_eq!(hours_of(measurements_within(&measurements, start, end, true)),
+               vec![1, 2, 3, 3]);
+    assert_eq!(hours_of(measurements_in(&measurements, start, end)),
+               vec![1, 2]);
+    // An empty window still has its end when inclusive.
+    assert!(measurements_within(&measurements, start, start, false).is_empty());
+    assert_eq!(hours_of(measurements_within(&measurements, start, start, true)),
+               vec![1]);
+  }
+
+  #[test]
+  fn windows_slice_empty_partial_and_full_ranges() {
+    let measurements =
+      testing::series(&[Some(0.0), Some(1.0), Some(2.0), Some(3.0)]);
+    let hours = |start: i64, end: i64| {
+      hours_of(measurements_in(&measurements,
+                               testing::time(start),
+                               testing::time(end)))
+    };
+    // Before, after and between the measurements.
+    assert!(hours(-5, -1).is_empty());
+    assert!(hours(4, 10).is_empty());
+    assert!(hours(2, 2).is_empty());
+    assert!(measurements_in(&[], testing::time(0), testing::time(4))
+      .is_empty());
+    // Overlapping either end.
+    assert_eq!(hours(-5, 2), vec![0, 1]);
+    assert_eq!(hours(2, 10), vec![2, 3]);
+    // Covering all of them.
+    assert_eq!(hours(0, 4), vec![0, 1, 2, 3]);
+    assert_eq!(hours(-5, 10), vec![0, 1, 2, 3]);
+    assert_eq!(hours_of(measurements_within(&measurements,
+                                            testing::time(0),
+                                            testing::time(3),
+                                            true)),
+               vec![0, 1, 2, 3]);
+  }
+
+  #[test]
+  fn tms_rows_mirror_xyz_rows() {
+    let params = TileParams {
+      scheme: Some(String::from("tms")),
+      ..Default::default()
+    };
+    assert_eq!(params.xyz_y(2, 1, 0), Ok(3));
+    let flipped = tile_viewport(2, 1, 3).unwrap();
+    let top = tile_viewport(2, 1, 0).unwrap();
+    assert_eq!(flipped.longitude_min, top.longitude_min);
+    assert_eq!(flipped.longitude_max, top.longitude_max);
+    assert!((flipped.latitude_min + top.latitude_max).abs() < 1e-3);
+    assert!((flipped.latitude_max + top.latitude_min).abs() < 1e-3);
+  }
+
+  #[test]
+  fn rejects_zoom_beyond_max() {
+    let params = TileParams::default();
+    assert!(params.xyz_y(MAX_ZOOM, 0, 0).is_ok());
+    assert!(params.xyz_y(32, 0, 0).is_err());
+  }
+
+  #[test]
+  fn rejects_tiles_outside_the_grid() {
+    for scheme in &["xyz", "tms"] {
+      let params = TileParams {
+        scheme: Some(String::from(*scheme)),
+        ..Default::default()
+      };
+      assert!(params.xyz_y(0, 0, 1).is_err());
+      assert!(params.xyz_y(0, 1, 0).is_err());
+      assert!(params.xyz_y(2, 3, 3).is_ok());
+      assert!(params.xyz_y(2, u32::max_value(), 0).is_err());
+    }
+    assert!(tile_viewport(0, 0, 1).is_none());
+    assert!(tile_viewport(1, u32::max_value(), u32::max_value()).is_none());
+
+    let store = store::StationStore::new(vec![], 0);
+    let tile_cache =
+      cache::TileCache::new(10, std::time::Duration::from_secs(60));
+    match get_tile(0,
+                   0,
+                   1,
+                   &TileParams::default(),
+                   &store,
+                   &RenderOptions::default(),
+                   &tile_cache) {
+      Err(RenderError::BadRequest(_)) => {}
+      _ => panic!("rendered tile 0/0/1"),
+    }
+  }
+
+  #[test]
+  fn missing_direction_is_not_variable_wind() {
+    let with_wind = |hour: i64, wind: &str| {
+      let record = testing::ish_record(hour, Some(5.0), "");
+      format!("{}{}{}", &record[..60], wind, &record[70..])
+    };
+    let input = format!("{}\n{}\n{}\n",
+                        with_wind(0, "9991N00501"),
+                        with_wind(1, "9991V00501"),
+                        with_wind(2, "9999N99999"));
+    let station = parse("010000-99999-2016", &mut io::Cursor::new(input), 100)
+      .unwrap();
+    assert_eq!(station.measurements[0].wind,
+               Some(WindMeasurement::Normal {
+                 speed: 5.0,
+                 direction: None,
+               }));
+    assert_eq!(station.measurements[1].wind, Some(WindMeasurement::Variable));
+    assert_eq!(station.measurements[2].wind, None);
+  }
+
+  #[test]
+  fn mmap_parses_like_a_reader() {
+    let records = [testing::ish_record(0, Some(12.3), ""),
+                   testing::ish_record(1, None, ""),
+                   testing::ish_record(2, Some(-4.0), "ADDMA1")];
+    let filename = testing::scratch_file("mmap",
+                                         "010000-99999-2016",
+                                         records.join("\n").as_bytes());
+    let filename = filename.to_str().unwrap();
+    let plain = parse_file(filename, 100, false).unwrap();
+    let mapped = parse_file(filename, 100, true).unwrap();
+    assert_eq!(plain.measurements.len(), 2);
+    assert_eq!((mapped.latitude, mapped.longitude),
+               (plain.latitude, plain.longitude));
+    assert_eq!(format!("{:?}", mapped.measurements),
+               format!("{:?}", plain.measurements));
+  }
+
+  #[test]
+  fn rejects_channel_bounds_below_one() {
+    let args = |bound: &str| testing::args(&["--channel-bound", bound]);
+    assert!(check_args("", &args("4")).is_ok());
+    assert!(check_args("", &args("0")).is_err());
+    assert!(check_args("", &args("many")).is_err());
+  }
+
+  #[test]
+  fn loads_every_one_of_many_small_files() {
+    const NUM_FILES: usize = 50;
+    let dir = testing::scratch_dir("small-files");
+    let record = testing::ish_record(0, Some(5.0), "");
+    let mut expected = vec![];
+    for i in 0..NUM_FILES {
+      let usaf = format!("{:06}", 100000 + i);
+      fs::File::create(dir.join(format!("{}-99999-2016.gz", usaf)))
+        .unwrap()
+        .write_all(format!("{}{}{}", &record[..4], usaf, &record[10..])
+          .as_bytes())
+        .unwrap();
+      expected.push(usaf);
+    }
+    // A bound well below the number of files, so workers block on the loader.
+    let args = testing::args(&["--directory",
+                               dir.to_str().unwrap(),
+                               "--channel-bound",
+                               "2"]);
+    let (stations, _, num_failed) = load_stations(&args, None);
+    let mut usafs = stations.iter().map(|s| s.usaf.clone()).collect::<Vec<_>>();
+    usafs.sort();
+    assert_eq!(usafs, expected);
+    assert_eq!(num_failed, 0);
+  }
+
+  #[test]
+  fn mercator_meters_round_trip() {
+    for &(longitude, latitude) in &[(0.0, 0.0), (13.4, 52.5), (-122.3, -47.6)] {
+      let x = WEB_MERCATOR_RADIUS * (longitude as f64).to_radians();
+      let y = WEB_MERCATOR_RADIUS * mercator(latitude) as f64;
+      let (back_longitude, back_latitude) = mercator_meters_to_degrees(x, y);
+      assert!((back_longitude - longitude).abs() < 1e-4);
+      assert!((back_latitude - latitude).abs() < 1e-4);
+    }
+  }
+
+  #[test]
+  fn marker_shapes_paint_their_pattern() {
+    // The painted pixels of a size 5 marker, one row of its box per string.
+    let pattern = |shape: &str| {
+      let shape = MarkerShape::parse(shape).unwrap();
+      (0..5)
+        .map(|dy| {
+          (0..5)
+            .map(|dx| if shape.covers(dx, dy, 5) { '#' } else { '.' })
+            .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(pattern("square"),
+               vec!["#####", "#####", "#####", "#####", "#####"]);
+    assert_eq!(pattern("circle"),
+               vec![".###.", "#####", "#####", "#####", ".###."]);
+    assert_eq!(pattern("cross"),
+               vec!["..#..", "..#..", "#####", "..#..", "..#.."]);
+  }
+
+  #[test]
+  fn smoothing_averages_out_noise() {
+    let measurements =
+      testing::series(&[Some(10.0), Some(14.0), Some(6.0), Some(14.0),
+                        Some(6.0)]);
+    let smoothed = mean_around(&measurements,
+                               testing::time(2),
+                               time::Duration::hours(2),
+                               |m| m.air_temperature);
+    // Hours 1 and 2, as the end of the window is exclusive.
+    assert_eq!(smoothed, Some(10.0));
+  }
+
+  #[test]
+  fn static_paths_stay_in_the_static_dir() {
+    let dir = path::Path::new("static");
+    assert_eq!(static_path(dir, "app.js"),
+               Some(path::PathBuf::from("static/app.js")));
+    assert_eq!(static_path(dir, ".."), None);
+    assert_eq!(static_path(dir, "../Cargo.toml"), None);
+    assert_eq!(static_path(dir, "..\\Cargo.toml"), None);
+  }
+
+  #[test]
+  fn prerenders_one_tile_at_zoom_zero() {
+    let stations = vec![testing::station("010000",
+                                         10.0,
+                                         10.0,
+                                         testing::series(&[Some(5.0)]))];
+    let dir = testing::scratch_dir("prerender");
+    let written = prerender_tiles(store::StationStore::new(stations, 0)
+                                    .shared(),
+                                  RenderOptions::default(),
+                                  0,
+                                  &dir,
+                                  2)
+      .unwrap();
+    assert_eq!(written, 1);
+    assert!(dir.join("0").join("0").join("0.png").is_file());
+  }
+
+  #[test]
+  fn empty_pixels_take_the_background_color() {
+    let params = TileParams {
+      bg: Some(String::from("#336699")),
+      ..Default::default()
+    };
+    let options = params.render_options(&RenderOptions::default()).unwrap();
+    let store = store::StationStore::new(vec![], 0);
+    let (img, _) = draw_tile(&store, 0, 0, 0, &options);
+    assert_eq!(*img.get_pixel(0, 0), image::Rgb([0x33, 0x66, 0x99]));
+  }
+
+  #[test]
+  fn bivariate_colors_stations_with_both_fields() {
+    let mut measurements = testing::series(&[Some(20.0)]);
+    measurements[0].air_pressure = Some(1000.0);
+    let store =
+      store::StationStore::new(vec![testing::station("010000",
+                                                     10.0,
+                                                     10.0,
+                                                     measurements)],
+                               0);
+    let params = TileParams {
+      mode: Some(String::from("bivariate")),
+      a: Some(String::from("temperature")),
+      b: Some(String::from("pressure")),
+      ..Default::default()
+    };
+    let options = params.render_options(&RenderOptions::default()).unwrap();
+    let (img, stats) = draw_tile(&store, 0, 0, 0, &options);
+    assert_eq!(stats.stations_drawn, 1);
+    let expected =
+      bivariate_color(Field::Temperature, 20.0, Field::Pressure, 1000.0);
+    assert!(img.pixels().any(|p| *p == expected));
+  }
+
+  #[test]
+  fn scalar_fields_color_the_same_station_differently() {
+    let mut measurements = testing::series(&[Some(20.0)]);
+    measurements[0].dew_point = Some(5.0);
+    measurements[0].air_pressure = Some(1000.0);
+    let store =
+      store::StationStore::new(vec![testing::station("010000",
+                                                     10.0,
+                                                     10.0,
+                                                     measurements)],
+                               0);
+    let drawn = |field: &str, value: f32| {
+      let params = TileParams {
+        field: Some(String::from(field)),
+        ..Default::default()
+      };
+      let options = params.render_options(&RenderOptions::default()).unwrap();
+      let (img, stats) = draw_tile(&store, 0, 0, 0, &options);
+      assert_eq!(stats.stations_drawn, 1);
+      let expected =
+        palette_color(Field::parse(field).unwrap().normalize(value));
+      assert!(img.pixels().any(|p| *p == expected), "{}", field);
+      expected
+    };
+    let temperature = drawn("temperature", 20.0);
+    let dew_point = drawn("dew_point", 5.0);
+    let pressure = drawn("pressure", 1000.0);
+    assert!(temperature != dew_point);
+    assert!(temperature != pressure);
+    assert!(dew_point != pressure);
+  }
+
+  #[test]
+  fn mercator_bounds_clamp_the_poles() {
+    let (min, max) = mercator_bounds(-90.0, 90.0).unwrap();
+    assert!(min.is_finite() && max.is_finite());
+    assert_eq!((min, max),
+               (mercator(-MERCATOR_MAX_LATITUDE),
+                mercator(MERCATOR_MAX_LATITUDE)));
+  }
+
+  #[test]
+  fn mercator_bounds_reject_empty_ranges() {
+    assert_eq!(mercator_bounds(10.0, 10.0), None);
+    assert_eq!(mercator_bounds(20.0, 10.0), None);
+    assert_eq!(mercator_bounds(86.0, 90.0), None);
+  }
+
+  #[test]
+  fn polar_tiles_have_a_viewport_at_max_zoom() {
+    let last = (1 << MAX_ZOOM) - 1;
+    let top = tile_viewport(MAX_ZOOM, 0, 0).unwrap();
+    let bottom = tile_viewport(MAX_ZOOM, 0, last).unwrap();
+    assert!(top.mercator_min < top.mercator_max);
+    assert!(bottom.mercator_min < bottom.mercator_max);
+  }
+
+  #[test]
+  fn merges_stations_sharing_an_id() {
+    // Hours 1 and 2, from a file without a position.
+    let mut measurements = testing::series(&[None, None, Some(2.0)]);
+    measurements.remove(0);
+    let first = testing::station("010000", -1000.0, -1000.0, measurements);
+    let mut second =
+      testing::station("010000", 10.0, 20.0, testing::series(&[Some(0.0)]));
+    second.elevation = Some(100);
+    let other = testing::station("020000", 0.0, 0.0, vec![]);
+
+    let merged = merge_stations(vec![first, other, second], DupPolicy::First);
+    assert_eq!(merged.len(), 2);
+    assert_eq!(merged[0].usaf, "010000");
+    assert_eq!((merged[0].latitude, merged[0].longitude), (10.0, 20.0));
+    assert_eq!(merged[0].elevation, Some(100));
+    let times = merged[0].measurements
+      .iter()
+      .map(|m| m.datetime)
+      .collect::<Vec<_>>();
+    assert_eq!(times,
+               vec![testing::time(0), testing::time(1), testing::time(2)]);
+  }
+
+  #[test]
+  fn parse_errors_name_the_line() {
+    let record = testing::ish_record(1, Some(5.0), "");
+    let bad = format!("{}+95000{}", &record[..28], &record[34..]);
+    let input = format!("{}\n{}\n", testing::ish_record(0, Some(5.0), ""), bad);
+    let error = match parse("010000-99999-2016",
+                            &mut io::Cursor::new(input),
+                            100) {
+      Ok(_) => panic!("parsed a latitude of 95"),
+      Err(error) => error,
+    };
+    assert!(error.to_string().starts_with("010000-99999-2016:2: "),
+            "{}",
+            error);
+  }
+
+  #[test]
+  fn skips_lines_with_invalid_bytes() {
+    let mut corrupt = testing::ish_record(1, Some(6.0), "").into_bytes();
+    corrupt[60] = 0xff;
+    let mut input = testing::ish_record(0, Some(5.0), "").into_bytes();
+    input.push(b'\n');
+    input.extend_from_slice(&corrupt);
+    input.push(b'\n');
+    input.extend_from_slice(testing::ish_record(2, Some(7.0), "").as_bytes());
+    let station = parse("010000-99999-2016", &mut io::Cursor::new(input), 100)
+      .unwrap();
+    let temperatures = station.measurements
+      .iter()
+      .map(|m| m.air_temperature)
+      .collect::<Vec<_>>();
+    assert_eq!(temperatures, vec![Some(5.0), Some(7.0)]);
+  }
+
+  #[test]
+  fn rejects_renders_above_the_pixel_limit() {
+    let limit = MaxRenderPixels(100 * 100);
+    let check = |size: &str| {
+      parse_size(size).and_then(|(width, height)| limit.check(width, height))
+    };
+    assert_eq!(check("100x100"), Ok(()));
+    assert!(check("101x100").is_err());
+    // The product doesn't overflow u32.
+    assert!(check("65536x65536").is_err());
+  }
+
+  #[test]
+  fn dup_policies_resolve_conflicting_duplicates() {
+    let at = |report_type: &[u8; 5], t: f32| {
+      let mut m = testing::measurement(testing::time(0), Some(t));
+      m.report_type = *report_type;
+      m
+    };
+    let duplicates =
+      vec![at(b"FM-15", 1.0), at(b"FM-12", 2.0), at(b"FM-15", 6.0)];
+    let resolved = |policy: DupPolicy| {
+      let mut measurements = duplicates.clone();
+      measurements.push(testing::measurement(testing::time(1), Some(9.0)));
+      assert_eq!(dedup_measurements(&mut measurements, policy), 2);
+      assert_eq!(measurements[1].air_temperature, Some(9.0));
+      measurements[0].air_temperature.unwrap()
+    };
+    assert_eq!(resolved(DupPolicy::First), 1.0);
+    assert_eq!(resolved(DupPolicy::Last), 6.0);
+    assert_eq!(resolved(DupPolicy::PreferSynop), 2.0);
+    assert_eq!(resolved(DupPolicy::Mean), 3.0);
+  }
+
+  #[test]
+  fn svg_tiles_have_a_circle_per_station() {
+    let stations = [(10.0, 10.0), (-30.0, 100.0), (45.0, -60.0)]
+      .iter()
+      .enumerate()
+      .map(|(i, &(latitude, longitude))| {
+        testing::station(&format!("01000{}", i),
+                         latitude,
+                         longitude,
+                         testing::series(&[Some(i as f32)]))
+      })
+      .collect();
+    let store = store::StationStore::new(stations, 0);
+    let (svg, stats) =
+      draw_tile_svg(&store, 0, 0, 0, &RenderOptions::default());
+    assert_eq!(stats.stations_drawn, 3);
+    assert_eq!(svg.matches("<circle").count(), 3);
+  }
+
+  #[test]
+  fn attribution_is_drawn_on_the_bottom_row() {
+    let mut options = RenderOptions::default();
+    options.background = image::Rgb([255, 255, 255]);
+    options.attribution = Some(Attribution {
+      text: "OSM",
+      every_tile: false,
+    });
+    let store = store::StationStore::new(vec![], 0);
+    let corner = |y: u32| {
+      let (img, _) = draw_tile(&store, 1, 0, y, &options);
+      *img.get_pixel(TILE_SIZE - 1, TILE_SIZE - 1)
+    };
+    assert_eq!(corner(1), image::Rgb([0, 0, 0]));
+    assert_eq!(corner(0), image::Rgb([255, 255, 255]));
+  }
+
+  #[test]
+  fn highlighted_stations_take_their_color() {
+    let path = testing::scratch_file("highlight",
+                                     "highlights.txt",
+                                     b"# annotated\n010000-99999 00ff00\n");
+    let highlights =
+      highlight::Highlights::load(path.to_str().unwrap(), 3).unwrap();
+    let mut options = RenderOptions::default();
+    // Options only hold 'static references, like main's.
+    options.highlights = Some(unsafe { &*Box::into_raw(Box::new(highlights)) });
+
+    let stations = vec![testing::station("010000",
+                                         0.0,
+                                         0.0,
+                                         testing::series(&[Some(5.0)])),
+                        testing::station("020000",
+                                         0.0,
+                                         0.0,
+                                         testing::series(&[Some(5.0)]))];
+    let placed = vec![(&stations[0], 10, 10), (&stations[1], 20, 20)];
+    let (start, end) = options.window();
+    let (markers, _) = place_stations(&placed, &[], start, end, &options);
+    assert_eq!(markers[0].color, image::Rgb([0, 255, 0]));
+    assert_eq!(markers[0].scale, 3);
+    assert_eq!(markers[1].color, options.mode.color(5.0, 1.0));
+    assert_eq!(markers[1].scale, 1);
+  }
+
+  #[test]
+  fn truncated_gzip_is_an_error() {
+    let records = (0..100)
+      .map(|hour| testing::ish_record(hour, Some(hour as f32 / 10.0), ""))
+      .collect::<Vec<_>>()
+      .join("\n");
+    let mut encoder =
+      flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::Default);
+    encoder.write_all(records.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let parse_gz = |bytes: &[u8]| {
+      parse_reader("010000-99999-2016.gz", io::Cursor::new(bytes), 1000)
+    };
+    assert_eq!(parse_gz(&compressed).unwrap().measurements.len(), 100);
+    assert!(parse_gz(&compressed[..compressed.len() / 2]).is_err());
+    // Misnamed plain text is read as such.
+    assert_eq!(parse_gz(records.as_bytes()).unwrap().measurements.len(), 100);
+  }
+
+  #[test]
+  fn day_window_spans_the_utc_day() {
+    assert_eq!(day_window("2016-01-02"),
+               Ok((testing::time(24), testing::time(48))));
+    assert!(day_window("2016-02-30").is_err());
+    assert!(day_window("20160102").is_err());
+  }
+
+  #[test]
+  fn solar_time_at_90_east_is_six_hours_ahead() {
+    assert_eq!(solar_offset(90.0), time::Duration::hours(6));
+    let station = testing::station("010000", 0.0, 90.0, vec![]);
+    let mut options = RenderOptions::default();
+    options.localtime = true;
+    // Local noon to 1pm is 6am to 7am UTC.
+    let (start, end, _) =
+      local_window(&station, testing::time(12), testing::time(13), &options);
+    assert_eq!((start, end), (testing::time(6), testing::time(7)));
+  }
+
+  #[test]
+  fn freshness_counts_back_from_the_fixed_now() {
+    let mut defaults = RenderOptions::default();
+    defaults.clock = Clock { fixed: Some(testing::time(10)) };
+    let params = TileParams {
+      freshness: Some(3.0),
+      ..Default::default()
+    };
+    let options = params.render_options(&defaults).unwrap();
+    assert_eq!(options.window(), (testing::time(7), testing::time(10)));
+
+    let stale = TileParams {
+      freshness: Some(0.0),
+      ..Default::default()
+    };
+    assert!(stale.render_options(&defaults).is_err());
+  }
+
+  #[test]
+  fn clip_markers_flags_values_above_the_ramp() {
+    let (_, t_max) = Field::Temperature.range();
+    let station = |usaf: &str, t: f32| {
+      testing::station(usaf, 0.0, 0.0, testing::series(&[Some(t)]))
+    };
+    let stations =
+      vec![station("010000", t_max + 5.0), station("020000", t_max - 5.0)];
+    let placed = vec![(&stations[0], 10, 10), (&stations[1], 20, 20)];
+    let clipped = |clip_markers: bool| {
+      let mut options = RenderOptions::default();
+      options.clip_markers = clip_markers;
+      let (start, end) = options.window();
+      place_stations(&placed, &[], start, end, &options)
+        .0
+        .iter()
+        .map(|marker| marker.clipped)
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(clipped(true), vec![true, false]);
+    assert_eq!(clipped(false), vec![false, false]);
+  }
+
+  #[test]
+  fn present_weather_codes_map_to_precip_types() {
+    assert_eq!(PrecipType::from_present_weather(61), Some(PrecipType::Rain));
+    assert_eq!(PrecipType::from_present_weather(71), Some(PrecipType::Snow));
+    assert_eq!(PrecipType::from_present_weather(66),
+               Some(PrecipType::Freezing));
+    assert_eq!(PrecipType::from_present_weather(68), Some(PrecipType::Mixed));
+    assert_eq!(PrecipType::from_present_weather(10), None);
+    // The first precipitation report wins, whichever MW slot it is in.
+    assert_eq!(parse_present_weather("ADDMW1101MW2711MW3611"),
+               Some(PrecipType::Snow));
+    assert_eq!(parse_present_weather("ADDAA101000091"), None);
+  }
+
+  #[test]
+  fn splits_records_at_the_declared_additional_length() {
+    for additional in &["", "ADDMA1", "ADDMW1711", "ADDAA101000091MW1101"] {
+      let record = testing::ish_record(0, Some(5.0), additional);
+      for line in &[record.clone(),
+                    format!("{}\r", record),
+                    format!("{}  ", record)] {
+        let (fixed, rest) = split_record(line).unwrap();
+        assert_eq!(fixed, &record[..105]);
+        assert_eq!(rest, *additional);
+      }
+      assert_eq!(split_record(&record[..record.len() - 1]), None);
+    }
+    // Characters beyond the declared length aren't additional data.
+    let record = testing::ish_record(0, None, "ADDMW1711");
+    let shorter = format!("0006{}", &record[4..]);
+    assert_eq!(split_record(&shorter).unwrap().1, "ADDMW1");
+
+    let records = [testing::ish_record(0, Some(5.0), ""),
+                   format!("{}\r", testing::ish_record(1, Some(6.0), "ADDMA1")),
+                   testing::ish_record(2, Some(7.0), "ADDMW1711")];
+    let station = parse("010000-99999-2016",
+                        &mut io::Cursor::new(records.join("\n")),
+                        100)
+      .unwrap();
+    let parsed = station.measurements
+      .iter()
+      .map(|m| (m.air_temperature, m.precip_type))
+      .collect::<Vec<_>>();
+    assert_eq!(parsed,
+               vec![(Some(5.0), None),
+                    (Some(6.0), None),
+                    (Some(7.0), Some(PrecipType::Snow))]);
+  }
+
+  #[test]
+  fn coverage_shows_silent_stations_in_black() {
+    let stations = vec![testing::station("010000",
+                                         0.0,
+                                         0.0,
+                                         testing::series(&[Some(1.0)])),
+                        testing::station("020000", 0.0, 0.0, vec![])];
+    let placed = vec![(&stations[0], 10, 10), (&stations[1], 20, 20)];
+    let mut options = RenderOptions::default();
+    options.mode = DrawMode::Coverage;
+    let (start, end) = options.window();
+    let (markers, stats) = place_stations(&placed, &[], start, end, &options);
+    assert_eq!(stats.stations_drawn, 1);
+    let colors = markers.iter().map(|m| m.color).collect::<Vec<_>>();
+    assert_eq!(colors,
+               vec![image::Rgb([255, 255, 255]), image::Rgb([0, 0, 0])]);
+  }
+
+  #[test]
+  fn basemap_draws_coastlines_only_near_land() {
+    let mut options = RenderOptions::default();
+    options.basemap = true;
+    let store = store::StationStore::new(vec![], 0);
+    let coast_pixels = |x: u32, y: u32| {
+      let (img, _) = draw_tile(&store, 3, x, y, &options);
+      img.pixels().filter(|&p| *p == basemap::COLOR).count()
+    };
+    // Western Europe, and the open Pacific.
+    assert!(coast_pixels(3, 2) > 0);
+    assert_eq!(coast_pixels(0, 4), 0);
+  }
+
+  // A day of hourly temperatures at a few stations, rising at different
+  // rates so that every frame differs.
+  fn render_stations() -> Vec<WeatherStation> {
+    (0..5)
+      .map(|i| {
+        let temperatures = (0..24)
+          .map(|hour| Some((i * hour) as f32 - 20.0))
+          .collect::<Vec<_>>();
+        testing::station(&format!("01000{}", i),
+                         -40.0 + 20.0 * i as f32,
+                         -100.0 + 50.0 * i as f32,
+                         testing::series(&temperatures))
+      })
+      .collect()
+  }
+
+  // The bytes of each frame render_frames writes with the given flags.
+  fn rendered_frames(test: &str, flags: &[&str]) -> Vec<Vec<u8>> {
+    let dir = testing::scratch_dir(test);
+    let mut all_flags = vec!["--render_dir",
+                             dir.to_str().unwrap(),
+                             "--render-start",
+                             "2016-01-01",
+                             "--render-end",
+                             "2016-01-02",
+                             "--render-frames",
+                             "4"];
+    all_flags.extend_from_slice(flags);
+    render_frames(&testing::args(&all_flags), render_stations());
+    (0..4)
+      .map(|i| {
+        let mut bytes = vec![];
+        fs::File::open(dir.join(format!("weather-{:04}.png", i)))
+          .unwrap()
+          .read_to_end(&mut bytes)
+          .unwrap();
+        bytes
+      })
+      .collect()
+  }
+
+  #[test]
+  fn parallel_frames_match_serial_ones() {
+    let serial = rendered_frames("frames-serial", &["--threads", "1"]);
+    let parallel = rendered_frames("frames-parallel", &["--threads", "4"]);
+    assert!(serial == parallel);
+  }
+
+  #[test]
+  fn delta_frames_match_full_renders() {
+    let full = rendered_frames("frames-full", &["--threads", "1"]);
+    let delta = rendered_frames("frames-delta",
+                                &["--threads", "1", "--render-delta"]);
+    assert!(full == delta);
+    // Two runs of two frames, each starting from a full render.
+    let runs = rendered_frames("frames-delta-runs",
+                               &["--threads", "2", "--render-delta"]);
+    assert!(full == runs);
+  }
+
+  #[test]
+  fn spatial_fill_colors_stations_from_neighbors() {
+    let stations = vec![testing::station("010000", 10.0, 10.0, vec![]),
+                        testing::station("020000",
+                                         10.0,
+                                         10.5,
+                                         testing::series(&[Some(30.0)]))];
+    let store = store::StationStore::new(stations, 0);
+    let params = TileParams {
+      spatial_fill: Some(100.0),
+      ..Default::default()
+    };
+    let options = params.render_options(&RenderOptions::default()).unwrap();
+    let viewport = tile_viewport(0, 0, 0).unwrap();
+    let (placed, neighbors) =
+      tile_stations(&store, 0, 0, 0, &viewport, &options);
+    let (start, end) = options.window();
+    let (markers, _) =
+      place_stations(&placed, &neighbors, start, end, &options);
+    let filled = placed.iter()
+      .position(|&(station, _, _)| station.usaf == "010000")
+      .unwrap();
+    assert_eq!(markers[filled].color, options.mode.color(30.0, 1.0));
+
+    let too_far = TileParams {
+      spatial_fill: Some(MAX_SPATIAL_FILL_KM + 1.0),
+      ..Default::default()
+    };
+    assert!(too_far.render_options(&RenderOptions::default()).is_err());
+  }
+
+  #[test]
+  fn loads_only_files_with_matching_extensions() {
+    let dir = testing::scratch_dir("extensions");
+    let write = |name: &str, contents: &str| {
+      fs::File::create(dir.join(name))
+        .unwrap()
+        .write_all(contents.as_bytes())
+        .unwrap();
+    };
+    let record = testing::ish_record(0, Some(5.0), "");
+    // Station files are named after the station they contain.
+    write("010000-99999-2016.gz", &record);
+    write("020000-99999-2016.TXT", &record.replace("010000", "020000"));
+    write("README", "not a weather station");
+    write("010000-99999-2016.gz.md5",
+          "d41d8cd98f00b204e9800998ecf8427e  010000-99999-2016.gz");
+    let directory = dir.to_str().unwrap();
+
+    let (stations, _, num_failed) =
+      load_stations(&testing::args(&["--directory", directory]), None);
+    assert_eq!(stations.len(), 1);
+    assert_eq!(num_failed, 0);
+
+    let args = testing::args(&["--directory",
+                               directory,
+                               "--extensions",
+                               "gz,txt"]);
+    let (stations, _, num_failed) = load_stations(&args, None);
+    let mut usafs = stations.iter().map(|s| s.usaf.clone()).collect::<Vec<_>>();
+    usafs.sort();
+    assert_eq!(usafs, vec!["010000", "020000"]);
+    assert_eq!(num_failed, 0);
+  }
+
+  #[test]
+  fn color_scale_stops_match_drawn_colors() {
+    for &(mode, gamma) in &[("temperature", 1.0),
+                            ("temperature", 2.0),
+                            ("anomaly", 1.0)] {
+      let mut options = RenderOptions::default();
+      options.mode = DrawMode::parse(mode).unwrap();
+      options.gamma = gamma;
+      let scale = color_scale_of(&options,
+                                 units::TemperatureUnit::Celsius,
+                                 units::WindUnit::MetersPerSecond)
+        .unwrap();
+      assert_eq!(scale.stops.len(), COLOR_STOPS);
+      assert_eq!(scale.stops[0].value, scale.min);
+      assert_eq!(scale.stops[COLOR_STOPS - 1].value, scale.max);
+      for stop in &scale.stops {
+        assert_eq!(stop.color, svg::hex(options.mode.color(stop.value, gamma)));
+      }
+      // Values beyond the ends take the end colors.
+      assert_eq!(svg::hex(options.mode.color(scale.min - 10.0, gamma)),
+                 scale.stops[0].color);
+      assert_eq!(svg::hex(options.mode.color(scale.max + 10.0, gamma)),
+                 scale.stops[COLOR_STOPS - 1].color);
+    }
+
+    let mut options = RenderOptions::default();
+    options.mode = DrawMode::Coverage;
+    assert!(color_scale_of(&options,
+                           units::TemperatureUnit::Celsius,
+                           units::WindUnit::MetersPerSecond)
+      .is_none());
+  }
+
+  #[test]
+  fn gamma_shifts_the_midpoint_color() {
+    let midpoint_color = |gamma: &str| {
+      let params = TileParams {
+        gamma: Some(gamma.parse().unwrap()),
+        ..Default::default()
+      };
+      let options = params.render_options(&RenderOptions::default()).unwrap();
+      let (min, max) = options.mode.ramp().unwrap();
+      (options.mode.color((min + max) / 2.0, options.gamma),
+       options.mode.color(max, options.gamma))
+    };
+    let (linear, linear_max) = midpoint_color("1");
+    assert_eq!(linear, palette_color(0.5));
+    let (darker, darker_max) = midpoint_color("2");
+    assert_eq!(darker, palette_color(0.25));
+    let (lighter, _) = midpoint_color("0.5");
+    assert!(darker.data[0] < linear.data[0]);
+    assert!(lighter.data[0] > linear.data[0]);
+    // The ends of the ramp stay put.
+    assert_eq!(darker_max, linear_max);
+
+    let params = TileParams {
+      gamma: Some(0.0),
+      ..Default::default()
+    };
+    assert!(params.render_options(&RenderOptions::default()).is_err());
+  }
+
+  #[test]
+  fn default_window_narrows_to_the_configured_hours() {
+    let station = testing::station("010000",
+                                   0.0,
+                                   0.0,
+                                   testing::series(&[Some(1.0); 72]));
+    let last = station.measurements.last().map(|m| m.datetime);
+    let mut defaults = RenderOptions::default();
+    defaults.default_window = Some(default_window(24.0, &defaults.clock, last));
+    let options = TileParams::default().render_options(&defaults).unwrap();
+    let (start, end) = options.window();
+    let drawn = station.measurements
+      .iter()
+      .filter(|m| m.datetime >= start && m.datetime < end)
+      .map(|m| m.datetime)
+      .collect::<Vec<_>>();
+    assert_eq!(drawn.len(), 24);
+    assert_eq!(drawn.last(), last.as_ref());
+
+    // A fixed now takes precedence over the latest measurement.
+    defaults.clock.fixed = Some(testing::time(12));
+    assert_eq!(default_window(6.0, &defaults.clock, last),
+               (testing::time(6), testing::time(12)));
+
+    // Requests still pick their own window.
+    let mut options = defaults;
+    options.window = Some(day_window("2016-01-02").unwrap());
+    assert_eq!(options.window(), (testing::time(24), testing::time(48)));
+  }
+
+  #[test]
+  fn stack_channels_hold_normalized_fields() {
+    let mut measurements = testing::series(&[Some(5.0), Some(5.0)]);
+    measurements[0].wind = Some(WindMeasurement::Calm);
+    measurements[1].wind = Some(WindMeasurement::observed(20.0, Some(90)));
+    let stations = vec![testing::station("010000", 0.0, -5.0, measurements)];
+    // Pixel centers at longitudes -15, -5, 5 and 15.
+    let bbox = api::BoundingBox::parse("-20,-1,20,1").unwrap();
+    let (start, end) = RenderOptions::default().window();
+    let img = stack_image(&stations, &bbox, 4, 1, start, end);
+    // 5 °C is the middle of the temperature range, there is no pressure,
+    // and the mean wind speed of 10 m/s is the middle of its range.
+    assert_eq!(img.get_pixel(1, 0).data, [128, 0, 128]);
+    // Too far from the station to interpolate.
+    for &x in &[0, 2, 3] {
+      assert_eq!(img.get_pixel(x, 0).data, [0, 0, 0]);
+    }
+  }
+
+  #[test]
+  fn timeseries_interpolate_on_an_even_grid() {
+    let measurements =
+      testing::series(&[Some(0.0), None, Some(10.0), Some(4.0)]);
+    let station = testing::station("010000", 52.5, 13.4, measurements);
+    let store = store::StationStore::new(vec![station], 0);
+    let params = |steps: usize, unit: Option<&str>| {
+      TimeseriesParams {
+        start: testing::time(0).to_rfc3339(),
+        end: testing::time(4).to_rfc3339(),
+        steps: steps,
+        unit: unit.map(String::from),
+      }
+    };
+
+    let series = timeseries_of(&store, 0, 0, 0, &params(9, None)).unwrap();
+    assert_eq!(series.times.len(), 9);
+    assert_eq!(series.times[1],
+               (testing::time(0) + time::Duration::minutes(30)).to_rfc3339());
+    assert_eq!(series.times[8], testing::time(4).to_rfc3339());
+    assert_eq!(series.stations.len(), 1);
+    // Across the missing value at 1:00, then nothing after the last one.
+    assert_eq!(series.stations[0].temperatures,
+               vec![Some(0.0),
+                    Some(2.5),
+                    Some(5.0),
+                    Some(7.5),
+                    Some(10.0),
+                    Some(7.0),
+                    Some(4.0),
+                    None,
+                    None]);
+    assert!(!series.truncated);
+
+    let fahrenheit =
+      timeseries_of(&store, 0, 0, 0, &params(3, Some("f"))).unwrap();
+    assert_eq!(fahrenheit.stations[0].temperatures,
+               vec![Some(32.0), Some(50.0), None]);
+
+    assert!(timeseries_of(&store, 0, 0, 0, &params(0, None)).is_err());
+    assert!(timeseries_of(&store, 0, 0, 1, &params(9, None)).is_err());
+    assert!(timeseries_of(&store, MAX_ZOOM + 1, 0, 0, &params(9, None))
+      .is_err());
+    assert!(timeseries_of(&store, 0, 0, 0, &params(MAX_TIMESERIES_STEPS + 1,
+                                                   None))
+      .is_err());
+  }
+
+  #[test]
+  fn hour_filter_selects_observations_near_the_hour() {
+    let params = TileParams {
+      hour: Some(0),
+      hour_window: Some(1.0),
+      ..Default::default()
+    };
+    let options = params.render_options(&RenderOptions::default()).unwrap();
+    let temperatures = (0..30).map(|h| Some(h as f32)).collect::<Vec<_>>();
+    let selected = testing::series(&temperatures)
+      .iter()
+      .filter(|m| options.selects(m))
+      .map(|m| m.air_temperature.unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(selected, vec![0.0, 1.0, 23.0, 24.0, 25.0]);
+  }
+
+  #[test]
+  fn batched_tiles_round_trip() {
+    use byteorder::ReadBytesExt;
+
+    let store = store::StationStore::new(render_stations(), 0);
+    let options = RenderOptions::default();
+    let tiles = vec![render_tile_png(&store, 1, 0, 0, &options).unwrap(),
+                     render_tile_png(&store, 1, 1, 0, &options).unwrap()];
+    let body = frame_tiles(&tiles).unwrap();
+
+    let mut header = io::Cursor::new(&body);
+    assert_eq!(header.read_u32::<LittleEndian>().unwrap(), 2);
+    for tile in &tiles {
+      let offset = header.read_u32::<LittleEndian>().unwrap() as usize;
+      let len = header.read_u32::<LittleEndian>().unwrap() as usize;
+      assert!(body[offset..].starts_with(b"\x89PNG"));
+      assert_eq!(&body[offset..offset + len], &tile.png[..]);
+    }
+    let end = body.len() - tiles[1].png.len();
+    assert_eq!(end, 4 + 8 * 2 + tiles[0].png.len());
+  }
+
+  #[test]
+  fn speed_zero_normal_wind_is_calm() {
+    let with_wind = |hour: i64, wind: &str| {
+      let record = testing::ish_record(hour, Some(5.0), "");
+      format!("{}{}{}", &record[..60], wind, &record[70..])
+    };
+    let input = format!("{}\n{}\n",
+                        with_wind(0, "0001N00001"),
+                        with_wind(1, "2701N00301"));
+    let station = parse("010000-99999-2016", &mut io::Cursor::new(input), 100)
+      .unwrap();
+    match station.measurements[0].wind {
+      Some(WindMeasurement::Calm) => {}
+      wind => panic!("expected calm, got {:?}", wind),
+    }
+    match station.measurements[1].wind {
+      Some(WindMeasurement::Normal { speed, direction }) => {
+        assert_eq!((speed, direction), (3.0, Some(270)));
+      }
+      wind => panic!("expected a normal wind, got {:?}", wind),
+    }
+  }
+
+  #[test]
+  fn calm_stations_take_the_calm_color() {
+    let station = |usaf: &str, wind: WindMeasurement| {
+      let mut measurements = testing::series(&[Some(5.0)]);
+      measurements[0].wind = Some(wind);
+      testing::station(usaf, 0.0, 0.0, measurements)
+    };
+    let stations = vec![station("010000", WindMeasurement::Calm),
+                        station("020000", WindMeasurement::Variable),
+                        station("030000",
+                                WindMeasurement::observed(10.0, Some(90)))];
+    let placed = stations.iter()
+      .enumerate()
+      .map(|(i, station)| (station, 10 * i as i32, 10))
+      .collect::<Vec<_>>();
+    let colors = |calm_color: Option<&str>| {
+      let params = TileParams {
+        mode: Some(String::from("barbs")),
+        calm_color: calm_color.map(String::from),
+        ..Default::default()
+      };
+      let options = params.render_options(&RenderOptions::default()).unwrap();
+      let (start, end) = options.window();
+      let (markers, _) = place_stations(&placed, &[], start, end, &options);
+      markers.iter().map(|m| m.color).collect::<Vec<_>>()
+    };
+    let defaults = RenderOptions::default();
+    let normal = DrawMode::Barbs.color(10.0, 1.0);
+    assert_eq!(colors(None),
+               vec![defaults.calm_color, defaults.variable_color, normal]);
+    assert_eq!(colors(Some("123456")),
+               vec![image::Rgb([0x12, 0x34, 0x56]),
+                    defaults.variable_color,
+                    normal]);
+  }
+
+  #[test]
+  fn records_provenance_when_enabled() {
+    let dir = testing::scratch_dir("provenance");
+    let record = testing::ish_record(0, Some(5.0), "");
+    for usaf in &["010000", "020000"] {
+      fs::File::create(dir.join(format!("{}-99999-2016.gz", usaf)))
+        .unwrap()
+        .write_all(record.replace("010000", usaf).as_bytes())
+        .unwrap();
+    }
+    let directory = dir.to_str().unwrap();
+
+    let args =
+      testing::args(&["--directory", directory, "--track-provenance"]);
+    let (stations, sources, _) = load_stations(&args, None);
+    assert_eq!(stations.len(), 2);
+    for station in &stations {
+      let source = station.measurements[0].source.unwrap() as usize;
+      let name = format!("{}-99999-2016.gz", station.usaf);
+      assert!(sources[source].ends_with(&name), "{}", sources[source]);
+    }
+
+    let args = testing::args(&["--directory", directory]);
+    let (stations, sources, _) = load_stations(&args, None);
+    assert!(sources.is_empty());
+    assert!(stations.iter()
+      .flat_map(|station| station.measurements.iter())
+      .all(|m| m.source.is_none()));
+  }
+
+  #[test]
+  fn cancelled_loads_stop_parsing() {
+    use std::sync::atomic;
+    // Not cancel::flag(), which tests loading in parallel would see.
+    static CANCELLED: atomic::AtomicBool = atomic::ATOMIC_BOOL_INIT;
+
+    let dir = testing::scratch_dir("cancel");
+    let record = testing::ish_record(0, Some(5.0), "");
+    for usaf in &["010000", "020000", "030000"] {
+      fs::File::create(dir.join(format!("{}-99999-2016.gz", usaf)))
+        .unwrap()
+        .write_all(record.replace("010000", usaf).as_bytes())
+        .unwrap();
+    }
+    let cache = testing::scratch_dir("cancel-cache").join("stations");
+    let args = testing::args(&["--directory",
+                               dir.to_str().unwrap(),
+                               "--station-cache",
+                               cache.to_str().unwrap()]);
+
+    CANCELLED.store(true, atomic::Ordering::SeqCst);
+    let (stations, _, num_failed) =
+      load_stations_until(&args, None, &CANCELLED);
+    assert!(stations.is_empty());
+    assert_eq!(num_failed, 0);
+    // A partial load isn't cached as if it were complete.
+    assert!(!cache.exists());
+
+    CANCELLED.store(false, atomic::Ordering::SeqCst);
+    let (stations, _, _) = load_stations_until(&args, None, &CANCELLED);
+    assert_eq!(stations.len(), 3);
+    assert!(cache.exists());
+  }
+
+  #[test]
+  fn tiles_change_only_where_measurements_did() {
+    let berlin = |hours: usize| {
+      testing::station("010000",
+                       52.5,
+                       13.4,
+                       testing::series(&vec![Some(5.0); hours]))
+    };
+    let santiago =
+      testing::station("020000", -33.4, -70.6, testing::series(&[Some(20.0)]));
+    // At zoom 1 Berlin is on tile 1/1/0 and Santiago on 1/0/1.
+    let old = store::StationStore::new(vec![berlin(2), santiago.clone()], 1);
+    let mut reloaded =
+      store::StationStore::new(vec![berlin(3), santiago.clone()], 2);
+    reloaded.inherit_updated(&old);
+    assert!(tile_changed_since(&reloaded, 1, 1, 0, 1));
+    assert!(!tile_changed_since(&reloaded, 1, 0, 1, 1));
+    assert!(tile_changed_since(&reloaded, 1, 0, 1, 0));
+
+    let shared = store::StationStore::new(vec![santiago], 1).shared();
+    publish_partial(&shared, &[berlin(2)]);
+    let partial = shared.read().unwrap();
+    assert_eq!(partial.version, 2);
+    assert!(tile_changed_since(&partial, 1, 1, 0, 1));
+    assert!(!tile_changed_since(&partial, 1, 0, 1, 1));
+
+    // What the route rejects with a 400 doesn't panic here either.
+    for &(zoom, x, y) in &[(20, 0, 0), (1, 0, 2), (1, 2, 0)] {
+      assert!(check_tile(zoom, x, y).is_err());
+      assert!(!tile_changed_since(&partial, zoom, x, y, 0));
+    }
+  }
+
+  #[test]
+  fn antialiased_lines_blend_along_diagonals() {
+    let white = image::Rgb([255u8, 255u8, 255u8]);
+    let mut aa = image::RgbImage::new(10, 10);
+    draw_line_aa(&mut aa, (0.0, 0.0), (9.0, 4.0), white);
+    assert!(aa.pixels().any(|p| p.data[0] > 0 && p.data[0] < 255));
+
+    let mut plain = image::RgbImage::new(10, 10);
+    draw_line(&mut plain, (0.0, 0.0), (9.0, 4.0), white);
+    assert!(plain.pixels().all(|p| p.data[0] == 0 || p.data[0] == 255));
+
+    // Parallel to an edge and entirely outside the image.
+    let mut outside = image::RgbImage::new(10, 10);
+    draw_line_aa(&mut outside, (0.0, -3.0), (9.0, -3.0), white);
+    draw_line(&mut outside, (12.0, 0.0), (12.0, 9.0), white);
+    assert!(outside.pixels().all(|p| p.data == [0, 0, 0]));
+  }
+
+  #[test]
+  fn count_weighted_clusters_favor_busy_stations() {
+    let station = |usaf: &str, temperatures: &[Option<f32>]| {
+      testing::station(usaf, 0.0, 0.0, testing::series(temperatures))
+    };
+    let quiet = station("010000", &[Some(0.0)]);
+    let busy = station("020000", &[Some(10.0); 3]);
+    let far = station("030000", &[Some(5.0)]);
+    let placed = vec![(&quiet, 5, 5), (&busy, 6, 6), (&far, 40, 40)];
+    let cluster = |weight: &str| {
+      let params = TileParams {
+        cluster: Some(16),
+        cluster_weight: Some(String::from(weight)),
+        ..Default::default()
+      };
+      let options = params.render_options(&RenderOptions::default()).unwrap();
+      let (start, end) = options.window();
+      let (markers, _) = place_stations(&placed, &[], start, end, &options);
+      markers.iter().map(|m| (m.x, m.y, m.value)).collect::<Vec<_>>()
+    };
+    assert_eq!(cluster("equal"),
+               vec![(5, 5, Some(5.0)), (40, 40, Some(5.0))]);
+    assert_eq!(cluster("count"),
+               vec![(5, 5, Some(7.5)), (40, 40, Some(5.0))]);
+  }
+
+  #[test]
+  fn sqlite_tiles_match_in_memory_ones() {
+    let connection = rusqlite::Connection::open_in_memory().unwrap();
+    testing::insert_stations(&connection, &render_stations());
+    let mut database = store::StationStore::new(vec![], 0);
+    database.database = Some(source::SqliteSource::new(connection));
+    let memory = store::StationStore::new(render_stations(), 0);
+
+    let mut window = RenderOptions::default();
+    window.window = Some((testing::time(2), testing::time(5)));
+    let mut fill = RenderOptions::default();
+    fill.spatial_fill = Some(MAX_SPATIAL_FILL_KM);
+    for options in &[RenderOptions::default(), window, fill] {
+      for &(zoom, x, y) in &[(0, 0, 0), (1, 0, 1), (2, 1, 2)] {
+        let expected = render_tile_png(&memory, zoom, x, y, options).unwrap();
+        let tile = render_tile_png(database.source(), zoom, x, y, options)
+          .unwrap();
+        assert!(tile.png == expected.png, "tile {}/{}/{}", zoom, x, y);
+        assert_eq!(tile.stations_drawn, expected.stations_drawn);
+      }
+    }
+
+    let args = testing::args(&["--sqlite", "stations.db", "--directory", "."]);
+    assert!(check_args("serve", &args).is_err());
+  }
+
+  #[test]
+  fn larger_density_radius_spreads_wider() {
+    let marker = || {
+      Marker {
+        x: 32,
+        y: 32,
+        color: image::Rgb([255u8, 255u8, 255u8]),
+        scale: 1,
+        clipped: false,
+        wind: None,
+        value: None,
+        weight: 1.0,
+      }
+    };
+    let spread = |radius: u32, shape: KernelShape| {
+      let mut options = RenderOptions::default();
+      options.density = DensityKernel {
+        radius: radius,
+        shape: shape,
+      };
+      let mut img = image::RgbImage::from_pixel(64, 64, options.background);
+      draw_density(&mut img, &[marker()], &options);
+      assert_eq!(*img.get_pixel(32, 32),
+                 palette_color(1.0 / DENSITY_SATURATION));
+      img.pixels().filter(|&&p| p != options.background).count()
+    };
+    assert_eq!(spread(2, KernelShape::Box), 5 * 5);
+    assert_eq!(spread(8, KernelShape::Box), 17 * 17);
+    assert!(spread(8, KernelShape::Gaussian) >
+            spread(2, KernelShape::Gaussian));
+    assert_eq!(spread(0, KernelShape::Gaussian), 1);
+  }
+
+  #[test]
+  fn require_elevation_drops_stations_without_one() {
+    let dir = testing::scratch_dir("require-elevation");
+    let record = testing::ish_record(0, Some(5.0), "");
+    let unknown = format!("{}+9999{}", &record[..46], &record[51..])
+      .replace("010000", "020000");
+    for &(name, contents) in &[("010000-99999-2016.gz", &record),
+                               ("020000-99999-2016.gz", &unknown)] {
+      fs::File::create(dir.join(name))
+        .unwrap()
+        .write_all(contents.as_bytes())
+        .unwrap();
+    }
+    let directory = dir.to_str().unwrap();
+
+    let (stations, _, _) =
+      load_stations(&testing::args(&["--directory", directory]), None);
+    assert_eq!(stations.len(), 2);
+
+    let args =
+      testing::args(&["--directory", directory, "--require-elevation"]);
+    let (stations, _, _) = load_stations(&args, None);
+    assert_eq!(stations.len(), 1);
+    assert_eq!(stations[0].usaf, "010000");
+    assert_eq!(stations[0].elevation, Some(100));
+  }
+
+  #[test]
+  fn renders_tiles_from_an_in_memory_store() {
+    use byteorder::{BigEndian, ByteOrder};
+
+    let store = store::StationStore::new(render_stations(), 0);
+    let tile = render_tile_png(&store, 0, 0, 0, &RenderOptions::default())
+      .unwrap();
+    assert!(tile.png.starts_with(b"\x89PNG"));
+    // The IHDR chunk's width and height.
+    assert_eq!(BigEndian::read_u32(&tile.png[16..20]), TILE_SIZE);
+    assert_eq!(BigEndian::read_u32(&tile.png[20..24]), TILE_SIZE);
+    assert_eq!(tile.stations_drawn, 5);
+
+    let tile_cache =
+      cache::TileCache::new(10, std::time::Duration::from_secs(60));
+    let params = TileParams {
+      mode: Some(String::from("sunshine")),
+      ..Default::default()
+    };
+    match get_tile(0,
+                   0,
+                   0,
+                   &params,
+                   &store,
+                   &RenderOptions::default(),
+                   &tile_cache) {
+      Err(RenderError::BadRequest(_)) => {}
+      _ => panic!("rendered a tile in an unknown mode"),
+    }
+  }
+
+  #[test]
+  fn partial_stations_render_while_loading() {
+    let mut empty = store::StationStore::new(vec![], 0);
+    empty.loading = true;
+    let shared = empty.shared();
+    let tile_cache =
+      cache::TileCache::new(10, std::time::Duration::from_secs(60));
+    let stations_drawn = || {
+      let store = shared.read().unwrap();
+      assert!(store.loading);
+      match get_tile(0,
+                     0,
+                     0,
+                     &TileParams::default(),
+                     &store,
+                     &RenderOptions::default(),
+                     &tile_cache) {
+        Ok(tile) => tile.stations_drawn,
+        Err(_) => panic!("rendering a partial store failed"),
+      }
+    };
+    assert_eq!(stations_drawn(), 0);
+
+    let stations = render_stations();
+    publish_partial(&shared, &stations[..2]);
+    assert_eq!(stations_drawn(), 2);
+    publish_partial(&shared, &stations[2..]);
+    assert_eq!(stations_drawn(), stations.len());
+  }
+
+  #[test]
+  fn reduces_temperatures_to_sea_level() {
+    assert_eq!(meteo::sea_level_temperature(10.0, 1000.0), 16.5);
+
+    let mut mountain =
+      testing::station("010000", 0.0, 0.0, testing::series(&[Some(10.0)]));
+    mountain.elevation = Some(1000);
+    let unknown =
+      testing::station("020000", 0.0, 0.0, testing::series(&[Some(10.0)]));
+    let placed = vec![(&mountain, 0, 0), (&unknown, 1, 1)];
+    let values = |missing: &str| {
+      let params = TileParams {
+        mslt: Some(true),
+        mslt_missing: Some(String::from(missing)),
+        ..Default::default()
+      };
+      let options = params.render_options(&RenderOptions::default()).unwrap();
+      let (start, end) = options.window();
+      let (markers, _) = place_stations(&placed, &[], start, end, &options);
+      markers.iter().map(|m| (m.x, m.value)).collect::<Vec<_>>()
+    };
+    assert_eq!(values("keep"), vec![(0, Some(16.5)), (1, Some(10.0))]);
+    assert_eq!(values("skip"), vec![(0, Some(16.5))]);
+  }
+
+  #[test]
+  fn sparklines_have_the_requested_size() {
+    use byteorder::{BigEndian, ByteOrder};
+
+    let background = image::Rgb([0u8, 0u8, 0u8]);
+    let temperatures = testing::series(&[Some(-5.0), Some(3.0), Some(10.0)])
+      .iter()
+      .map(|m| (m.datetime, m.air_temperature.unwrap()))
+      .collect::<Vec<_>>();
+    for &(width, height) in &[(120, 30), (64, 16)] {
+      let img = draw_sparkline(&temperatures, width, height, background);
+      assert_eq!(img.dimensions(), (width, height));
+      // Autoscaled: the line reaches the bottom left and top right corners.
+      assert!(*img.get_pixel(0, height - 1) != background);
+      assert!(*img.get_pixel(width - 1, 0) != background);
+
+      let png = encode_png(img, false).unwrap();
+      assert_eq!(BigEndian::read_u32(&png[16..20]), width);
+      assert_eq!(BigEndian::read_u32(&png[20..24]), height);
+    }
+    let empty = draw_sparkline(&[], 120, 30, background);
+    assert_eq!(empty.dimensions(), (120, 30));
+  }
+
+  #[test]
+  fn outline_surrounds_filled_markers() {
+    let fill = image::Rgb([0u8, 255u8, 0u8]);
+    let red = image::Rgb([255u8, 0u8, 0u8]);
+    let marker = || {
+      Marker {
+        x: 16,
+        y: 16,
+        color: fill,
+        scale: 1,
+        clipped: false,
+        wind: None,
+        value: None,
+        weight: 1.0,
+      }
+    };
+    let viewport = Viewport::new(-10.0, 10.0, -10.0, 10.0, 32, 32).unwrap();
+    let draw = |outline: Option<image::Rgb<u8>>| {
+      let mut options = RenderOptions::default();
+      options.outline = outline;
+      draw_markers(&[marker()], &viewport, 4, &options)
+    };
+    // A size 4 square centered on (16, 16) covers 14..18 in both directions.
+    let img = draw(Some(red));
+    let background = RenderOptions::default().background;
+    for x in 12..21 {
+      for y in 12..21 {
+        let expected = if x >= 14 && x < 18 && y >= 14 && y < 18 {
+          fill
+        } else if x >= 13 && x < 19 && y >= 13 && y < 19 {
+          red
+        } else {
+          background
+        };
+        assert_eq!(*img.get_pixel(x, y), expected, "({}, {})", x, y);
+      }
+    }
+    assert!(draw(None).pixels().all(|&p| p != red));
+  }
 }
@@ -0,0 +1,120 @@
+// Derived meteorological quantities. Temperatures are in degrees Celsius,
+// wind speeds in m/s and relative humidity in percent.
+
+fn celsius_to_fahrenheit(t: f32) -> f32 {
+  t * 9.0 / 5.0 + 32.0
+}
+
+fn fahrenheit_to_celsius(t: f32) -> f32 {
+  (t - 32.0) * 5.0 / 9.0
+}
+
+// Relative humidity from temperature and dew point, using the Magnus formula
+// with the Alduchov & Eskridge coefficients.
+pub fn relative_humidity(temp_c: f32, dew_point_c: f32) -> f32 {
+  let saturation = |t: f32| (17.625 * t / (243.04 + t)).exp();
+  (100.0 * saturation(dew_point_c) / saturation(temp_c)).min(100.0)
+}
+
+// NWS wind chill, only defined at or below 10 °C and for winds above
+// 4.8 km/h.
+// Following https://www.weather.gov/media/epz/wxcalc/windChill.pdf
+pub fn wind_chill(temp_c: f32, wind_ms: f32) -> Option<f32> {
+  let wind_kmh = wind_ms * 3.6;
+  if temp_c > 10.0 || wind_kmh <= 4.8 {
+    return None;
+  }
+  let v = wind_kmh.powf(0.16);
+  Some(13.12 + 0.6215 * temp_c - 11.37 * v + 0.3965 * temp_c * v)
+}
+
+// NWS heat index (Rothfusz regression), only defined from 80 °F and 40 %
+// relative humidity upwards.
+// Following https://www.wpc.ncep.noaa.gov/html/heatindex_equation.shtml
+pub fn heat_index(temp_c: f32, rh: f32) -> Option<f32> {
+  let t = celsius_to_fahrenheit(temp_c);
+  if t < 80.0 || rh < 40.0 {
+    return None;
+  }
+  let hi = -42.379 + 2.04901523 * t + 10.14333127 * rh -
+           0.22475541 * t * rh - 0.00683783 * t * t -
+           0.05481717 * rh * rh + 0.00122874 * t * t * rh +
+           0.00085282 * t * rh * rh - 0.00000199 * t * t * rh * rh;
+  Some(fahrenheit_to_celsius(hi))
+}
+
+// "Feels like" temperature: wind chill when cold and windy, heat index when
+// hot and humid, otherwise the air temperature itself.
+pub fn apparent_temperature(temp_c: f32,
+                            wind_ms: Option<f32>,
+                            dew_point_c: Option<f32>)
+                            -> f32 {
+  wind_ms.and_then(|w| wind_chill(temp_c, w))
+    .or_else(|| {
+      dew_point_c.and_then(|d| heat_index(temp_c, relative_humidity(temp_c, d)))
+    })
+    .unwrap_or(temp_c)
+}
+
+// Temperature drop per meter of height in the standard atmosphere.
+pub const LAPSE_RATE: f32 = 0.0065;
+
+// The temperature a station at elevation_m meters would measure at sea level,
+// assuming the standard lapse rate all the way down.
+pub fn sea_level_temperature(temp_c: f32, elevation_m: f32) -> f32 {
+  temp_c + LAPSE_RATE * elevation_m
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_near(value: Option<f32>, expected: f32, tolerance: f32) {
+    match value {
+      Some(v) => {
+        assert!((v - expected).abs() < tolerance,
+                "{} instead of {}",
+                v,
+                expected)
+      }
+      None => panic!("no value instead of {}", expected),
+    }
+  }
+
+  #[test]
+  fn wind_chill_matches_the_table() {
+    // -10 °C at 30 km/h and -20 °C at 20 km/h, -20 and -30 in the table.
+    assert_near(wind_chill(-10.0, 30.0 / 3.6), -19.5, 0.1);
+    assert_near(wind_chill(-20.0, 20.0 / 3.6), -30.5, 0.1);
+  }
+
+  #[test]
+  fn wind_chill_is_undefined_when_warm_or_still() {
+    assert_eq!(wind_chill(15.0, 10.0), None);
+    assert_eq!(wind_chill(-10.0, 1.0), None);
+  }
+
+  #[test]
+  fn heat_index_matches_the_table() {
+    // 90 °F at 70 % is 106 °F in the table.
+    assert_near(heat_index(fahrenheit_to_celsius(90.0), 70.0)
+                  .map(celsius_to_fahrenheit),
+                106.0,
+                0.5);
+    assert_near(heat_index(32.0, 70.0), 40.4, 0.1);
+  }
+
+  #[test]
+  fn heat_index_is_undefined_when_cool_or_dry() {
+    assert_eq!(heat_index(20.0, 80.0), None);
+    assert_eq!(heat_index(32.0, 30.0), None);
+  }
+
+  #[test]
+  fn apparent_temperature_falls_back_to_the_air_temperature() {
+    assert_eq!(apparent_temperature(18.0, Some(5.0), Some(10.0)), 18.0);
+    assert_eq!(apparent_temperature(-10.0, None, None), -10.0);
+    assert_eq!(apparent_temperature(-10.0, Some(30.0 / 3.6), None),
+               wind_chill(-10.0, 30.0 / 3.6).unwrap());
+  }
+}
@@ -0,0 +1,160 @@
+// Measurements as newline delimited JSON, one object per line, e.g.
+// {"usaf": "029070", "wban": "99999", "datetime": "2016-01-01T00:00:00Z",
+//  "lat": 60.2, "lon": 24.9, "temp": -3.1}
+// A file may mix several stations. Such data is expected to be cleaned
+// already, so every value is taken to have passed quality control.
+
+use std::collections;
+use std::io;
+use std::io::BufRead;
+
+use serde_json;
+
+use QualityCodes;
+use WeatherMeasurement;
+use WeatherStation;
+use WindMeasurement;
+use parse_datetime;
+
+#[derive(Deserialize)]
+struct Line {
+  usaf: String,
+  wban: String,
+  // RFC 3339 or YYYY-MM-DD.
+  datetime: String,
+  lat: f32,
+  lon: f32,
+  elevation: Option<i32>,
+  // Degrees Celsius.
+  temp: Option<f32>,
+  dew_point: Option<f32>,
+  // Hectopascals.
+  pressure: Option<f32>,
+  // Meters per second, and degrees the wind is coming from.
+  wind_speed: Option<f32>,
+  wind_direction: Option<i32>,
+  // ISH report type such as FM-12.
+  report_type: Option<String>,
+}
+
+fn measurement(line: &Line) -> Result<WeatherMeasurement, String> {
+  let mut report_type = *b"     ";
+  if let Some(ref code) = line.report_type {
+    if code.len() != report_type.len() {
+      return Err(format!("invalid report_type {:?}", code));
+    }
+    report_type.copy_from_slice(code.as_bytes());
+  }
+  Ok(WeatherMeasurement {
+    datetime: try!(parse_datetime(&line.datetime)),
+    report_type: report_type,
+    wind: line.wind_speed
+      .map(|speed| WindMeasurement::observed(speed, line.wind_direction)),
+    air_temperature: line.temp,
+    dew_point: line.dew_point,
+    air_pressure: line.pressure,
+    precip_type: None,
+    source: None,
+    quality: QualityCodes {
+      wind_speed: b'1',
+      air_temperature: b'1',
+      dew_point: b'1',
+      air_pressure: b'1',
+    },
+  })
+}
+
+// Groups the lines by station, in order of first appearance. At most
+// max_measurements are kept per station.
+pub fn parse<R: BufRead>(filename: &str,
+                         reader: R,
+                         max_measurements: usize)
+                         -> Result<Vec<WeatherStation>, io::Error> {
+  let mut stations: Vec<WeatherStation> = vec![];
+  let mut index = collections::HashMap::new();
+  for (line_number, line) in reader.lines().enumerate() {
+    let line = try!(line);
+    if line.trim().is_empty() {
+      continue;
+    }
+    let invalid = |message: String| {
+      io::Error::new(io::ErrorKind::InvalidData,
+                     format!("{}:{}: {}", filename, line_number + 1, message))
+    };
+    let line: Line = try!(serde_json::from_str(&line)
+      .map_err(|e| invalid(e.to_string())));
+    if line.lat.abs() > 90.0 || line.lon.abs() > 180.0 {
+      return Err(invalid(format!("invalid location {}, {}",
+                                 line.lat,
+                                 line.lon)));
+    }
+    let m = try!(measurement(&line).map_err(&invalid));
+
+    let id = (line.usaf.clone(), line.wban.clone());
+    let i = *index.entry(id).or_insert_with(|| {
+      stations.push(WeatherStation {
+        usaf: line.usaf.clone(),
+        wban: line.wban.clone(),
+        name: None,
+        country: None,
+        state: None,
+
+        latitude: line.lat,
+        longitude: line.lon,
+        elevation: line.elevation,
+
+        measurements: vec![],
+      });
+      stations.len() - 1
+    });
+    if stations[i].measurements.len() < max_measurements {
+      stations[i].measurements.push(m);
+    }
+  }
+  Ok(stations)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SAMPLE: &'static str = "{\"usaf\": \"029070\", \"wban\": \"99999\", \
+    \"datetime\": \"2016-01-01T00:00:00Z\", \"lat\": 60.2, \"lon\": 24.9, \
+    \"temp\": -3.1}\n\
+    \n\
+    {\"usaf\": \"010000\", \"wban\": \"99999\", \"datetime\": \"2016-01-01\", \
+    \"lat\": 70.9, \"lon\": -8.7, \"wind_speed\": 4.5, \
+    \"wind_direction\": 270, \"report_type\": \"FM-15\"}\n\
+    {\"usaf\": \"029070\", \"wban\": \"99999\", \
+    \"datetime\": \"2016-01-01T01:00:00Z\", \"lat\": 60.2, \"lon\": 24.9, \
+    \"temp\": -2.5}\n";
+
+  #[test]
+  fn groups_lines_by_station() {
+    let stations = parse("sample.ndjson", io::Cursor::new(SAMPLE), 100)
+      .unwrap();
+    assert_eq!(stations.len(), 2);
+    assert_eq!(stations[0].usaf, "029070");
+    assert_eq!((stations[0].latitude, stations[0].longitude), (60.2, 24.9));
+    let temperatures = stations[0]
+      .measurements
+      .iter()
+      .map(|m| m.air_temperature)
+      .collect::<Vec<_>>();
+    assert_eq!(temperatures, vec![Some(-3.1), Some(-2.5)]);
+    let windy = &stations[1].measurements[0];
+    assert_eq!(&windy.report_type, b"FM-15");
+    assert!(windy.wind.is_some());
+  }
+
+  #[test]
+  fn reports_the_line_of_invalid_locations() {
+    let input = "{\"usaf\": \"010000\", \"wban\": \"99999\", \
+                 \"datetime\": \"2016-01-01\", \"lat\": 91, \"lon\": 0}\n";
+    let error = match parse("bad.ndjson", io::Cursor::new(input), 100) {
+      Ok(_) => panic!("parsed a latitude of 91"),
+      Err(error) => error,
+    };
+    assert!(error.to_string().starts_with("bad.ndjson:1: "), "{}", error);
+  }
+}
@@ -0,0 +1,260 @@
+// Minimal writer for the NetCDF classic (CDF-1) format, supporting exactly
+// what the gridded exports need: fixed-size dimensions, float variables and
+// text/float attributes.
+// Format reference:
+// https://www.unidata.ucar.edu/software/netcdf/docs/file_format_specifications.html
+
+use std::io;
+use std::io::Write;
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+const NC_DIMENSION: u32 = 0x0A;
+const NC_VARIABLE: u32 = 0x0B;
+const NC_ATTRIBUTE: u32 = 0x0C;
+
+const NC_CHAR: u32 = 2;
+const NC_FLOAT: u32 = 5;
+
+pub enum Attribute {
+  Text(String),
+  Float(Vec<f32>),
+}
+
+pub struct Variable {
+  pub name: String,
+  // Indices into File::dimensions, slowest varying first.
+  pub dimensions: Vec<usize>,
+  pub attributes: Vec<(String, Attribute)>,
+  pub data: Vec<f32>,
+}
+
+#[derive(Default)]
+pub struct File {
+  pub dimensions: Vec<(String, usize)>,
+  pub attributes: Vec<(String, Attribute)>,
+  pub variables: Vec<Variable>,
+}
+
+fn write_u32(w: &mut Write, n: u32) -> Result<(), io::Error> {
+  w.write_u32::<BigEndian>(n)
+}
+
+fn write_padding(w: &mut Write, len: usize) -> Result<(), io::Error> {
+  w.write_all(&[0u8; 4][..(4 - len % 4) % 4])
+}
+
+fn write_name(w: &mut Write, name: &str) -> Result<(), io::Error> {
+  try!(write_u32(w, name.len() as u32));
+  try!(w.write_all(name.as_bytes()));
+  write_padding(w, name.len())
+}
+
+fn write_attributes(w: &mut Write,
+                    attributes: &[(String, Attribute)])
+                    -> Result<(), io::Error> {
+  if attributes.is_empty() {
+    try!(write_u32(w, 0));
+    return write_u32(w, 0);
+  }
+  try!(write_u32(w, NC_ATTRIBUTE));
+  try!(write_u32(w, attributes.len() as u32));
+  for &(ref name, ref value) in attributes {
+    try!(write_name(w, name));
+    match *value {
+      Attribute::Text(ref text) => {
+        try!(write_u32(w, NC_CHAR));
+        try!(write_name(w, text));
+      }
+      Attribute::Float(ref values) => {
+        try!(write_u32(w, NC_FLOAT));
+        try!(write_u32(w, values.len() as u32));
+        for v in values {
+          try!(w.write_f32::<BigEndian>(*v));
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+impl File {
+  fn write_header(&self,
+                  w: &mut Write,
+                  offsets: &[u32])
+                  -> Result<(), io::Error> {
+    try!(w.write_all(b"CDF\x01"));
+    // No record dimension, so no records.
+    try!(write_u32(w, 0));
+
+    try!(write_u32(w, NC_DIMENSION));
+    try!(write_u32(w, self.dimensions.len() as u32));
+    for &(ref name, len) in &self.dimensions {
+      try!(write_name(w, name));
+      try!(write_u32(w, len as u32));
+    }
+
+    try!(write_attributes(w, &self.attributes));
+
+    try!(write_u32(w, NC_VARIABLE));
+    try!(write_u32(w, self.variables.len() as u32));
+    for (variable, &offset) in self.variables.iter().zip(offsets) {
+      try!(write_name(w, &variable.name));
+      try!(write_u32(w, variable.dimensions.len() as u32));
+      for &dimension in &variable.dimensions {
+        try!(write_u32(w, dimension as u32));
+      }
+      try!(write_attributes(w, &variable.attributes));
+      try!(write_u32(w, NC_FLOAT));
+      try!(write_u32(w, (variable.data.len() * 4) as u32));
+      try!(write_u32(w, offset));
+    }
+    Ok(())
+  }
+
+  pub fn write(&self, w: &mut Write) -> Result<(), io::Error> {
+    for variable in &self.variables {
+      let expected = variable.dimensions
+        .iter()
+        .map(|&d| self.dimensions[d].1)
+        .product::<usize>();
+      ret_check_eq!(variable.data.len(), expected);
+    }
+
+    // The header has a fixed size regardless of the offsets it contains, so
+    // measure it once with placeholders to find where the data starts.
+    let mut header = Vec::new();
+    try!(self.write_header(&mut header, &vec![0; self.variables.len()]));
+    let mut offsets = Vec::new();
+    let mut offset = header.len();
+    for variable in &self.variables {
+      offsets.push(offset as u32);
+      offset += variable.data.len() * 4;
+    }
+
+    try!(self.write_header(w, &offsets));
+    for variable in &self.variables {
+      for v in &variable.data {
+        try!(w.write_f32::<BigEndian>(*v));
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use byteorder::{BigEndian, ByteOrder};
+
+  // Walks a header the way a CDF-1 reader would.
+  struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+  }
+
+  impl<'a> Reader<'a> {
+    fn u32(&mut self) -> u32 {
+      let n = BigEndian::read_u32(&self.bytes[self.pos..self.pos + 4]);
+      self.pos += 4;
+      n
+    }
+
+    fn name(&mut self) -> String {
+      let len = self.u32() as usize;
+      let name = String::from_utf8(self.bytes[self.pos..self.pos + len]
+          .to_vec())
+        .unwrap();
+      self.pos += (len + 3) / 4 * 4;
+      name
+    }
+
+    fn skip_attributes(&mut self) {
+      self.u32();
+      for _ in 0..self.u32() {
+        self.name();
+        match self.u32() {
+          NC_CHAR => {
+            self.name();
+          }
+          NC_FLOAT => {
+            let n = self.u32() as usize;
+            self.pos += n * 4;
+          }
+          t => panic!("unexpected attribute type {}", t),
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn header_points_at_the_data() {
+    let file = File {
+      dimensions: vec![(String::from("lat"), 2), (String::from("lon"), 3)],
+      attributes: vec![(String::from("title"),
+                        Attribute::Text(String::from("t")))],
+      variables: vec![Variable {
+                        name: String::from("lat"),
+                        dimensions: vec![0],
+                        attributes: vec![],
+                        data: vec![10.0, 20.0],
+                      },
+                      Variable {
+                        name: String::from("temperature"),
+                        dimensions: vec![0, 1],
+                        attributes: vec![(String::from("_FillValue"),
+                                          Attribute::Float(vec![-1.0]))],
+                        data: vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.5],
+                      }],
+    };
+    let mut bytes = vec![];
+    file.write(&mut bytes).unwrap();
+    assert_eq!(&bytes[..4], b"CDF\x01");
+
+    let mut r = Reader {
+      bytes: &bytes,
+      pos: 4,
+    };
+    assert_eq!(r.u32(), 0);
+    assert_eq!(r.u32(), NC_DIMENSION);
+    assert_eq!(r.u32(), 2);
+    assert_eq!((r.name(), r.u32()), (String::from("lat"), 2));
+    assert_eq!((r.name(), r.u32()), (String::from("lon"), 3));
+    r.skip_attributes();
+
+    assert_eq!(r.u32(), NC_VARIABLE);
+    assert_eq!(r.u32(), 2);
+    let mut offsets = vec![];
+    for &(name, ref dimensions, size) in &[("lat", vec![0u32], 8u32),
+                                          ("temperature", vec![0, 1], 24)] {
+      assert_eq!(r.name(), name);
+      let n = r.u32();
+      assert_eq!((0..n).map(|_| r.u32()).collect::<Vec<_>>(), *dimensions);
+      r.skip_attributes();
+      assert_eq!(r.u32(), NC_FLOAT);
+      assert_eq!(r.u32(), size);
+      offsets.push(r.u32() as usize);
+    }
+
+    // The data follows the header directly, one variable after the other.
+    assert_eq!(offsets, vec![r.pos, r.pos + 8]);
+    assert_eq!(BigEndian::read_f32(&bytes[offsets[0] + 4..]), 20.0);
+    assert_eq!(BigEndian::read_f32(&bytes[offsets[1] + 20..]), 6.5);
+    assert_eq!(bytes.len(), offsets[1] + 24);
+  }
+
+  #[test]
+  fn rejects_data_not_matching_the_dimensions() {
+    let file = File {
+      dimensions: vec![(String::from("lat"), 2)],
+      attributes: vec![],
+      variables: vec![Variable {
+                        name: String::from("lat"),
+                        dimensions: vec![0],
+                        attributes: vec![],
+                        data: vec![10.0],
+                      }],
+    };
+    assert!(file.write(&mut vec![]).is_err());
+  }
+}
@@ -0,0 +1,789 @@
+// Hand-maintained OpenAPI 3 description of the HTTP API, served at
+// /api/openapi.json. Update it whenever a route or parameter changes.
+
+use serde_json;
+
+use json::JSON;
+
+fn tile_path_parameters() -> serde_json::Value {
+  json!([
+    {"name": "zoom", "in": "path", "required": true,
+     "schema": {"type": "integer", "minimum": 0, "maximum": 18}},
+    {"name": "x", "in": "path", "required": true,
+     "schema": {"type": "integer", "minimum": 0}},
+    {"name": "y", "in": "path", "required": true,
+     "schema": {"type": "integer", "minimum": 0}}
+  ])
+}
+
+fn field_schema() -> serde_json::Value {
+  json!({"type": "string",
+         "enum": ["temperature", "dew_point", "pressure", "wind_speed",
+                  "humidity"]})
+}
+
+fn render_parameters() -> serde_json::Value {
+  json!([
+    {"name": "mode", "in": "query",
+     "schema": {"type": "string",
+                "enum": ["temperature", "apparent", "bivariate", "anomaly",
+                         "barbs", "precip", "coverage", "density"],
+                "default": "temperature"}},
+    {"name": "a", "in": "query",
+     "description": "Field shown as hue with mode=bivariate",
+     "schema": field_schema()},
+    {"name": "b", "in": "query",
+     "description": "Field shown as brightness with mode=bivariate",
+     "schema": field_schema()},
+    {"name": "field", "in": "query",
+     "description": "Color by this field instead of a mode",
+     "schema": field_schema()},
+    {"name": "select", "in": "query",
+     "description": "Which measurement colors a station; interp requires at",
+     "schema": {"type": "string", "enum": ["first", "interp"],
+                "default": "first"}},
+    {"name": "at", "in": "query",
+     "schema": {"type": "string", "format": "date-time"}},
+    {"name": "localtime", "in": "query",
+     "description": "Interpret at and dated tiles in each station's solar \
+                     time, longitude / 15 hours ahead of UTC",
+     "schema": {"type": "boolean", "default": false}},
+    {"name": "freshness", "in": "query",
+     "description": "Only draw measurements from this many hours before now",
+     "schema": {"type": "number", "exclusiveMinimum": 0}},
+    {"name": "clip_markers", "in": "query",
+     "description": "Ring stations whose value is beyond the color ramp",
+     "schema": {"type": "boolean", "default": false}},
+    {"name": "inclusive_end", "in": "query",
+     "description": "Also draw measurements exactly at the end of the time \
+                     window, which is otherwise half-open",
+     "schema": {"type": "boolean", "default": false}},
+    {"name": "basemap", "in": "query",
+     "description": "Draw coarse coastlines under the stations",
+     "schema": {"type": "boolean", "default": false}},
+    {"name": "aa", "in": "query",
+     "description": "Antialias the lines of mode=barbs",
+     "schema": {"type": "boolean", "default": false}},
+    {"name": "mslt", "in": "query",
+     "description": "Reduce air temperatures to sea level with the standard \
+                     lapse rate of 6.5 °C/km",
+     "schema": {"type": "boolean", "default": false}},
+    {"name": "mslt_missing", "in": "query",
+     "description": "With mslt, whether stations without an elevation are \
+                     drawn unadjusted or skipped",
+     "schema": {"type": "string", "enum": ["keep", "skip"],
+                "default": "keep"}},
+    {"name": "hour", "in": "query",
+     "description": "Only draw measurements taken around this UTC hour, or \
+                     solar hour with localtime",
+     "schema": {"type": "integer", "minimum": 0, "maximum": 23}},
+    {"name": "hour_window", "in": "query",
+     "description": "How many hours from hour measurements may be",
+     "schema": {"type": "number", "minimum": 0, "default": 1}},
+    {"name": "spatial_fill", "in": "query",
+     "description": "Color stations without a value by interpolating the \
+                     stations within this many km",
+     "schema": {"type": "number", "exclusiveMinimum": 0, "maximum": 500}},
+    {"name": "cluster", "in": "query",
+     "description": "Merge the stations within square cells of this many \
+                     pixels into one marker colored by their mean value",
+     "schema": {"type": "integer", "minimum": 1}},
+    {"name": "cluster_weight", "in": "query",
+     "description": "With cluster, whether every station counts the same \
+                     or by its number of measurements in the window",
+     "schema": {"type": "string", "enum": ["equal", "count"],
+                "default": "equal"}},
+    {"name": "density_radius", "in": "query",
+     "description": "Radius in pixels that mode=density spreads each \
+                     station over",
+     "schema": {"type": "integer", "minimum": 0, "maximum": 64,
+                "default": 8}},
+    {"name": "density_kernel", "in": "query",
+     "description": "Shape mode=density spreads each station with",
+     "schema": {"type": "string", "enum": ["box", "gaussian"],
+                "default": "gaussian"}},
+    {"name": "marker", "in": "query",
+     "schema": {"type": "string", "enum": ["square", "circle", "cross"]}},
+    {"name": "smooth", "in": "query",
+     "description": "Averaging window in hours",
+     "schema": {"type": "number", "exclusiveMinimum": 0}},
+    {"name": "bg", "in": "query",
+     "description": "Background color as RRGGBB",
+     "schema": {"type": "string", "pattern": "^#?[0-9a-fA-F]{6}$"}},
+    {"name": "outline", "in": "query",
+     "description": "Draw a one pixel border of this RRGGBB color around \
+                     every marker",
+     "schema": {"type": "string", "pattern": "^#?[0-9a-fA-F]{6}$"}},
+    {"name": "calm_color", "in": "query",
+     "description": "Marker color as RRGGBB of stations reporting calm \
+                     wind in the wind modes",
+     "schema": {"type": "string", "pattern": "^#?[0-9a-fA-F]{6}$"}},
+    {"name": "variable_color", "in": "query",
+     "description": "Marker color as RRGGBB of stations reporting variable \
+                     wind in the wind modes",
+     "schema": {"type": "string", "pattern": "^#?[0-9a-fA-F]{6}$"}},
+    {"name": "gamma", "in": "query",
+     "description": "Raise values normalized to [0, 1] to this power before \
+                     coloring them; below 1 spreads the low end of the ramp, \
+                     above 1 the high end",
+     "schema": {"type": "number", "exclusiveMinimum": 0, "default": 1}},
+    {"name": "depth", "in": "query",
+     "description": "8idx: indexed color for tiles of at most 256 colors",
+     "schema": {"type": "string", "enum": ["24", "8idx"], "default": "24"}}
+  ])
+}
+
+fn tile_parameters() -> serde_json::Value {
+  let mut parameters = tile_path_parameters();
+  {
+    let list = parameters.as_array_mut().unwrap();
+    list.extend(render_parameters().as_array().unwrap().iter().cloned());
+    list.push(scheme_parameter());
+    list.push(unit_parameter());
+    list.push(wind_unit_parameter());
+  }
+  parameters
+}
+
+fn scheme_parameter() -> serde_json::Value {
+  json!({"name": "scheme", "in": "query",
+         "schema": {"type": "string", "enum": ["xyz", "tms"],
+                    "default": "xyz"}})
+}
+
+fn batch_parameters() -> serde_json::Value {
+  let mut parameters = render_parameters();
+  parameters.as_array_mut().unwrap().push(scheme_parameter());
+  parameters
+}
+
+fn colorscale_parameters() -> serde_json::Value {
+  let mut parameters = render_parameters();
+  {
+    let list = parameters.as_array_mut().unwrap();
+    list.push(unit_parameter());
+    list.push(wind_unit_parameter());
+  }
+  parameters
+}
+
+fn changed_parameters() -> serde_json::Value {
+  let since = json!({"name": "since", "in": "query", "required": true,
+                     "schema": {"type": "integer", "minimum": 0}});
+  let mut parameters = tile_path_parameters();
+  parameters.as_array_mut().unwrap().push(since);
+  parameters
+}
+
+fn timeseries_parameters() -> serde_json::Value {
+  let mut parameters = tile_path_parameters();
+  {
+    let parameters = parameters.as_array_mut().unwrap();
+    parameters.push(json!({"name": "start", "in": "query", "required": true,
+                           "schema": {"type": "string",
+                                      "format": "date-time"}}));
+    parameters.push(json!({"name": "end", "in": "query", "required": true,
+                           "schema": {"type": "string",
+                                      "format": "date-time"}}));
+    parameters.push(json!({"name": "steps", "in": "query", "required": true,
+                           "description": "Number of evenly spaced times \
+                                           from start to end, both included",
+                           "schema": {"type": "integer", "minimum": 1,
+                                      "maximum": 500}}));
+    parameters.push(unit_parameter());
+  }
+  parameters
+}
+
+fn dated_tile_parameters() -> serde_json::Value {
+  let date = json!({"name": "date", "in": "path", "required": true,
+                    "schema": {"type": "string", "format": "date"}});
+  let mut parameters = tile_parameters();
+  parameters.as_array_mut().unwrap().insert(0, date);
+  parameters
+}
+
+fn bbox_parameter() -> serde_json::Value {
+  json!({"name": "bbox", "in": "query",
+         "description": "longitude_min,latitude_min,longitude_max,latitude_max",
+         "schema": {"type": "string"}})
+}
+
+fn unit_parameter() -> serde_json::Value {
+  json!({"name": "unit", "in": "query",
+         "description": "Temperature unit of returned values",
+         "schema": {"type": "string", "enum": ["c", "f"], "default": "c"}})
+}
+
+fn wind_unit_parameter() -> serde_json::Value {
+  json!({"name": "wind_unit", "in": "query",
+         "description": "Wind speed unit of returned values",
+         "schema": {"type": "string", "enum": ["ms", "kn", "mph"],
+                    "default": "ms"}})
+}
+
+fn units_schema() -> serde_json::Value {
+  json!({"type": "object",
+         "properties": {
+           "temperature": {"type": "string",
+                           "enum": ["celsius", "fahrenheit"]},
+           "pressure": {"type": "string", "enum": ["hPa"]},
+           "wind_speed": {"type": "string", "enum": ["m/s", "kn", "mph"]},
+           "humidity": {"type": "string", "enum": ["%"]}
+         }})
+}
+
+// Adds the 429 that the rate limited /api/map routes may return.
+fn rate_limited(mut responses: serde_json::Value) -> serde_json::Value {
+  let description = json!({"description": "Rate limit exceeded"});
+  responses.as_object_mut().unwrap().insert(String::from("429"), description);
+  responses
+}
+
+// A value and when it was first reached, null without data.
+fn extreme_schema() -> serde_json::Value {
+  json!({"type": "object", "nullable": true,
+         "properties": {
+           "value": {"type": "number"},
+           "time": {"type": "string", "format": "date-time"}
+         }})
+}
+
+fn png_response() -> serde_json::Value {
+  json!({"200": {"description": "PNG image",
+                 "content": {"image/png": {}}},
+         "400": {"description": "Invalid parameters"}})
+}
+
+fn json_response(schema: serde_json::Value) -> serde_json::Value {
+  json!({"200": {"description": "OK",
+                 "content": {"application/json": {"schema": schema}}},
+         "400": {"description": "Invalid parameters"}})
+}
+
+pub fn document() -> serde_json::Value {
+  json!({
+    "openapi": "3.0.0",
+    "info": {"title": "tenki", "version": "0.1.0"},
+    "paths": {
+      "/api/map/{zoom}/{x}/{y}/tile.png": {
+        "get": {
+          "summary": "Slippy map tile of the stations",
+          "parameters": tile_parameters(),
+          "responses": rate_limited(png_response())
+        }
+      },
+      "/api/map/{date}/{zoom}/{x}/{y}/tile.png": {
+        "get": {
+          "summary": "Tile of the measurements of one UTC day",
+          "parameters": dated_tile_parameters(),
+          "responses": rate_limited(png_response())
+        }
+      },
+      "/api/map/{zoom}/{x}/{y}/tile.txt": {
+        "get": {
+          "summary": "Tile as a data:image/png;base64 URI",
+          "parameters": tile_parameters(),
+          "responses": rate_limited(json!({
+            "200": {"description": "Data URI", "content": {"text/plain": {}}},
+            "400": {"description": "Invalid parameters"}
+          }))
+        }
+      },
+      "/api/map/{zoom}/{x}/{y}/tile.svg": {
+        "get": {
+          "summary": "Tile as SVG, with wind arrows for field=wind_speed",
+          "parameters": tile_parameters(),
+          "responses": rate_limited(json!({
+            "200": {"description": "SVG image",
+                    "content": {"image/svg+xml": {}}},
+            "400": {"description": "Invalid parameters"}
+          }))
+        }
+      },
+      "/api/tiles": {
+        "post": {
+          "summary": "Several tile.png with the same parameters at once",
+          "parameters": batch_parameters(),
+          "requestBody": {
+            "required": true,
+            "content": {"application/json": {"schema": {
+              "type": "array",
+              "maxItems": 256,
+              "items": {
+                "type": "object",
+                "required": ["zoom", "x", "y"],
+                "properties": {
+                  "zoom": {"type": "integer", "minimum": 0, "maximum": 18},
+                  "x": {"type": "integer", "minimum": 0},
+                  "y": {"type": "integer", "minimum": 0}
+                }
+              }
+            }}}
+          },
+          "responses": rate_limited(json!({
+            "200": {"description": "Little-endian u32 tile count, then a \
+                                    u32 offset from the start of the body \
+                                    and u32 length per tile, then the PNGs, \
+                                    in request order",
+                    "content": {"application/octet-stream": {}}},
+            "400": {"description": "Invalid parameters"}
+          }))
+        }
+      },
+      "/api/map/{zoom}/{x}/{y}/stats.json": {
+        "get": {
+          "summary": "What the corresponding tile.png draws",
+          "parameters": tile_parameters(),
+          "responses": rate_limited(json_response(json!({
+            "type": "object",
+            "properties": {
+              "stations_drawn": {"type": "integer"},
+              "min": {"type": "number", "nullable": true},
+              "max": {"type": "number", "nullable": true},
+              "units": units_schema()
+            }
+          })))
+        }
+      },
+      "/api/map/{zoom}/{x}/{y}/changed": {
+        "get": {
+          "summary": "Whether a tile's stations changed since a data version",
+          "parameters": changed_parameters(),
+          "responses": json_response(json!({
+            "type": "object",
+            "properties": {
+              "changed": {"type": "boolean"},
+              "version": {"type": "integer"}
+            }
+          }))
+        }
+      },
+      "/api/map/{zoom}/{x}/{y}/timeseries.json": {
+        "get": {
+          "summary": "Air temperatures of a tile's stations over time",
+          "description": "Interpolated at each time between the closest \
+                          measurements; at most 200 stations are listed.",
+          "parameters": timeseries_parameters(),
+          "responses": json_response(json!({
+            "type": "object",
+            "properties": {
+              "times": {"type": "array",
+                        "items": {"type": "string", "format": "date-time"}},
+              "stations": {
+                "type": "array",
+                "items": {
+                  "type": "object",
+                  "properties": {
+                    "usaf": {"type": "string"},
+                    "wban": {"type": "string"},
+                    "temperatures": {
+                      "type": "array",
+                      "items": {"type": "number", "nullable": true}
+                    }
+                  }
+                }
+              },
+              "truncated": {"type": "boolean"},
+              "units": units_schema()
+            }
+          }))
+        }
+      },
+      "/health": {
+        "get": {
+          "summary": "Whether the server is up and still loading stations",
+          "responses": json_response(json!({
+            "type": "object",
+            "properties": {
+              "loading": {"type": "boolean"},
+              "stations": {"type": "integer"},
+              "version": {"type": "integer"}
+            }
+          }))
+        }
+      },
+      "/api/colorscale": {
+        "get": {
+          "summary": "The color ramp tiles with the same parameters use",
+          "parameters": colorscale_parameters(),
+          "responses": json_response(json!({
+            "type": "object",
+            "properties": {
+              "min": {"type": "number"},
+              "max": {"type": "number"},
+              "stops": {
+                "type": "array",
+                "items": {
+                  "type": "object",
+                  "properties": {
+                    "value": {"type": "number"},
+                    "color": {"type": "string"}
+                  }
+                }
+              },
+              "units": units_schema()
+            }
+          }))
+        }
+      },
+      "/api/render3857": {
+        "get": {
+          "summary": "Render an arbitrary EPSG:3857 extent",
+          "parameters": [
+            {"name": "bbox", "in": "query", "required": true,
+             "description": "xmin,ymin,xmax,ymax in meters",
+             "schema": {"type": "string"}},
+            {"name": "size", "in": "query", "required": true,
+             "description": "WIDTHxHEIGHT in pixels, at most \
+                             --max-render-pixels in total",
+             "schema": {"type": "string"}},
+            {"name": "mode", "in": "query", "schema": {"type": "string"}}
+          ],
+          "responses": png_response()
+        }
+      },
+      "/api/field.tiff": {
+        "get": {
+          "summary": "Interpolated field as a float GeoTIFF",
+          "parameters": [
+            {"name": "bbox", "in": "query", "required": true,
+             "description": "longitude_min,latitude_min,longitude_max,\
+                             latitude_max in degrees",
+             "schema": {"type": "string"}},
+            {"name": "size", "in": "query", "required": true,
+             "description": "WIDTHxHEIGHT in pixels, at most \
+                             --max-render-pixels in total",
+             "schema": {"type": "string"}},
+            {"name": "field", "in": "query", "schema": field_schema()},
+            {"name": "start", "in": "query",
+             "schema": {"type": "string", "format": "date-time"}},
+            {"name": "end", "in": "query",
+             "schema": {"type": "string", "format": "date-time"}}
+          ],
+          "responses": {
+            "200": {"description": "Single-band float32 raster in EPSG:4326 \
+                                    with -9999 where no station is near",
+                    "content": {"image/tiff": {}}},
+            "400": {"description": "Invalid parameters"}
+          }
+        }
+      },
+      "/api/render/stack.png": {
+        "get": {
+          "summary": "Interpolated fields as the channels of one PNG",
+          "description": "Red is air temperature over -30 to 40 °C, green \
+                          pressure over 950 to 1050 hPa and blue wind \
+                          speed over 0 to 20 m/s. Each is the window mean, \
+                          clamped to its range and scaled to 1 to 255; 0 \
+                          means no station was near enough.",
+          "parameters": [
+            {"name": "bbox", "in": "query", "required": true,
+             "description": "longitude_min,latitude_min,longitude_max,\
+                             latitude_max in degrees",
+             "schema": {"type": "string"}},
+            {"name": "size", "in": "query", "required": true,
+             "description": "WIDTHxHEIGHT in pixels, at most \
+                             --max-render-pixels in total",
+             "schema": {"type": "string"}},
+            {"name": "start", "in": "query",
+             "schema": {"type": "string", "format": "date-time"}},
+            {"name": "end", "in": "query",
+             "schema": {"type": "string", "format": "date-time"}}
+          ],
+          "responses": {
+            "200": {"description": "RGB image in EPSG:4326",
+                    "content": {"image/png": {}}},
+            "400": {"description": "Invalid parameters"}
+          }
+        }
+      },
+      "/api/stats/measurement-histogram": {
+        "get": {
+          "summary": "Distribution of measurement counts across stations",
+          "parameters": [
+            {"name": "bins", "in": "query",
+             "schema": {"type": "integer", "minimum": 1, "default": 20}},
+            bbox_parameter()
+          ],
+          "responses": json_response(json!({
+            "type": "object",
+            "properties": {
+              "edges": {"type": "array", "items": {"type": "number"}},
+              "counts": {"type": "array", "items": {"type": "integer"}}
+            }
+          }))
+        }
+      },
+      "/api/stats/gaps": {
+        "get": {
+          "summary": "Stations whose largest gap exceeds a threshold",
+          "parameters": [
+            {"name": "hours", "in": "query", "required": true,
+             "schema": {"type": "number", "minimum": 0}}
+          ],
+          "responses": json_response(json!({
+            "type": "array",
+            "items": {
+              "type": "object",
+              "properties": {
+                "usaf": {"type": "string"},
+                "wban": {"type": "string"},
+                "name": {"type": "string", "nullable": true},
+                "gap_hours": {"type": "number"},
+                "gap_start": {"type": "string", "format": "date-time"},
+                "gap_end": {"type": "string", "format": "date-time"}
+              }
+            }
+          }))
+        }
+      },
+      "/api/station/{usaf}/{wban}/aggregate.json": {
+        "get": {
+          "summary": "Daily or monthly air temperature statistics of a station",
+          "parameters": [
+            {"name": "usaf", "in": "path", "required": true,
+             "schema": {"type": "string"}},
+            {"name": "wban", "in": "path", "required": true,
+             "schema": {"type": "string"}},
+            {"name": "by", "in": "query",
+             "schema": {"type": "string", "enum": ["day", "month"],
+                        "default": "day"}},
+            {"name": "stat", "in": "query",
+             "schema": {"type": "string", "enum": ["mean", "min", "max"],
+                        "default": "mean"}},
+            unit_parameter()
+          ],
+          "responses": json_response(json!({
+            "type": "object",
+            "properties": {
+              "usaf": {"type": "string"},
+              "wban": {"type": "string"},
+              "name": {"type": "string", "nullable": true},
+              "buckets": {
+                "type": "array",
+                "items": {
+                  "type": "object",
+                  "properties": {
+                    "start": {"type": "string"},
+                    "value": {"type": "number"}
+                  }
+                }
+              },
+              "units": units_schema()
+            }
+          }))
+        }
+      },
+      "/api/station/{usaf}/{wban}/measurements.json": {
+        "get": {
+          "summary": "All measurements of a station",
+          "parameters": [
+            {"name": "usaf", "in": "path", "required": true,
+             "schema": {"type": "string"}},
+            {"name": "wban", "in": "path", "required": true,
+             "schema": {"type": "string"}},
+            unit_parameter(),
+            wind_unit_parameter()
+          ],
+          "responses": json_response(json!({
+            "type": "object",
+            "properties": {
+              "usaf": {"type": "string"},
+              "wban": {"type": "string"},
+              "name": {"type": "string", "nullable": true},
+              "measurements": {
+                "type": "array",
+                "items": {
+                  "type": "object",
+                  "properties": {
+                    "time": {"type": "string", "format": "date-time"},
+                    "air_temperature": {"type": "number", "nullable": true},
+                    "dew_point": {"type": "number", "nullable": true},
+                    "air_pressure": {"type": "number", "nullable": true},
+                    "wind_speed": {"type": "number", "nullable": true},
+                    "wind_direction": {"type": "integer", "nullable": true},
+                    "source": {"type": "string", "nullable": true,
+                               "description": "Input file, only with \
+                                               --track-provenance"}
+                  }
+                }
+              },
+              "units": units_schema()
+            }
+          }))
+        }
+      },
+      "/api/station/{usaf}/{wban}.png": {
+        "get": {
+          "summary": "Sparkline of a station's air temperature",
+          "parameters": [
+            {"name": "usaf", "in": "path", "required": true,
+             "schema": {"type": "string"}},
+            {"name": "wban", "in": "path", "required": true,
+             "schema": {"type": "string"}},
+            {"name": "start", "in": "query",
+             "schema": {"type": "string", "format": "date-time"}},
+            {"name": "end", "in": "query",
+             "schema": {"type": "string", "format": "date-time"}},
+            {"name": "size", "in": "query",
+             "description": "WIDTHxHEIGHT in pixels",
+             "schema": {"type": "string", "default": "120x30"}}
+          ],
+          "responses": {
+            "200": {"description": "PNG image",
+                    "content": {"image/png": {}}},
+            "400": {"description": "Invalid parameters"},
+            "404": {"description": "Unknown station"}
+          }
+        }
+      },
+      "/api/station/{usaf}/{wban}/extremes": {
+        "get": {
+          "summary": "Highest and lowest temperature and highest wind speed \
+                      of a station",
+          "parameters": [
+            {"name": "usaf", "in": "path", "required": true,
+             "schema": {"type": "string"}},
+            {"name": "wban", "in": "path", "required": true,
+             "schema": {"type": "string"}},
+            unit_parameter(),
+            wind_unit_parameter()
+          ],
+          "responses": json_response(json!({
+            "type": "object",
+            "properties": {
+              "usaf": {"type": "string"},
+              "wban": {"type": "string"},
+              "name": {"type": "string", "nullable": true},
+              "max_temperature": extreme_schema(),
+              "min_temperature": extreme_schema(),
+              "max_wind_speed": extreme_schema(),
+              "units": units_schema()
+            }
+          }))
+        }
+      },
+      "/api/station/{usaf}/{wban}/data.parquet": {
+        "get": {
+          "summary": "All measurements of a station as a Parquet file",
+          "parameters": [
+            {"name": "usaf", "in": "path", "required": true,
+             "schema": {"type": "string"}},
+            {"name": "wban", "in": "path", "required": true,
+             "schema": {"type": "string"}}
+          ],
+          "responses": {
+            "200": {"description": "One row per measurement, null where \
+                                    a value is missing",
+                    "content": {"application/vnd.apache.parquet": {}}},
+            "404": {"description": "Unknown station"}
+          }
+        }
+      },
+      "/api/stations": {
+        "get": {
+          "summary": "The loaded stations ordered by id, a page at a time",
+          "parameters": [
+            {"name": "limit", "in": "query",
+             "schema": {"type": "integer", "minimum": 1, "maximum": 10000,
+                        "default": 1000}},
+            {"name": "offset", "in": "query",
+             "schema": {"type": "integer", "minimum": 0, "default": 0}}
+          ],
+          "responses": json_response(json!({
+            "type": "object",
+            "properties": {
+              "stations": {
+                "type": "array",
+                "items": {
+                  "type": "object",
+                  "properties": {
+                    "usaf": {"type": "string"},
+                    "wban": {"type": "string"},
+                    "name": {"type": "string", "nullable": true},
+                    "latitude": {"type": "number"},
+                    "longitude": {"type": "number"},
+                    "elevation": {"type": "integer", "nullable": true},
+                    "measurements": {"type": "integer"}
+                  }
+                }
+              },
+              "total": {"type": "integer"},
+              "next_offset": {"type": "integer", "nullable": true}
+            }
+          }))
+        }
+      },
+      "/api/alerts": {
+        "get": {
+          "summary": "Stations whose value in a window is beyond a threshold",
+          "description": "Each station's value is selected from the window \
+                          as for tiles.",
+          "parameters": [
+            {"name": "field", "in": "query", "required": true,
+             "schema": field_schema()},
+            {"name": "op", "in": "query", "required": true,
+             "description": "gt for values above the threshold, lt for \
+                             values below it",
+             "schema": {"type": "string", "enum": ["gt", "lt"]}},
+            {"name": "value", "in": "query", "required": true,
+             "description": "Threshold in the requested units",
+             "schema": {"type": "number"}},
+            {"name": "start", "in": "query",
+             "schema": {"type": "string", "format": "date-time"}},
+            {"name": "end", "in": "query",
+             "schema": {"type": "string", "format": "date-time"}},
+            unit_parameter(),
+            wind_unit_parameter()
+          ],
+          "responses": json_response(json!({
+            "type": "object",
+            "properties": {
+              "stations": {
+                "type": "array",
+                "items": {
+                  "type": "object",
+                  "properties": {
+                    "usaf": {"type": "string"},
+                    "wban": {"type": "string"},
+                    "name": {"type": "string", "nullable": true},
+                    "latitude": {"type": "number"},
+                    "longitude": {"type": "number"},
+                    "value": {"type": "number"}
+                  }
+                }
+              },
+              "units": units_schema()
+            }
+          }))
+        }
+      },
+      "/api/stats/field-range": {
+        "get": {
+          "summary": "Global range of a measurement field",
+          "parameters": [
+            {"name": "field", "in": "query", "required": true,
+             "schema": field_schema()},
+            unit_parameter(),
+            wind_unit_parameter()
+          ],
+          "responses": json_response(json!({
+            "type": "object",
+            "properties": {
+              "min": {"type": "number", "nullable": true},
+              "max": {"type": "number", "nullable": true},
+              "count": {"type": "integer"},
+              "units": units_schema()
+            }
+          }))
+        }
+      }
+    }
+  })
+}
+
+#[get("/api/openapi.json")]
+fn openapi() -> JSON<serde_json::Value> {
+  JSON(document())
+}
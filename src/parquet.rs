@@ -0,0 +1,524 @@
+// Minimal writer for Apache Parquet files: a single row group of flat,
+// uncompressed, PLAIN encoded columns, which is all the measurement dumps
+// need. Metadata is serialized with the Thrift compact protocol by hand.
+// Format reference: https://github.com/apache/parquet-format
+
+use std::io;
+use std::io::Write;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+const MAGIC: &'static [u8] = b"PAR1";
+
+// parquet.thrift enum values.
+const TYPE_INT32: i32 = 1;
+const TYPE_INT64: i32 = 2;
+const TYPE_FLOAT: i32 = 4;
+const REPETITION_REQUIRED: i32 = 0;
+const REPETITION_OPTIONAL: i32 = 1;
+const CONVERTED_TIMESTAMP_MILLIS: i32 = 9;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CODEC_UNCOMPRESSED: i32 = 0;
+const PAGE_DATA: i32 = 0;
+
+pub enum Values {
+  // Milliseconds since the epoch, never null.
+  Timestamp(Vec<i64>),
+  Int32(Vec<Option<i32>>),
+  Float(Vec<Option<f32>>),
+}
+
+pub struct Column {
+  pub name: String,
+  pub values: Values,
+}
+
+pub struct File {
+  pub columns: Vec<Column>,
+}
+
+// Thrift compact protocol, just the parts used here.
+mod thrift {
+  const TYPE_I32: u8 = 5;
+  const TYPE_I64: u8 = 6;
+  const TYPE_BINARY: u8 = 8;
+  const TYPE_LIST: u8 = 9;
+  pub const TYPE_STRUCT: u8 = 12;
+
+  pub struct Writer {
+    pub buf: Vec<u8>,
+    // Id of the previous field of each open struct, as field ids are delta
+    // encoded.
+    last_field: Vec<i16>,
+  }
+
+  impl Writer {
+    pub fn new() -> Writer {
+      Writer {
+        buf: vec![],
+        last_field: vec![0],
+      }
+    }
+
+    fn varint(&mut self, mut n: u64) {
+      while n >= 0x80 {
+        self.buf.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+      }
+      self.buf.push(n as u8);
+    }
+
+    fn zigzag(&mut self, n: i64) {
+      self.varint(((n << 1) ^ (n >> 63)) as u64);
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+      let delta = id - *self.last_field.last().unwrap();
+      if delta > 0 && delta <= 15 {
+        self.buf.push((delta as u8) << 4 | kind);
+      } else {
+        self.buf.push(kind);
+        self.zigzag(id as i64);
+      }
+      *self.last_field.last_mut().unwrap() = id;
+    }
+
+    pub fn i32(&mut self, id: i16, n: i32) {
+      self.field(id, TYPE_I32);
+      self.zigzag(n as i64);
+    }
+
+    pub fn i64(&mut self, id: i16, n: i64) {
+      self.field(id, TYPE_I64);
+      self.zigzag(n);
+    }
+
+    pub fn string(&mut self, id: i16, s: &str) {
+      self.field(id, TYPE_BINARY);
+      self.list_element_string(s);
+    }
+
+    pub fn list_element_i32(&mut self, n: i32) {
+      self.zigzag(n as i64);
+    }
+
+    pub fn list_element_string(&mut self, s: &str) {
+      self.varint(s.len() as u64);
+      self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn list_header(&mut self, kind: u8, len: usize) {
+      if len < 15 {
+        self.buf.push((len as u8) << 4 | kind);
+      } else {
+        self.buf.push(0xf0 | kind);
+        self.varint(len as u64);
+      }
+    }
+
+    pub fn list(&mut self, id: i16, kind: u8, len: usize) {
+      self.field(id, TYPE_LIST);
+      self.list_header(kind, len);
+    }
+
+    pub fn list_i32(&mut self, id: i16, len: usize) {
+      self.list(id, TYPE_I32, len);
+    }
+
+    pub fn list_string(&mut self, id: i16, len: usize) {
+      self.list(id, TYPE_BINARY, len);
+    }
+
+    // Starts a struct that is a field; list elements use begin_element.
+    pub fn begin_struct(&mut self, id: i16) {
+      self.field(id, TYPE_STRUCT);
+      self.begin_element();
+    }
+
+    pub fn begin_element(&mut self) {
+      self.last_field.push(0);
+    }
+
+    pub fn end_struct(&mut self) {
+      self.buf.push(0);
+      self.last_field.pop();
+    }
+  }
+}
+
+impl Column {
+  fn physical_type(&self) -> i32 {
+    match self.values {
+      Values::Timestamp(_) => TYPE_INT64,
+      Values::Int32(_) => TYPE_INT32,
+      Values::Float(_) => TYPE_FLOAT,
+    }
+  }
+
+  fn len(&self) -> usize {
+    match self.values {
+      Values::Timestamp(ref v) => v.len(),
+      Values::Int32(ref v) => v.len(),
+      Values::Float(ref v) => v.len(),
+    }
+  }
+
+  // Definition levels: 1 for present values, 0 for nulls. None for required
+  // columns, which have no levels.
+  fn definition_levels(&self) -> Option<Vec<bool>> {
+    match self.values {
+      Values::Timestamp(_) => None,
+      Values::Int32(ref v) => Some(v.iter().map(|v| v.is_some()).collect()),
+      Values::Float(ref v) => Some(v.iter().map(|v| v.is_some()).collect()),
+    }
+  }
+
+  // Writes the definition levels (if any) followed by the non-null values.
+  fn write_page(&self, w: &mut Vec<u8>) -> Result<(), io::Error> {
+    if let Some(levels) = self.definition_levels() {
+      let encoded = encode_levels(&levels);
+      try!(w.write_u32::<LittleEndian>(encoded.len() as u32));
+      try!(w.write_all(&encoded));
+    }
+    match self.values {
+      Values::Timestamp(ref values) => {
+        for &v in values {
+          try!(w.write_i64::<LittleEndian>(v));
+        }
+      }
+      Values::Int32(ref values) => {
+        for &v in values.iter().filter_map(|v| v.as_ref()) {
+          try!(w.write_i32::<LittleEndian>(v));
+        }
+      }
+      Values::Float(ref values) => {
+        for &v in values.iter().filter_map(|v| v.as_ref()) {
+          try!(w.write_f32::<LittleEndian>(v));
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+// RLE/bit-packing hybrid encoding with bit width 1, using only RLE runs.
+fn encode_levels(levels: &[bool]) -> Vec<u8> {
+  let mut encoded = vec![];
+  let mut i = 0;
+  while i < levels.len() {
+    let run = levels[i..].iter().take_while(|&&l| l == levels[i]).count();
+    let mut header = (run as u64) << 1;
+    while header >= 0x80 {
+      encoded.push((header as u8 & 0x7f) | 0x80);
+      header >>= 7;
+    }
+    encoded.push(header as u8);
+    encoded.push(levels[i] as u8);
+    i += run;
+  }
+  encoded
+}
+
+impl File {
+  pub fn write(&self, w: &mut Write) -> Result<(), io::Error> {
+    let num_rows = self.columns.first().map(|c| c.len()).unwrap_or(0);
+    for column in &self.columns {
+      ret_check_eq!(column.len(), num_rows);
+    }
+
+    let mut out = Vec::from(MAGIC);
+    // (offset, size) of each column chunk.
+    let mut chunks = vec![];
+    for column in &self.columns {
+      let mut page = vec![];
+      try!(column.write_page(&mut page));
+
+      let mut header = thrift::Writer::new();
+      header.i32(1, PAGE_DATA);
+      header.i32(2, page.len() as i32);
+      header.i32(3, page.len() as i32);
+      header.begin_struct(5);
+      header.i32(1, num_rows as i32);
+      header.i32(2, ENCODING_PLAIN);
+      header.i32(3, ENCODING_RLE);
+      header.i32(4, ENCODING_RLE);
+      header.end_struct();
+      header.end_struct();
+
+      chunks.push((out.len(), header.buf.len() + page.len()));
+      out.extend_from_slice(&header.buf);
+      out.extend_from_slice(&page);
+    }
+
+    let mut meta = thrift::Writer::new();
+    meta.i32(1, 1);
+    meta.list(2, thrift::TYPE_STRUCT, self.columns.len() + 1);
+    meta.begin_element();
+    meta.string(4, "schema");
+    meta.i32(5, self.columns.len() as i32);
+    meta.end_struct();
+    for column in &self.columns {
+      meta.begin_element();
+      meta.i32(1, column.physical_type());
+      meta.i32(3,
+               if column.definition_levels().is_some() {
+                 REPETITION_OPTIONAL
+               } else {
+                 REPETITION_REQUIRED
+               });
+      meta.string(4, &column.name);
+      if let Values::Timestamp(_) = column.values {
+        meta.i32(6, CONVERTED_TIMESTAMP_MILLIS);
+      }
+      meta.end_struct();
+    }
+    meta.i64(3, num_rows as i64);
+
+    meta.list(4, thrift::TYPE_STRUCT, 1);
+    meta.begin_element();
+    meta.list(1, thrift::TYPE_STRUCT, self.columns.len());
+    for (column, &(offset, size)) in self.columns.iter().zip(&chunks) {
+      meta.begin_element();
+      meta.i64(2, offset as i64);
+      meta.begin_struct(3);
+      meta.i32(1, column.physical_type());
+      meta.list_i32(2, 2);
+      meta.list_element_i32(ENCODING_PLAIN);
+      meta.list_element_i32(ENCODING_RLE);
+      meta.list_string(3, 1);
+      meta.list_element_string(&column.name);
+      meta.i32(4, CODEC_UNCOMPRESSED);
+      meta.i64(5, num_rows as i64);
+      meta.i64(6, size as i64);
+      meta.i64(7, size as i64);
+      meta.i64(9, offset as i64);
+      meta.end_struct();
+      meta.end_struct();
+    }
+    meta.i64(2, chunks.iter().map(|&(_, size)| size).sum::<usize>() as i64);
+    meta.i64(3, num_rows as i64);
+    meta.end_struct();
+    meta.end_struct();
+
+    out.extend_from_slice(&meta.buf);
+    try!(out.write_u32::<LittleEndian>(meta.buf.len() as u32));
+    out.extend_from_slice(MAGIC);
+    w.write_all(&out)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use byteorder::{ByteOrder, LittleEndian};
+
+  // A decoded Thrift compact protocol value.
+  enum Value {
+    Int(i64),
+    Binary(Vec<u8>),
+    List(Vec<Value>),
+    Struct(Vec<(i16, Value)>),
+  }
+
+  impl Value {
+    fn field(&self, id: i16) -> &Value {
+      match *self {
+        Value::Struct(ref fields) => {
+          &fields.iter().find(|field| field.0 == id).unwrap().1
+        }
+        _ => panic!("field {} of a non-struct", id),
+      }
+    }
+
+    fn int(&self) -> i64 {
+      match *self {
+        Value::Int(n) => n,
+        _ => panic!("not an integer"),
+      }
+    }
+
+    fn string(&self) -> String {
+      match *self {
+        Value::Binary(ref bytes) => String::from_utf8(bytes.clone()).unwrap(),
+        _ => panic!("not a string"),
+      }
+    }
+
+    fn list(&self) -> &[Value] {
+      match *self {
+        Value::List(ref values) => values,
+        _ => panic!("not a list"),
+      }
+    }
+  }
+
+  struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+  }
+
+  impl<'a> Reader<'a> {
+    fn byte(&mut self) -> u8 {
+      self.pos += 1;
+      self.bytes[self.pos - 1]
+    }
+
+    fn varint(&mut self) -> u64 {
+      let mut n = 0;
+      let mut shift = 0;
+      loop {
+        let b = self.byte();
+        n |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+          return n;
+        }
+        shift += 7;
+      }
+    }
+
+    fn zigzag(&mut self) -> i64 {
+      let n = self.varint();
+      (n >> 1) as i64 ^ -((n & 1) as i64)
+    }
+
+    fn value(&mut self, kind: u8) -> Value {
+      match kind {
+        5 | 6 => Value::Int(self.zigzag()),
+        8 => {
+          let len = self.varint() as usize;
+          self.pos += len;
+          Value::Binary(self.bytes[self.pos - len..self.pos].to_vec())
+        }
+        9 => {
+          let header = self.byte();
+          let mut len = (header >> 4) as usize;
+          if len == 15 {
+            len = self.varint() as usize;
+          }
+          let mut values = vec![];
+          for _ in 0..len {
+            values.push(self.value(header & 0x0f));
+          }
+          Value::List(values)
+        }
+        12 => {
+          let mut fields = vec![];
+          let mut last = 0;
+          loop {
+            let header = self.byte();
+            if header == 0 {
+              return Value::Struct(fields);
+            }
+            let id = match (header >> 4) as i16 {
+              0 => self.zigzag() as i16,
+              delta => last + delta,
+            };
+            last = id;
+            fields.push((id, self.value(header & 0x0f)));
+          }
+        }
+        _ => panic!("unexpected type {}", kind),
+      }
+    }
+  }
+
+  // Decodes the definition levels at the start of a page, returning them and
+  // the values that follow.
+  fn levels(page: &[u8]) -> (Vec<bool>, &[u8]) {
+    let levels_len = LittleEndian::read_u32(page) as usize;
+    let mut reader = Reader {
+      bytes: &page[..4 + levels_len],
+      pos: 4,
+    };
+    let mut levels = vec![];
+    while reader.pos < 4 + levels_len {
+      let header = reader.varint();
+      assert_eq!(header & 1, 0, "bit-packed run");
+      let level = reader.byte() == 1;
+      levels.extend((0..header >> 1).map(|_| level));
+    }
+    (levels, &page[4 + levels_len..])
+  }
+
+  #[test]
+  fn reads_back() {
+    let file = File {
+      columns: vec![Column {
+                      name: String::from("time"),
+                      values: Values::Timestamp(vec![1000, 2000, 3000, 4000]),
+                    },
+                    Column {
+                      name: String::from("temperature"),
+                      values: Values::Float(vec![Some(1.5),
+                                                 None,
+                                                 None,
+                                                 Some(-2.0)]),
+                    },
+                    Column {
+                      name: String::from("elevation"),
+                      values: Values::Int32(vec![None,
+                                                 Some(7),
+                                                 Some(8),
+                                                 Some(9)]),
+                    }],
+    };
+    let mut bytes = vec![];
+    file.write(&mut bytes).unwrap();
+    let len = bytes.len();
+    assert_eq!(&bytes[..4], MAGIC);
+    assert_eq!(&bytes[len - 4..], MAGIC);
+
+    // The footer is followed by its length, and nothing else but the magic.
+    let footer_len = LittleEndian::read_u32(&bytes[len - 8..len - 4]) as usize;
+    let mut footer = Reader {
+      bytes: &bytes,
+      pos: len - 8 - footer_len,
+    };
+    let meta = footer.value(thrift::TYPE_STRUCT);
+    assert_eq!(footer.pos, len - 8);
+    assert_eq!(meta.field(3).int(), 4);
+
+    let schema = meta.field(2).list();
+    assert_eq!(schema.len(), 4);
+    assert_eq!(schema[0].field(5).int(), 3);
+    let expected = [("time", TYPE_INT64, REPETITION_REQUIRED),
+                    ("temperature", TYPE_FLOAT, REPETITION_OPTIONAL),
+                    ("elevation", TYPE_INT32, REPETITION_OPTIONAL)];
+    for (element, &(name, kind, repetition)) in schema[1..]
+      .iter()
+      .zip(&expected) {
+      assert_eq!(element.field(4).string(), name);
+      assert_eq!(element.field(1).int(), kind as i64);
+      assert_eq!(element.field(3).int(), repetition as i64);
+    }
+    assert_eq!(schema[1].field(6).int(), CONVERTED_TIMESTAMP_MILLIS as i64);
+
+    // Each column chunk's page: the definition levels of optional columns,
+    // then only the values that aren't null.
+    let chunks = meta.field(4).list()[0].field(1).list();
+    let mut pages = vec![];
+    for chunk in chunks {
+      let mut page = Reader {
+        bytes: &bytes,
+        pos: chunk.field(2).int() as usize,
+      };
+      let header = page.value(thrift::TYPE_STRUCT);
+      assert_eq!(header.field(5).field(1).int(), 4);
+      let size = header.field(3).int() as usize;
+      pages.push(&bytes[page.pos..page.pos + size]);
+    }
+    assert_eq!(pages.len(), 3);
+    assert_eq!(LittleEndian::read_i64(&pages[0][24..]), 4000);
+    assert_eq!(pages[0].len(), 4 * 8);
+
+    let (present, values) = levels(pages[1]);
+    assert_eq!(present, vec![true, false, false, true]);
+    assert_eq!(values.len(), 2 * 4);
+    assert_eq!(LittleEndian::read_f32(&values[4..]), -2.0);
+    let (present, values) = levels(pages[2]);
+    assert_eq!(present, vec![false, true, true, true]);
+    assert_eq!(values.len(), 3 * 4);
+    assert_eq!(LittleEndian::read_i32(values), 7);
+  }
+}
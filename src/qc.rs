@@ -0,0 +1,202 @@
+// Post-load quality control passes over parsed stations.
+
+use time::Duration;
+
+use WeatherMeasurement;
+use WeatherStation;
+
+// ISH quality codes for "passed all quality control checks".
+const PASSED: &'static [u8] = b"15";
+
+// Whether every value present in the measurement passed all checks.
+fn verified(m: &WeatherMeasurement) -> bool {
+  let passed = |value: bool, code: u8| !value || PASSED.contains(&code);
+  passed(m.wind.is_some(), m.quality.wind_speed) &&
+  passed(m.air_temperature.is_some(), m.quality.air_temperature) &&
+  passed(m.dew_point.is_some(), m.quality.dew_point) &&
+  passed(m.air_pressure.is_some(), m.quality.air_pressure)
+}
+
+// How far the dew point may exceed the air temperature before the pair is
+// taken to be wrong rather than rounded, in °C.
+const DEW_POINT_TOLERANCE: f32 = 0.1;
+
+// Whether the dew point is above the air temperature, which is physically
+// impossible.
+fn inconsistent(m: &WeatherMeasurement) -> bool {
+  match (m.dew_point, m.air_temperature) {
+    (Some(dew_point), Some(t)) => dew_point > t + DEW_POINT_TOLERANCE,
+    _ => false,
+  }
+}
+
+// Counts the measurements whose dew point is above their air temperature.
+pub fn count_inconsistent(station: &WeatherStation) -> usize {
+  station.measurements.iter().filter(|m| inconsistent(m)).count()
+}
+
+// Drops measurements whose dew point is above their air temperature. Returns
+// the number of measurements dropped.
+pub fn drop_inconsistent(station: &mut WeatherStation) -> usize {
+  let num_measurements = station.measurements.len();
+  station.measurements.retain(|m| !inconsistent(m));
+  num_measurements - station.measurements.len()
+}
+
+// Drops measurements with any value that didn't pass all quality control
+// checks. Returns the number of measurements dropped.
+pub fn drop_unverified(station: &mut WeatherStation) -> usize {
+  let num_measurements = station.measurements.len();
+  station.measurements.retain(verified);
+  num_measurements - station.measurements.len()
+}
+
+// Drops air temperatures more than k standard deviations away from the
+// station's mean. Returns the number of values dropped.
+//
+// The standard deviation includes the outliers themselves, so with n values
+// none can be more than (n - 1) / sqrt(n) of them away: at k = 3 stations need
+// at least 11 temperatures before anything is dropped.
+pub fn sigma_clip(station: &mut WeatherStation, k: f32) -> usize {
+  let values = station.measurements
+    .iter()
+    .filter_map(|m| m.air_temperature)
+    .collect::<Vec<_>>();
+  if values.len() < 2 {
+    return 0;
+  }
+  let n = values.len() as f32;
+  let mean = values.iter().sum::<f32>() / n;
+  let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / n;
+  let limit = k * variance.sqrt();
+
+  let mut num_dropped = 0;
+  for m in &mut station.measurements {
+    if m.air_temperature.map(|t| (t - mean).abs() > limit).unwrap_or(false) {
+      m.air_temperature = None;
+      num_dropped += 1;
+    }
+  }
+  num_dropped
+}
+
+// Linearly interpolates missing air temperatures between the surrounding
+// known values, as long as those are at most max_gap apart. Expects the
+// measurements sorted by time. Returns the number of values filled.
+pub fn fill_gaps(station: &mut WeatherStation, max_gap: Duration) -> usize {
+  let known = station.measurements
+    .iter()
+    .enumerate()
+    .filter(|&(_, m)| m.air_temperature.is_some())
+    .map(|(i, _)| i)
+    .collect::<Vec<_>>();
+
+  let mut num_filled = 0;
+  for pair in known.windows(2) {
+    let (before, after) = (pair[0], pair[1]);
+    if after - before < 2 {
+      continue;
+    }
+    let (start, t0) = (station.measurements[before].datetime,
+                       station.measurements[before].air_temperature.unwrap());
+    let (end, t1) = (station.measurements[after].datetime,
+                     station.measurements[after].air_temperature.unwrap());
+    let span = (end - start).num_seconds();
+    if end - start > max_gap || span <= 0 {
+      continue;
+    }
+    for m in &mut station.measurements[before + 1..after] {
+      let f = (m.datetime - start).num_seconds() as f32 / span as f32;
+      m.air_temperature = Some(t0 + f * (t1 - t0));
+      num_filled += 1;
+    }
+  }
+  num_filled
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing;
+
+  #[test]
+  fn sigma_clip_drops_a_single_outlier() {
+    let mut temperatures = (0..20)
+      .map(|i| Some(if i % 2 == 0 { 9.5 } else { 10.5 }))
+      .collect::<Vec<_>>();
+    temperatures[7] = Some(40.0);
+    let mut station =
+      testing::station("010000", 0.0, 0.0, testing::series(&temperatures));
+    assert_eq!(sigma_clip(&mut station, 3.0), 1);
+    assert_eq!(station.measurements[7].air_temperature, None);
+  }
+
+  #[test]
+  fn sigma_clip_keeps_short_series() {
+    let mut temperatures = vec![Some(10.0); 10];
+    temperatures[7] = Some(1000.0);
+    let mut station =
+      testing::station("010000", 0.0, 0.0, testing::series(&temperatures));
+    assert_eq!(sigma_clip(&mut station, 3.0), 0);
+  }
+
+  #[test]
+  fn fill_gaps_interpolates_a_missing_sample() {
+    let mut station =
+      testing::station("010000",
+                       0.0,
+                       0.0,
+                       testing::series(&[Some(10.0), None, Some(14.0)]));
+    assert_eq!(fill_gaps(&mut station, Duration::hours(3)), 1);
+    assert_eq!(station.measurements[1].air_temperature, Some(12.0));
+  }
+
+  #[test]
+  fn fill_gaps_skips_long_gaps() {
+    let mut station =
+      testing::station("010000",
+                       0.0,
+                       0.0,
+                       testing::series(&[Some(10.0), None, Some(14.0)]));
+    assert_eq!(fill_gaps(&mut station, Duration::hours(1)), 0);
+    assert_eq!(station.measurements[1].air_temperature, None);
+  }
+
+  #[test]
+  fn drop_unverified_keeps_only_passed_values() {
+    let mut measurements = testing::series(&[Some(1.0), Some(2.0), None]);
+    // Suspect temperature.
+    measurements[1].quality.air_temperature = b'2';
+    // A failed code on a value that is missing anyway doesn't count.
+    measurements[2].quality.air_temperature = b'3';
+    measurements[2].air_pressure = Some(1000.0);
+    let mut station = testing::station("010000", 0.0, 0.0, measurements);
+    assert_eq!(drop_unverified(&mut station), 1);
+    let kept = station.measurements
+      .iter()
+      .map(|m| m.datetime)
+      .collect::<Vec<_>>();
+    assert_eq!(kept, vec![testing::time(0), testing::time(2)]);
+  }
+
+  #[test]
+  fn drop_inconsistent_drops_dew_points_above_temperature() {
+    let mut measurements =
+      testing::series(&[Some(10.0), Some(10.0), Some(10.0), None]);
+    measurements[0].dew_point = Some(12.0);
+    // Within the rounding tolerance.
+    measurements[1].dew_point = Some(10.05);
+    measurements[2].dew_point = Some(8.0);
+    measurements[3].dew_point = Some(12.0);
+    let mut station = testing::station("010000", 0.0, 0.0, measurements);
+    assert_eq!(count_inconsistent(&station), 1);
+    assert_eq!(drop_inconsistent(&mut station), 1);
+    let kept = station.measurements
+      .iter()
+      .map(|m| m.datetime)
+      .collect::<Vec<_>>();
+    assert_eq!(kept,
+               vec![testing::time(1), testing::time(2), testing::time(3)]);
+    assert_eq!(count_inconsistent(&station), 0);
+  }
+}
@@ -0,0 +1,128 @@
+// Per-client token buckets limiting how fast a client can request map tiles.
+// Rocket has no middleware, so routes opt in by taking a TileRateLimit guard.
+
+use std::collections;
+use std::net;
+use std::sync;
+use std::time;
+
+use rocket;
+use rocket::Outcome;
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+
+// Above this many tracked clients, buckets that have refilled completely are
+// forgotten, they are indistinguishable from new ones.
+const MAX_BUCKETS: usize = 10000;
+
+pub struct RateLimiter {
+  // Tokens added per second; None disables limiting.
+  rate: Option<f64>,
+  burst: f64,
+  // Identify clients by X-Forwarded-For rather than the peer address.
+  trust_proxy: bool,
+  buckets: sync::Mutex<collections::HashMap<net::IpAddr, (time::Instant, f64)>>,
+}
+
+fn seconds(d: time::Duration) -> f64 {
+  d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+impl RateLimiter {
+  pub fn new(rate: Option<f64>, burst: f64, trust_proxy: bool) -> RateLimiter {
+    RateLimiter {
+      rate: rate,
+      burst: burst,
+      trust_proxy: trust_proxy,
+      buckets: sync::Mutex::new(collections::HashMap::new()),
+    }
+  }
+
+  // Takes a token from the client's bucket, or returns false if it is empty.
+  pub fn allow(&self, client: net::IpAddr) -> bool {
+    let rate = match self.rate {
+      Some(rate) => rate,
+      None => return true,
+    };
+    let now = time::Instant::now();
+    let mut buckets = self.buckets.lock().unwrap();
+    if buckets.len() > MAX_BUCKETS {
+      let full = buckets.iter()
+        .filter(|&(_, &(last, tokens))| {
+          tokens + seconds(now.duration_since(last)) * rate >= self.burst
+        })
+        .map(|(client, _)| *client)
+        .collect::<Vec<_>>();
+      for client in full {
+        buckets.remove(&client);
+      }
+    }
+
+    let bucket = buckets.entry(client).or_insert((now, self.burst));
+    let refill = seconds(now.duration_since(bucket.0)) * rate;
+    *bucket = (now, (bucket.1 + refill).min(self.burst));
+    if bucket.1 < 1.0 {
+      return false;
+    }
+    bucket.1 -= 1.0;
+    true
+  }
+
+  fn client(&self, request: &Request) -> Option<net::IpAddr> {
+    if self.trust_proxy {
+      // The first entry is the original client, later ones are proxies.
+      let forwarded = request.headers()
+        .get_one("X-Forwarded-For")
+        .and_then(|f| f.split(',').next())
+        .and_then(|ip| ip.trim().parse::<net::IpAddr>().ok());
+      if forwarded.is_some() {
+        return forwarded;
+      }
+    }
+    request.remote().map(|address| address.ip())
+  }
+}
+
+// Fails the request with 429 Too Many Requests if the client is over its
+// limit.
+pub struct TileRateLimit;
+
+impl<'a, 'r> FromRequest<'a, 'r> for TileRateLimit {
+  type Error = ();
+
+  fn from_request(request: &'a Request<'r>)
+                  -> request::Outcome<TileRateLimit, ()> {
+    let limiter = match rocket::State::<RateLimiter>::from_request(request) {
+      Outcome::Success(limiter) => limiter,
+      _ => return Outcome::Failure((Status::InternalServerError, ())),
+    };
+    match limiter.client(request) {
+      Some(client) if !limiter.allow(client) => {
+        Outcome::Failure((Status::TooManyRequests, ()))
+      }
+      _ => Outcome::Success(TileRateLimit),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn allows_a_burst_then_limits() {
+    let limiter = RateLimiter::new(Some(0.001), 3.0, false);
+    let client = net::IpAddr::from([192, 0, 2, 1]);
+    let results = (0..5).map(|_| limiter.allow(client)).collect::<Vec<_>>();
+    assert_eq!(results, vec![true, true, true, false, false]);
+    // Other clients have buckets of their own.
+    assert!(limiter.allow(net::IpAddr::from([192, 0, 2, 2])));
+  }
+
+  #[test]
+  fn allows_everything_without_a_rate() {
+    let limiter = RateLimiter::new(None, 1.0, false);
+    let client = net::IpAddr::from([192, 0, 2, 1]);
+    assert!((0..10).all(|_| limiter.allow(client)));
+  }
+}
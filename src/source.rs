@@ -0,0 +1,311 @@
+// Where the tile routes get their stations from: normally the stations loaded
+// into memory, or with --sqlite a read-only database queried for every tile,
+// for datasets that don't fit into memory.
+
+use std::io;
+use std::path;
+use std::sync;
+
+use chrono::prelude::*;
+use rusqlite;
+use time;
+
+use store::StationStore;
+use {QualityCodes, WeatherMeasurement, WeatherStation, WindMeasurement};
+
+// The tables --sqlite reads. Like ndjson input, the data is expected to be
+// cleaned already, so every value is taken to have passed quality control.
+pub const SCHEMA: &'static str = "
+CREATE TABLE stations (
+  usaf TEXT NOT NULL,
+  wban TEXT NOT NULL,
+  latitude REAL NOT NULL,
+  longitude REAL NOT NULL,
+  elevation INTEGER,
+  PRIMARY KEY (usaf, wban)
+);
+CREATE TABLE measurements (
+  usaf TEXT NOT NULL,
+  wban TEXT NOT NULL,
+  -- Seconds since the epoch.
+  time INTEGER NOT NULL,
+  -- ISH report type such as FM-12.
+  report_type TEXT,
+  -- Degrees Celsius.
+  temp REAL,
+  dew_point REAL,
+  -- Hectopascals.
+  pressure REAL,
+  -- Meters per second, and degrees the wind is coming from.
+  wind_speed REAL,
+  wind_direction INTEGER
+);
+CREATE INDEX measurements_by_station ON measurements (usaf, wban, time);
+";
+
+pub trait StationSource {
+  // Calls f with a store of at least the stations within the bounds, holding
+  // at least their measurements in [start, end).
+  fn with_stations(&self,
+                   longitude_min: f32,
+                   longitude_max: f32,
+                   latitude_min: f32,
+                   latitude_max: f32,
+                   start: DateTime<UTC>,
+                   end: DateTime<UTC>,
+                   f: &mut FnMut(&StationStore))
+                   -> Result<(), io::Error>;
+}
+
+impl StationSource for StationStore {
+  fn with_stations(&self,
+                   _: f32,
+                   _: f32,
+                   _: f32,
+                   _: f32,
+                   _: DateTime<UTC>,
+                   _: DateTime<UTC>,
+                   f: &mut FnMut(&StationStore))
+                   -> Result<(), io::Error> {
+    f(self);
+    Ok(())
+  }
+}
+
+pub struct SqliteSource {
+  // A connection can't be used from several threads at once, so requests
+  // take turns.
+  connection: sync::Mutex<rusqlite::Connection>,
+}
+
+fn io_error(error: rusqlite::Error) -> io::Error {
+  io::Error::new(io::ErrorKind::Other, error)
+}
+
+// A row of the measurements query in with_stations.
+fn measurement(row: &rusqlite::Row)
+               -> Result<WeatherMeasurement, rusqlite::Error> {
+  let time: i64 = try!(row.get_checked(0));
+  let code: Option<String> = try!(row.get_checked(1));
+  let mut report_type = *b"     ";
+  if let Some(code) = code {
+    if code.len() == report_type.len() {
+      report_type.copy_from_slice(code.as_bytes());
+    }
+  }
+  let value = |i: i32| -> Result<Option<f32>, rusqlite::Error> {
+    let value: Option<f64> = try!(row.get_checked(i));
+    Ok(value.map(|v| v as f32))
+  };
+  let wind_direction: Option<i32> = try!(row.get_checked(6));
+  Ok(WeatherMeasurement {
+    datetime: UTC.timestamp(time, 0),
+    report_type: report_type,
+    wind: try!(value(5))
+      .map(|speed| WindMeasurement::observed(speed, wind_direction)),
+    air_temperature: try!(value(2)),
+    dew_point: try!(value(3)),
+    air_pressure: try!(value(4)),
+    precip_type: None,
+    source: None,
+    quality: QualityCodes {
+      wind_speed: b'1',
+      air_temperature: b'1',
+      dew_point: b'1',
+      air_pressure: b'1',
+    },
+  })
+}
+
+impl SqliteSource {
+  pub fn new(connection: rusqlite::Connection) -> SqliteSource {
+    SqliteSource { connection: sync::Mutex::new(connection) }
+  }
+
+  // Opens a database with the tables in SCHEMA, which is never written to.
+  pub fn open(filename: &path::Path) -> Result<SqliteSource, io::Error> {
+    let flags = rusqlite::SQLITE_OPEN_READ_ONLY;
+    let connection =
+      try!(rusqlite::Connection::open_with_flags(filename, flags)
+        .map_err(io_error));
+    Ok(SqliteSource::new(connection))
+  }
+
+  // The stations within the bounds with their measurements in [start, end),
+  // ordered by id.
+  pub fn stations(&self,
+                  longitude_min: f32,
+                  longitude_max: f32,
+                  latitude_min: f32,
+                  latitude_max: f32,
+                  start: DateTime<UTC>,
+                  end: DateTime<UTC>)
+                  -> Result<Vec<WeatherStation>, rusqlite::Error> {
+    let connection = self.connection.lock().unwrap();
+    let mut query = try!(connection.prepare("SELECT usaf, wban, latitude, \
+                                             longitude, elevation \
+                                             FROM stations \
+                                             WHERE longitude \
+                                             BETWEEN ?1 AND ?2 \
+                                             AND latitude BETWEEN ?3 AND ?4 \
+                                             ORDER BY usaf, wban"));
+    let rows = try!(query.query_map(&[&(longitude_min as f64),
+                                      &(longitude_max as f64),
+                                      &(latitude_min as f64),
+                                      &(latitude_max as f64)],
+                                    |row| -> Result<_, rusqlite::Error> {
+      let latitude: f64 = try!(row.get_checked(2));
+      let longitude: f64 = try!(row.get_checked(3));
+      Ok(WeatherStation {
+        usaf: try!(row.get_checked(0)),
+        wban: try!(row.get_checked(1)),
+        name: None,
+        country: None,
+        state: None,
+        latitude: latitude as f32,
+        longitude: longitude as f32,
+        elevation: try!(row.get_checked(4)),
+        measurements: vec![],
+      })
+    }));
+    let mut stations = vec![];
+    for station in rows {
+      stations.push(try!(try!(station)));
+    }
+
+    let mut query = try!(connection.prepare("SELECT time, report_type, temp, \
+                                             dew_point, pressure, wind_speed, \
+                                             wind_direction \
+                                             FROM measurements \
+                                             WHERE usaf = ?1 AND wban = ?2 \
+                                             AND time >= ?3 AND time < ?4 \
+                                             ORDER BY time"));
+    for station in &mut stations {
+      let rows = try!(query.query_map(&[&station.usaf,
+                                        &station.wban,
+                                        &start.timestamp(),
+                                        &end.timestamp()],
+                                      measurement));
+      let mut measurements = vec![];
+      for m in rows {
+        measurements.push(try!(try!(m)));
+      }
+      station.measurements = measurements;
+    }
+    Ok(stations)
+  }
+}
+
+impl StationSource for SqliteSource {
+  fn with_stations(&self,
+                   longitude_min: f32,
+                   longitude_max: f32,
+                   latitude_min: f32,
+                   latitude_max: f32,
+                   start: DateTime<UTC>,
+                   end: DateTime<UTC>,
+                   f: &mut FnMut(&StationStore))
+                   -> Result<(), io::Error> {
+    // A day either side covers the shifted windows of localtime and the
+    // neighbors interpolation looks for.
+    let margin = time::Duration::days(1);
+    let stations = try!(self.stations(longitude_min,
+                                      longitude_max,
+                                      latitude_min,
+                                      latitude_max,
+                                      start - margin,
+                                      end + margin)
+      .map_err(io_error));
+    // The database is read-only, so the version never changes.
+    f(&StationStore::new(stations, 0));
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing;
+
+  // An in-memory database holding the stations.
+  fn database(stations: &[WeatherStation]) -> SqliteSource {
+    let connection = rusqlite::Connection::open_in_memory().unwrap();
+    testing::insert_stations(&connection, stations);
+    SqliteSource::new(connection)
+  }
+
+  #[test]
+  fn reads_stations_within_bounds_and_window() {
+    let mut measurements = testing::series(&[Some(1.0),
+                                             Some(2.0),
+                                             None,
+                                             Some(4.0)]);
+    measurements[1].dew_point = Some(-1.5);
+    measurements[2].wind = Some(WindMeasurement::observed(3.5, Some(270)));
+    let source = database(&[testing::station("020000",
+                                             10.0,
+                                             20.0,
+                                             measurements.clone()),
+                            testing::station("010000",
+                                             -10.0,
+                                             20.0,
+                                             measurements.clone()),
+                            testing::station("030000",
+                                             50.0,
+                                             20.0,
+                                             measurements.clone())]);
+
+    let stations = source.stations(0.0,
+                                   30.0,
+                                   -20.0,
+                                   20.0,
+                                   testing::time(1),
+                                   testing::time(3))
+      .unwrap();
+    let usafs = stations.iter().map(|s| s.usaf.as_str()).collect::<Vec<_>>();
+    assert_eq!(usafs, vec!["010000", "020000"]);
+    assert_eq!((stations[1].latitude, stations[1].longitude), (10.0, 20.0));
+    assert!(stations[1].measurements == measurements[1..3].to_vec());
+
+    let none = source.stations(100.0,
+                               110.0,
+                               -20.0,
+                               20.0,
+                               testing::time(0),
+                               testing::time(4))
+      .unwrap();
+    assert!(none.is_empty());
+  }
+
+  #[test]
+  fn with_stations_widens_the_window() {
+    let hours = (0..72).map(|h| Some(h as f32)).collect::<Vec<_>>();
+    let station = testing::station("010000", 0.0, 0.0, testing::series(&hours));
+    let source = database(&[station]);
+    let mut num_measurements = 0;
+    source.with_stations(-1.0,
+                         1.0,
+                         -1.0,
+                         1.0,
+                         testing::time(30),
+                         testing::time(40),
+                         &mut |store| {
+        num_measurements = store.stations[0].measurements.len()
+      })
+      .unwrap();
+    // From hour 6 to hour 63.
+    assert_eq!(num_measurements, 58);
+  }
+
+  #[test]
+  fn never_writes_to_the_database() {
+    let filename = testing::scratch_dir("sqlite").join("stations.db");
+    {
+      let connection = rusqlite::Connection::open(&filename).unwrap();
+      testing::insert_stations(&connection, &[]);
+    }
+    let source = SqliteSource::open(&filename).unwrap();
+    let connection = source.connection.lock().unwrap();
+    assert!(connection.execute("DELETE FROM stations", &[]).is_err());
+  }
+}
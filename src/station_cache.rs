@@ -0,0 +1,411 @@
+// Binary snapshot of the stations parsed from a --directory, so that
+// restarts can skip parsing. A header identifies the format and the input the
+// stations were parsed from; a cache that doesn't match either is ignored
+// rather than read into garbage. All numbers are little-endian:
+//   magic "TENKISTC", u32 format version, u64 input fingerprint,
+//   u32 station count, then the stations as written by write_station.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{Read, Write};
+use std::path;
+use std::time;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chrono::prelude::*;
+
+use {PrecipType, QualityCodes, WeatherMeasurement, WeatherStation,
+     WindMeasurement};
+
+const MAGIC: &'static [u8; 8] = b"TENKISTC";
+
+// Bump whenever the layout below or the meaning of a parsed value changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+// Identifies the input files by path, size and modification time, and
+// whatever else changes how they are parsed. DefaultHasher may change between
+// Rust releases, which only costs a re-parse.
+pub fn fingerprint(paths: &[path::PathBuf],
+                   parse_options: &str)
+                   -> Result<u64, io::Error> {
+  let mut sorted = paths.iter().collect::<Vec<_>>();
+  sorted.sort();
+  let mut hasher = DefaultHasher::new();
+  parse_options.hash(&mut hasher);
+  for path in sorted {
+    let metadata = try!(fs::metadata(path));
+    path.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    // Not every platform records modification times.
+    if let Ok(modified) = metadata.modified() {
+      if let Ok(since) = modified.duration_since(time::UNIX_EPOCH) {
+        since.as_secs().hash(&mut hasher);
+        since.subsec_nanos().hash(&mut hasher);
+      }
+    }
+  }
+  Ok(hasher.finish())
+}
+
+pub fn write(filename: &path::Path,
+             fingerprint: u64,
+             stations: &[WeatherStation])
+             -> Result<(), io::Error> {
+  let mut w = io::BufWriter::new(try!(fs::File::create(filename)));
+  try!(encode(&mut w, FORMAT_VERSION, fingerprint, stations));
+  w.flush()
+}
+
+// The cached stations, or why the cache can't be used.
+pub fn read(filename: &path::Path,
+            fingerprint: u64)
+            -> Result<Vec<WeatherStation>, String> {
+  let f = try!(fs::File::open(filename).map_err(|e| e.to_string()));
+  decode(&mut io::BufReader::new(f), FORMAT_VERSION, fingerprint)
+}
+
+fn encode(w: &mut Write,
+          version: u32,
+          fingerprint: u64,
+          stations: &[WeatherStation])
+          -> Result<(), io::Error> {
+  try!(w.write_all(MAGIC));
+  try!(w.write_u32::<LittleEndian>(version));
+  try!(w.write_u64::<LittleEndian>(fingerprint));
+  try!(w.write_u32::<LittleEndian>(stations.len() as u32));
+  for station in stations {
+    try!(write_station(w, station));
+  }
+  Ok(())
+}
+
+fn decode(r: &mut Read,
+          version: u32,
+          fingerprint: u64)
+          -> Result<Vec<WeatherStation>, String> {
+  let invalid = |e: io::Error| format!("truncated or corrupt: {}", e);
+  let mut magic = [0u8; 8];
+  try!(r.read_exact(&mut magic).map_err(&invalid));
+  if &magic != MAGIC {
+    return Err(String::from("not a station cache"));
+  }
+  let cached_version = try!(r.read_u32::<LittleEndian>().map_err(&invalid));
+  if cached_version != version {
+    return Err(format!("format version {} instead of {}",
+                       cached_version,
+                       version));
+  }
+  let cached_fingerprint =
+    try!(r.read_u64::<LittleEndian>().map_err(&invalid));
+  if cached_fingerprint != fingerprint {
+    return Err(String::from("the input files changed"));
+  }
+  let n = try!(r.read_u32::<LittleEndian>().map_err(&invalid));
+  let mut stations = vec![];
+  for _ in 0..n {
+    stations.push(try!(read_station(r).map_err(&invalid)));
+  }
+  Ok(stations)
+}
+
+fn write_string(w: &mut Write, s: &str) -> Result<(), io::Error> {
+  try!(w.write_u32::<LittleEndian>(s.len() as u32));
+  w.write_all(s.as_bytes())
+}
+
+fn read_string(r: &mut Read) -> Result<String, io::Error> {
+  let len = try!(r.read_u32::<LittleEndian>());
+  let mut bytes = vec![];
+  try!(r.take(len as u64).read_to_end(&mut bytes));
+  if bytes.len() != len as usize {
+    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                              "string cut short"));
+  }
+  String::from_utf8(bytes)
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// Options are a presence byte followed by the value if present.
+fn write_option_string(w: &mut Write,
+                       s: &Option<String>)
+                       -> Result<(), io::Error> {
+  match *s {
+    Some(ref s) => {
+      try!(w.write_u8(1));
+      write_string(w, s)
+    }
+    None => w.write_u8(0),
+  }
+}
+
+fn read_present(r: &mut Read) -> Result<bool, io::Error> {
+  match try!(r.read_u8()) {
+    0 => Ok(false),
+    1 => Ok(true),
+    b => {
+      Err(io::Error::new(io::ErrorKind::InvalidData,
+                         format!("invalid presence byte {}", b)))
+    }
+  }
+}
+
+fn read_option_string(r: &mut Read) -> Result<Option<String>, io::Error> {
+  if try!(read_present(r)) {
+    read_string(r).map(Some)
+  } else {
+    Ok(None)
+  }
+}
+
+fn write_option_f32(w: &mut Write, v: Option<f32>) -> Result<(), io::Error> {
+  match v {
+    Some(v) => {
+      try!(w.write_u8(1));
+      w.write_f32::<LittleEndian>(v)
+    }
+    None => w.write_u8(0),
+  }
+}
+
+fn read_option_f32(r: &mut Read) -> Result<Option<f32>, io::Error> {
+  if try!(read_present(r)) {
+    r.read_f32::<LittleEndian>().map(Some)
+  } else {
+    Ok(None)
+  }
+}
+
+fn write_option_i32(w: &mut Write, v: Option<i32>) -> Result<(), io::Error> {
+  match v {
+    Some(v) => {
+      try!(w.write_u8(1));
+      w.write_i32::<LittleEndian>(v)
+    }
+    None => w.write_u8(0),
+  }
+}
+
+fn read_option_i32(r: &mut Read) -> Result<Option<i32>, io::Error> {
+  if try!(read_present(r)) {
+    r.read_i32::<LittleEndian>().map(Some)
+  } else {
+    Ok(None)
+  }
+}
+
+fn write_station(w: &mut Write,
+                 station: &WeatherStation)
+                 -> Result<(), io::Error> {
+  try!(write_string(w, &station.usaf));
+  try!(write_string(w, &station.wban));
+  try!(write_option_string(w, &station.name));
+  try!(write_option_string(w, &station.country));
+  try!(write_option_string(w, &station.state));
+  try!(w.write_f32::<LittleEndian>(station.latitude));
+  try!(w.write_f32::<LittleEndian>(station.longitude));
+  try!(write_option_i32(w, station.elevation));
+  try!(w.write_u32::<LittleEndian>(station.measurements.len() as u32));
+  for m in &station.measurements {
+    try!(write_measurement(w, m));
+  }
+  Ok(())
+}
+
+fn read_station(r: &mut Read) -> Result<WeatherStation, io::Error> {
+  let usaf = try!(read_string(r));
+  let wban = try!(read_string(r));
+  let name = try!(read_option_string(r));
+  let country = try!(read_option_string(r));
+  let state = try!(read_option_string(r));
+  let latitude = try!(r.read_f32::<LittleEndian>());
+  let longitude = try!(r.read_f32::<LittleEndian>());
+  let elevation = try!(read_option_i32(r));
+  let n = try!(r.read_u32::<LittleEndian>());
+  let mut measurements = vec![];
+  for _ in 0..n {
+    measurements.push(try!(read_measurement(r)));
+  }
+  Ok(WeatherStation {
+    usaf: usaf,
+    wban: wban,
+    name: name,
+    country: country,
+    state: state,
+    latitude: latitude,
+    longitude: longitude,
+    elevation: elevation,
+    measurements: measurements,
+  })
+}
+
+fn write_measurement(w: &mut Write,
+                     m: &WeatherMeasurement)
+                     -> Result<(), io::Error> {
+  try!(w.write_i64::<LittleEndian>(m.datetime.timestamp()));
+  try!(w.write_u32::<LittleEndian>(m.datetime.nanosecond()));
+  try!(w.write_all(&m.report_type));
+  match m.wind {
+    None => try!(w.write_u8(0)),
+    Some(WindMeasurement::Calm) => try!(w.write_u8(1)),
+    Some(WindMeasurement::Variable) => try!(w.write_u8(2)),
+    Some(WindMeasurement::Normal { speed, direction }) => {
+      try!(w.write_u8(3));
+      try!(w.write_f32::<LittleEndian>(speed));
+      try!(write_option_i32(w, direction));
+    }
+  }
+  try!(write_option_f32(w, m.air_temperature));
+  try!(write_option_f32(w, m.dew_point));
+  try!(write_option_f32(w, m.air_pressure));
+  try!(w.write_u8(match m.precip_type {
+    None => 0,
+    Some(PrecipType::Rain) => 1,
+    Some(PrecipType::Snow) => 2,
+    Some(PrecipType::Freezing) => 3,
+    Some(PrecipType::Mixed) => 4,
+  }));
+  try!(w.write_all(&[m.quality.wind_speed,
+                     m.quality.air_temperature,
+                     m.quality.dew_point,
+                     m.quality.air_pressure]));
+  match m.source {
+    Some(source) => {
+      try!(w.write_u8(1));
+      w.write_u16::<LittleEndian>(source)
+    }
+    None => w.write_u8(0),
+  }
+}
+
+fn read_measurement(r: &mut Read) -> Result<WeatherMeasurement, io::Error> {
+  let invalid = |what: &str, b: u8| {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("invalid {} {}", what, b))
+  };
+  let seconds = try!(r.read_i64::<LittleEndian>());
+  let nanos = try!(r.read_u32::<LittleEndian>());
+  let mut report_type = [0u8; 5];
+  try!(r.read_exact(&mut report_type));
+  let wind = match try!(r.read_u8()) {
+    0 => None,
+    1 => Some(WindMeasurement::Calm),
+    2 => Some(WindMeasurement::Variable),
+    3 => {
+      let speed = try!(r.read_f32::<LittleEndian>());
+      let direction = try!(read_option_i32(r));
+      Some(WindMeasurement::Normal {
+        speed: speed,
+        direction: direction,
+      })
+    }
+    b => return Err(invalid("wind kind", b)),
+  };
+  let air_temperature = try!(read_option_f32(r));
+  let dew_point = try!(read_option_f32(r));
+  let air_pressure = try!(read_option_f32(r));
+  let precip_type = match try!(r.read_u8()) {
+    0 => None,
+    1 => Some(PrecipType::Rain),
+    2 => Some(PrecipType::Snow),
+    3 => Some(PrecipType::Freezing),
+    4 => Some(PrecipType::Mixed),
+    b => return Err(invalid("precipitation type", b)),
+  };
+  let mut quality = [0u8; 4];
+  try!(r.read_exact(&mut quality));
+  let source = if try!(read_present(r)) {
+    Some(try!(r.read_u16::<LittleEndian>()))
+  } else {
+    None
+  };
+  Ok(WeatherMeasurement {
+    datetime: UTC.timestamp(seconds, nanos),
+    report_type: report_type,
+    wind: wind,
+    air_temperature: air_temperature,
+    dew_point: dew_point,
+    air_pressure: air_pressure,
+    precip_type: precip_type,
+    quality: QualityCodes {
+      wind_speed: quality[0],
+      air_temperature: quality[1],
+      dew_point: quality[2],
+      air_pressure: quality[3],
+    },
+    source: source,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing;
+
+  fn stations() -> Vec<WeatherStation> {
+    let mut measurements =
+      testing::series(&[Some(-3.5), None, Some(12.25)]);
+    measurements[0].wind = Some(WindMeasurement::observed(4.5, Some(270)));
+    measurements[1].wind = Some(WindMeasurement::Calm);
+    measurements[1].precip_type = Some(PrecipType::Snow);
+    measurements[2].dew_point = Some(8.0);
+    measurements[2].source = Some(7);
+    let mut first = testing::station("010000", 60.2, 24.9, measurements);
+    first.name = Some(String::from("HELSINKI"));
+    first.elevation = Some(51);
+    vec![first, testing::station("020000", -33.4, -70.6, vec![])]
+  }
+
+  fn encoded(version: u32, fingerprint: u64) -> Vec<u8> {
+    let mut bytes = vec![];
+    encode(&mut bytes, version, fingerprint, &stations()).unwrap();
+    bytes
+  }
+
+  #[test]
+  fn stations_round_trip() {
+    let bytes = encoded(FORMAT_VERSION, 42);
+    let decoded = decode(&mut io::Cursor::new(bytes), FORMAT_VERSION, 42)
+      .unwrap_or_else(|reason| panic!("{}", reason));
+    let original = stations();
+    assert_eq!(decoded.len(), original.len());
+    for (decoded, original) in decoded.iter().zip(&original) {
+      assert_eq!(decoded.usaf, original.usaf);
+      assert_eq!(decoded.wban, original.wban);
+      assert_eq!(decoded.name, original.name);
+      assert_eq!((decoded.latitude, decoded.longitude),
+                 (original.latitude, original.longitude));
+      assert_eq!(decoded.elevation, original.elevation);
+      assert_eq!(decoded.measurements, original.measurements);
+    }
+  }
+
+  #[test]
+  fn version_bump_invalidates_old_caches() {
+    let old = encoded(FORMAT_VERSION, 42);
+    match decode(&mut io::Cursor::new(old), FORMAT_VERSION + 1, 42) {
+      Ok(_) => panic!("read a cache of the previous format version"),
+      Err(reason) => assert!(reason.contains("version"), "{}", reason),
+    }
+  }
+
+  #[test]
+  fn rejects_other_inputs_and_garbage() {
+    match decode(&mut io::Cursor::new(encoded(FORMAT_VERSION, 42)),
+                 FORMAT_VERSION,
+                 43) {
+      Ok(_) => panic!("read a cache of other input files"),
+      Err(reason) => assert_eq!(reason, "the input files changed"),
+    }
+    let mut truncated = encoded(FORMAT_VERSION, 42);
+    let len = truncated.len();
+    truncated.truncate(len - 3);
+    assert!(decode(&mut io::Cursor::new(truncated), FORMAT_VERSION, 42)
+      .is_err());
+    assert!(decode(&mut io::Cursor::new(b"{\"usaf\": 1}".to_vec()),
+                   FORMAT_VERSION,
+                   42)
+      .is_err());
+  }
+}
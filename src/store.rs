@@ -0,0 +1,258 @@
+// The loaded stations plus the indices derived from them. Routes share one
+// behind a RwLock; whatever replaces it must build a complete new store first
+// and only then swap it in, so readers never see a half-updated one. The new
+// store's version must be higher, as caches key on it.
+
+use std::cmp;
+use std::collections;
+use std::f32;
+use std::sync;
+
+use source::{SqliteSource, StationSource};
+use {Placed, WeatherStation};
+use {clamp_latitude, mercator, tile_viewport};
+
+// Side of the spatial grid cells in degrees.
+const CELL_DEGREES: f32 = 1.0;
+
+pub struct StationStore {
+  pub stations: Vec<WeatherStation>,
+  // Identifies the data; anything derived from it is stale once this changes.
+  pub version: u64,
+  // Per station, the version its measurements last changed at. Every station
+  // is new in a freshly built store until it inherits from the one it
+  // replaces.
+  updated: Vec<u64>,
+  // Input files indexed by WeatherMeasurement::source.
+  pub sources: Vec<String>,
+  // Whether more stations are still being loaded into later stores.
+  pub loading: bool,
+  // With --sqlite, where the tile routes read stations from instead.
+  pub database: Option<SqliteSource>,
+  // (usaf, wban) to index into stations.
+  ids: collections::HashMap<(String, String), usize>,
+  // Indices into stations, ordered by (usaf, wban).
+  by_id: Vec<usize>,
+  // Grid cell to the indices of the stations inside it.
+  cells: collections::HashMap<(i32, i32), Vec<usize>>,
+  // Per zoom level from 0, tile to the indices and pixels of the stations
+  // drawn on it; see precompute_tiles.
+  tiles: Vec<collections::HashMap<(u32, u32), Vec<(usize, i32, i32)>>>,
+}
+
+pub type SharedStore = sync::Arc<sync::RwLock<StationStore>>;
+
+fn cell(longitude: f32, latitude: f32) -> (i32, i32) {
+  ((longitude / CELL_DEGREES).floor() as i32,
+   (latitude / CELL_DEGREES).floor() as i32)
+}
+
+impl StationStore {
+  pub fn new(stations: Vec<WeatherStation>, version: u64) -> StationStore {
+    let mut ids = collections::HashMap::new();
+    let mut cells = collections::HashMap::new();
+    for (i, station) in stations.iter().enumerate() {
+      ids.insert((station.usaf.clone(), station.wban.clone()), i);
+      cells.entry(cell(station.longitude, station.latitude))
+        .or_insert_with(Vec::new)
+        .push(i);
+    }
+    let mut by_id = (0..stations.len()).collect::<Vec<_>>();
+    by_id.sort_by(|&a, &b| {
+      (&stations[a].usaf, &stations[a].wban)
+        .cmp(&(&stations[b].usaf, &stations[b].wban))
+    });
+    let updated = vec![version; stations.len()];
+    StationStore {
+      stations: stations,
+      version: version,
+      updated: updated,
+      sources: vec![],
+      loading: false,
+      database: None,
+      ids: ids,
+      by_id: by_id,
+      cells: cells,
+      tiles: vec![],
+    }
+  }
+
+  pub fn shared(self) -> SharedStore {
+    sync::Arc::new(sync::RwLock::new(self))
+  }
+
+  // Where the tile routes get their stations from.
+  pub fn source(&self) -> &StationSource {
+    if let Some(ref database) = self.database {
+      return database;
+    }
+    self
+  }
+
+  // Takes over from the store this one replaces when each station last
+  // changed, for the stations whose measurements are the same in both.
+  pub fn inherit_updated(&mut self, previous: &StationStore) {
+    for (i, station) in self.stations.iter().enumerate() {
+      let id = (station.usaf.clone(), station.wban.clone());
+      if let Some(&j) = previous.ids.get(&id) {
+        if previous.stations[j].measurements == station.measurements {
+          self.updated[i] = previous.updated[j];
+        }
+      }
+    }
+  }
+
+  // A new store with the stations added, in which only they changed.
+  pub fn extend(self, new: &[WeatherStation], version: u64) -> StationStore {
+    let num_previous = self.stations.len();
+    let mut stations = self.stations;
+    stations.extend(new.iter().cloned());
+    let mut store = StationStore::new(stations, version);
+    store.updated[..num_previous].copy_from_slice(&self.updated);
+    store
+  }
+
+  // Works out which tiles of the zoom levels up to max_zoom each station is
+  // drawn on and at which pixel, trading memory for not having to search and
+  // project the stations for every tile.
+  pub fn precompute_tiles(&mut self, max_zoom: u32) {
+    let mut tiles = vec![];
+    for zoom in 0..max_zoom + 1 {
+      let n = 1i64 << zoom;
+      let mut positions = collections::HashMap::new();
+      for (i, station) in self.stations.iter().enumerate() {
+        let x = ((station.longitude + 180.0) / 360.0 * n as f32).floor();
+        let mercator_y = mercator(clamp_latitude(station.latitude));
+        let y =
+          ((1.0 - mercator_y / f32::consts::PI) / 2.0 * n as f32).floor();
+        // Stations on the edge between tiles are drawn on both, so the
+        // neighboring tiles are tried too.
+        for tile_x in cmp::max(x as i64 - 1, 0)..cmp::min(x as i64 + 2, n) {
+          for tile_y in cmp::max(y as i64 - 1, 0)..cmp::min(y as i64 + 2, n) {
+            let (tile_x, tile_y) = (tile_x as u32, tile_y as u32);
+            let pixel = tile_viewport(zoom, tile_x, tile_y).and_then(|v| {
+              v.pixel(station.longitude, station.latitude)
+            });
+            if let Some((pixel_x, pixel_y)) = pixel {
+              positions.entry((tile_x, tile_y))
+                .or_insert_with(Vec::new)
+                .push((i, pixel_x, pixel_y));
+            }
+          }
+        }
+      }
+      tiles.push(positions);
+    }
+    self.tiles = tiles;
+  }
+
+  // The stations drawn on a tile with their pixels, or None if the zoom level
+  // wasn't precomputed.
+  pub fn tile(&self, zoom: u32, x: u32, y: u32) -> Option<Vec<Placed>> {
+    self.tiles.get(zoom as usize).map(|positions| {
+      positions.get(&(x, y))
+        .map(|placed| {
+          placed.iter()
+            .map(|&(i, pixel_x, pixel_y)| (&self.stations[i], pixel_x, pixel_y))
+            .collect()
+        })
+        .unwrap_or_else(Vec::new)
+    })
+  }
+
+  // Whether the station's measurements changed after the given version.
+  pub fn changed_since(&self, station: &WeatherStation, version: u64) -> bool {
+    self.ids
+      .get(&(station.usaf.clone(), station.wban.clone()))
+      .map_or(false, |&i| self.updated[i] > version)
+  }
+
+  pub fn get(&self, usaf: &str, wban: &str) -> Option<&WeatherStation> {
+    self.ids
+      .get(&(String::from(usaf), String::from(wban)))
+      .map(|&i| &self.stations[i])
+  }
+
+  // Up to `limit` stations in id order, skipping the first `offset`.
+  pub fn page(&self, offset: usize, limit: usize) -> Vec<&WeatherStation> {
+    self.by_id
+      .iter()
+      .skip(offset)
+      .take(limit)
+      .map(|&i| &self.stations[i])
+      .collect()
+  }
+
+  // The stations in the grid cells overlapping the bounds, a superset of those
+  // inside them, in the order they were loaded like precomputed tiles.
+  pub fn near(&self,
+              longitude_min: f32,
+              longitude_max: f32,
+              latitude_min: f32,
+              latitude_max: f32)
+              -> Vec<&WeatherStation> {
+    let (x_min, y_min) = cell(longitude_min, latitude_min);
+    let (x_max, y_max) = cell(longitude_max, latitude_max);
+    let mut near = vec![];
+    for x in x_min..x_max + 1 {
+      for y in y_min..y_max + 1 {
+        if let Some(indices) = self.cells.get(&(x, y)) {
+          near.extend_from_slice(indices);
+        }
+      }
+    }
+    near.sort();
+    near.iter().map(|&i| &self.stations[i]).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use position_stations;
+  use testing;
+
+  #[test]
+  fn precomputed_tiles_match_projection() {
+    // Including stations on tile edges and beyond the mercator cut-off.
+    let coordinates = [(0.0, 0.0),
+                       (-180.0, 10.0),
+                       (179.99, -10.0),
+                       (45.0, 66.5),
+                       (13.4, 52.5),
+                       (-70.6, -33.4),
+                       (24.9, 85.0),
+                       (0.0, 89.0)];
+    let stations = coordinates.iter()
+      .enumerate()
+      .map(|(i, &(lon, lat))| {
+        testing::station(&format!("0{}0000", i), lat, lon, vec![])
+      })
+      .collect();
+    let mut store = StationStore::new(stations, 0);
+    store.precompute_tiles(3);
+    for zoom in 0..4 {
+      for x in 0..1 << zoom {
+        for y in 0..1 << zoom {
+          let viewport = tile_viewport(zoom, x, y).unwrap();
+          let near = store.near(viewport.longitude_min,
+                                viewport.longitude_max,
+                                viewport.latitude_min,
+                                viewport.latitude_max);
+          let projected = position_stations(near, &viewport);
+          let key = |placed: Vec<Placed>| {
+            placed.into_iter()
+              .map(|(station, x, y)| (station.usaf.clone(), x, y))
+              .collect::<Vec<_>>()
+          };
+          assert_eq!(key(store.tile(zoom, x, y).unwrap()), key(projected),
+                     "tile {}/{}/{}",
+                     zoom,
+                     x,
+                     y);
+        }
+      }
+    }
+    assert!(store.tile(4, 0, 0).is_none());
+  }
+}
@@ -0,0 +1,143 @@
+// Vector rendering of station markers as a minimal SVG document.
+
+use std::fmt::Write;
+
+use image;
+
+use Marker;
+use WindMeasurement;
+use barbs;
+
+// Wind arrows are this many marker radii long, but at least MIN_ARROW pixels.
+const ARROW_RADII: f32 = 4.0;
+const MIN_ARROW: f32 = 6.0;
+
+// As #rrggbb.
+pub fn hex(color: image::Rgb<u8>) -> String {
+  format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+fn write_barb(svg: &mut String, barb: &barbs::Barb, color: &str) {
+  for &((x1, y1), (x2, y2)) in &barb.lines {
+    write!(svg,
+           "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" \
+            stroke=\"{}\"/>\n",
+           x1,
+           y1,
+           x2,
+           y2,
+           color)
+      .unwrap();
+  }
+  for &((ax, ay), (bx, by), (cx, cy)) in &barb.pennants {
+    write!(svg,
+           "<polygon points=\"{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}\" \
+            fill=\"{}\"/>\n",
+           ax,
+           ay,
+           bx,
+           by,
+           cx,
+           cy,
+           color)
+      .unwrap();
+  }
+}
+
+// One <circle> per marker, plus a <line> pointing downwind for markers with
+// a wind direction. With `draw_barbs`, markers with wind are drawn as wind
+// barbs (or a ring for calm) instead. With `outline`, every marker sits on a
+// disk of that color one pixel wider.
+pub fn render(width: u32,
+              height: u32,
+              background: image::Rgb<u8>,
+              markers: &[Marker],
+              radius: f32,
+              draw_barbs: bool,
+              outline: Option<image::Rgb<u8>>)
+              -> String {
+  let mut svg = String::new();
+  write!(svg,
+         "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" \
+          height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+         width,
+         height,
+         width,
+         height)
+    .unwrap();
+  write!(svg,
+         "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>\n",
+         hex(background))
+    .unwrap();
+
+  let arrow = (radius * ARROW_RADII).max(MIN_ARROW);
+  for marker in markers {
+    let color = hex(marker.color);
+    let direction = match marker.wind {
+      Some(WindMeasurement::Normal { direction, .. }) => direction,
+      _ => None,
+    };
+    if draw_barbs {
+      match marker.wind {
+        Some(WindMeasurement::Normal { speed, direction: Some(direction) }) => {
+          let barb =
+            barbs::barb(marker.x as f32, marker.y as f32, direction, speed);
+          write_barb(&mut svg, &barb, &color);
+          continue;
+        }
+        Some(WindMeasurement::Calm) => {
+          write!(svg,
+                 "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"none\" \
+                  stroke=\"{}\"/>\n",
+                 marker.x,
+                 marker.y,
+                 barbs::CALM_RADIUS,
+                 color)
+            .unwrap();
+          continue;
+        }
+        _ => {}
+      }
+    } else if let Some(direction) = direction {
+      // The direction is where the wind comes from; y grows downwards.
+      let downwind = (direction as f32).to_radians();
+      write!(svg,
+             "<line x1=\"{}\" y1=\"{}\" x2=\"{:.1}\" y2=\"{:.1}\" \
+              stroke=\"{}\"/>\n",
+             marker.x,
+             marker.y,
+             marker.x as f32 - arrow * downwind.sin(),
+             marker.y as f32 + arrow * downwind.cos(),
+             color)
+        .unwrap();
+    }
+    if let Some(outline) = outline {
+      write!(svg,
+             "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"/>\n",
+             marker.x,
+             marker.y,
+             radius * marker.scale as f32 + 1.0,
+             hex(outline))
+        .unwrap();
+    }
+    write!(svg,
+           "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"/>\n",
+           marker.x,
+           marker.y,
+           radius * marker.scale as f32,
+           color)
+      .unwrap();
+    if marker.clipped {
+      write!(svg,
+             "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"none\" \
+              stroke=\"{}\"/>\n",
+             marker.x,
+             marker.y,
+             radius * marker.scale as f32 + 2.0,
+             color)
+        .unwrap();
+    }
+  }
+  svg.push_str("</svg>\n");
+  svg
+}
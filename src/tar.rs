@@ -0,0 +1,147 @@
+// Minimal streaming reader for tar archives, just enough to walk the NOAA
+// yearly bundles without extracting them to disk first. Supports plain
+// ustar headers plus GNU long names; everything that isn't a regular file is
+// skipped.
+
+use std::io;
+use std::io::Read;
+
+const BLOCK_SIZE: u64 = 512;
+
+// Calls `f` with the name and a reader over the contents of every regular file
+// in `archive`, in archive order. Whatever `f` leaves unread is skipped.
+pub fn for_each_file<R, F>(mut archive: R, mut f: F) -> Result<(), io::Error>
+  where R: Read,
+        F: FnMut(&str, &mut Read)
+{
+  let mut header = [0u8; BLOCK_SIZE as usize];
+  let mut long_name: Option<String> = None;
+
+  loop {
+    if !try!(read_header(&mut archive, &mut header)) {
+      return Ok(());
+    }
+    // The archive ends with (at least) one all-zero block.
+    if header.iter().all(|&b| b == 0) {
+      return Ok(());
+    }
+
+    let size = try!(parse_octal(&header[124..136]));
+    let name = long_name.take().unwrap_or_else(|| header_name(&header));
+
+    {
+      let mut entry = (&mut archive).take(size);
+      match header[156] {
+        b'0' | b'\0' | b'7' => f(&name, &mut entry),
+        b'L' => {
+          let mut buf = Vec::new();
+          try!(entry.read_to_end(&mut buf));
+          long_name = Some(String::from_utf8_lossy(&buf)
+            .trim_right_matches('\0')
+            .to_string());
+        }
+        _ => {}
+      }
+      try!(io::copy(&mut entry, &mut io::sink()));
+    }
+
+    let padding = (BLOCK_SIZE - size % BLOCK_SIZE) % BLOCK_SIZE;
+    try!(io::copy(&mut (&mut archive).take(padding), &mut io::sink()));
+  }
+}
+
+// Reads a full header block, returning false on a clean end of file.
+fn read_header(archive: &mut Read,
+               block: &mut [u8])
+               -> Result<bool, io::Error> {
+  let mut filled = 0;
+  while filled < block.len() {
+    match try!(archive.read(&mut block[filled..])) {
+      0 if filled == 0 => return Ok(false),
+      0 => {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                  "truncated tar header"))
+      }
+      n => filled += n,
+    }
+  }
+  Ok(true)
+}
+
+fn c_string(bytes: &[u8]) -> String {
+  let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+  String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn header_name(header: &[u8]) -> String {
+  let name = c_string(&header[0..100]);
+  if &header[257..262] != b"ustar" {
+    return name;
+  }
+  let prefix = c_string(&header[345..500]);
+  if prefix.is_empty() {
+    name
+  } else {
+    format!("{}/{}", prefix, name)
+  }
+}
+
+// Numeric header fields are NUL/space terminated octal, or big-endian base-256
+// with the high bit set for values that don't fit (GNU extension).
+fn parse_octal(field: &[u8]) -> Result<u64, io::Error> {
+  if field[0] & 0x80 != 0 {
+    return Ok(field[1..].iter().fold(0u64, |n, &b| (n << 8) | b as u64));
+  }
+  let digits = c_string(field);
+  let digits = digits.trim();
+  if digits.is_empty() {
+    return Ok(0);
+  }
+  u64::from_str_radix(digits, 8).map_err(|e| {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("bad tar header number {:?}: {}", digits, e))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A ustar archive of regular files, ending with the two zero blocks.
+  fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut tar = vec![];
+    for &(name, contents) in files {
+      let mut header = [0u8; BLOCK_SIZE as usize];
+      header[..name.len()].copy_from_slice(name.as_bytes());
+      let size = format!("{:011o}", contents.len());
+      header[124..135].copy_from_slice(size.as_bytes());
+      header[156] = b'0';
+      header[257..263].copy_from_slice(b"ustar\0");
+      tar.extend_from_slice(&header);
+      tar.extend_from_slice(contents.as_bytes());
+      let padding = (BLOCK_SIZE - contents.len() as u64 % BLOCK_SIZE) %
+                    BLOCK_SIZE;
+      tar.extend(vec![0u8; padding as usize]);
+    }
+    tar.extend(vec![0u8; 2 * BLOCK_SIZE as usize]);
+    tar
+  }
+
+  #[test]
+  fn reads_every_file() {
+    let tar = archive(&[("2016/010010-99999-2016", "first\n"),
+                        ("2016/010020-99999-2016", "second\n")]);
+    let mut files = vec![];
+    for_each_file(io::Cursor::new(tar), |name, entry| {
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents).unwrap();
+        files.push((String::from(name), contents));
+      })
+      .unwrap();
+    assert_eq!(files,
+               vec![(String::from("2016/010010-99999-2016"),
+                     String::from("first\n")),
+                    (String::from("2016/010020-99999-2016"),
+                     String::from("second\n"))]);
+  }
+}
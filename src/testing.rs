@@ -0,0 +1,152 @@
+// Builders for the synthetic stations tests run on.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::iter;
+use std::path;
+
+use chrono::prelude::*;
+use clap;
+use rusqlite;
+
+use source;
+use {QualityCodes, WeatherMeasurement, WeatherStation, WindMeasurement};
+use {convert_args, input_args, render_args, serve_args};
+
+// Hours into January 2016, so that tests can count in hours.
+pub fn time(hours: i64) -> DateTime<UTC> {
+  UTC.ymd(2016, 1, 1).and_hms(0, 0, 0) + ::time::Duration::hours(hours)
+}
+
+// A SYNOP report that passed quality control, with only an air temperature.
+pub fn measurement(datetime: DateTime<UTC>,
+                   air_temperature: Option<f32>)
+                   -> WeatherMeasurement {
+  WeatherMeasurement {
+    datetime: datetime,
+    report_type: *b"FM-12",
+    wind: None,
+    air_temperature: air_temperature,
+    dew_point: None,
+    air_pressure: None,
+    precip_type: None,
+    quality: QualityCodes {
+      wind_speed: b'1',
+      air_temperature: b'1',
+      dew_point: b'1',
+      air_pressure: b'1',
+    },
+    source: None,
+  }
+}
+
+// Hourly measurements from hour 0, with the given air temperatures.
+pub fn series(temperatures: &[Option<f32>]) -> Vec<WeatherMeasurement> {
+  temperatures.iter()
+    .enumerate()
+    .map(|(hour, &t)| measurement(time(hour as i64), t))
+    .collect()
+}
+
+pub fn station(usaf: &str,
+               latitude: f32,
+               longitude: f32,
+               measurements: Vec<WeatherMeasurement>)
+               -> WeatherStation {
+  WeatherStation {
+    usaf: String::from(usaf),
+    wban: String::from("99999"),
+    name: None,
+    country: None,
+    state: None,
+    latitude: latitude,
+    longitude: longitude,
+    elevation: None,
+    measurements: measurements,
+  }
+}
+
+// Creates the --sqlite tables and fills them with the stations.
+pub fn insert_stations(connection: &rusqlite::Connection,
+                       stations: &[WeatherStation]) {
+  connection.execute_batch(source::SCHEMA).unwrap();
+  for s in stations {
+    connection.execute("INSERT INTO stations VALUES (?1, ?2, ?3, ?4, ?5)",
+                       &[&s.usaf,
+                         &s.wban,
+                         &(s.latitude as f64),
+                         &(s.longitude as f64),
+                         &s.elevation])
+      .unwrap();
+    for m in &s.measurements {
+      let (speed, direction) = match m.wind {
+        Some(WindMeasurement::Normal { speed, direction }) => {
+          (Some(speed as f64), direction)
+        }
+        Some(WindMeasurement::Calm) => (Some(0.0), None),
+        _ => (None, None),
+      };
+      let report_type = String::from_utf8(m.report_type.to_vec()).unwrap();
+      connection.execute("INSERT INTO measurements VALUES \
+                          (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                         &[&s.usaf,
+                           &s.wban,
+                           &m.datetime.timestamp(),
+                           &report_type,
+                           &m.air_temperature.map(|t| t as f64),
+                           &m.dew_point.map(|t| t as f64),
+                           &m.air_pressure.map(|p| p as f64),
+                           &speed,
+                           &direction])
+        .unwrap();
+    }
+  }
+}
+
+// An ISH record of station 010000-99999 at time(hour), with only an air
+// temperature, followed by the given additional data.
+pub fn ish_record(hour: i64,
+                  air_temperature: Option<f32>,
+                  additional: &str)
+                  -> String {
+  let temperature = match air_temperature {
+    Some(t) => format!("{:+05}1", (t * 10.0).round() as i32),
+    None => String::from("+99999"),
+  };
+  let record = format!("{:04}01000099999{}4+51317+000000FM-12+0100\
+                        99999V02099999999999999999N9999999N9{}+99999\
+                        999999{}",
+                       additional.len(),
+                       time(hour).format("%Y%m%d%H%M"),
+                       temperature,
+                       additional);
+  assert_eq!(record.len(), 105 + additional.len());
+  record
+}
+
+// A fresh, empty directory for the test of the given name, so that tests
+// running in parallel don't share files.
+pub fn scratch_dir(test: &str) -> path::PathBuf {
+  let dir = env::temp_dir().join(format!("tenki-test-{}", test));
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+// Writes contents to a file of the given name in scratch_dir(test).
+pub fn scratch_file(test: &str, name: &str, contents: &[u8]) -> path::PathBuf {
+  let filename = scratch_dir(test).join(name);
+  fs::File::create(&filename).unwrap().write_all(contents).unwrap();
+  filename
+}
+
+// Command line flags, parsed like main does those given without a subcommand.
+pub fn args(flags: &[&str]) -> clap::ArgMatches<'static> {
+  clap::App::new("tenki")
+    .args(&input_args())
+    .args(&render_args())
+    .args(&convert_args())
+    .args(&serve_args())
+    .get_matches_from(iter::once("tenki").chain(flags.iter().cloned()))
+}
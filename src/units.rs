@@ -0,0 +1,145 @@
+// Units of the values in JSON responses. Internally temperatures are always
+// degrees Celsius, pressures hPa, wind speeds m/s and humidities percent.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TemperatureUnit {
+  Celsius,
+  Fahrenheit,
+}
+
+impl TemperatureUnit {
+  // Accepts the `?unit=` values c and f.
+  pub fn parse(unit: &str) -> Result<TemperatureUnit, String> {
+    match unit {
+      "c" => Ok(TemperatureUnit::Celsius),
+      "f" => Ok(TemperatureUnit::Fahrenheit),
+      _ => Err(format!("unknown temperature unit {:?}", unit)),
+    }
+  }
+
+  pub fn parse_option(unit: &Option<String>)
+                      -> Result<TemperatureUnit, String> {
+    match *unit {
+      Some(ref unit) => TemperatureUnit::parse(unit),
+      None => Ok(TemperatureUnit::Celsius),
+    }
+  }
+
+  // Converts a temperature in degrees Celsius to this unit.
+  pub fn from_celsius(&self, t: f32) -> f32 {
+    match *self {
+      TemperatureUnit::Celsius => t,
+      TemperatureUnit::Fahrenheit => t * 9.0 / 5.0 + 32.0,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match *self {
+      TemperatureUnit::Celsius => "celsius",
+      TemperatureUnit::Fahrenheit => "fahrenheit",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindUnit {
+  MetersPerSecond,
+  Knots,
+  MilesPerHour,
+}
+
+impl WindUnit {
+  // Accepts the `?wind_unit=` values ms, kn and mph.
+  pub fn parse(unit: &str) -> Result<WindUnit, String> {
+    match unit {
+      "ms" => Ok(WindUnit::MetersPerSecond),
+      "kn" => Ok(WindUnit::Knots),
+      "mph" => Ok(WindUnit::MilesPerHour),
+      _ => Err(format!("unknown wind speed unit {:?}", unit)),
+    }
+  }
+
+  pub fn parse_option(unit: &Option<String>) -> Result<WindUnit, String> {
+    match *unit {
+      Some(ref unit) => WindUnit::parse(unit),
+      None => Ok(WindUnit::MetersPerSecond),
+    }
+  }
+
+  // Converts a speed in m/s to this unit.
+  pub fn from_ms(&self, speed: f32) -> f32 {
+    match *self {
+      WindUnit::MetersPerSecond => speed,
+      // 1 kn = 1852 m/h, 1 mph = 1609.344 m/h.
+      WindUnit::Knots => speed * 3600.0 / 1852.0,
+      WindUnit::MilesPerHour => speed * 3600.0 / 1609.344,
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match *self {
+      WindUnit::MetersPerSecond => "m/s",
+      WindUnit::Knots => "kn",
+      WindUnit::MilesPerHour => "mph",
+    }
+  }
+}
+
+// Included as `units` in every JSON response carrying measurement values.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Units {
+  temperature: &'static str,
+  pressure: &'static str,
+  wind_speed: &'static str,
+  humidity: &'static str,
+}
+
+impl Units {
+  pub fn new(temperature: TemperatureUnit, wind_speed: WindUnit) -> Units {
+    Units {
+      temperature: temperature.name(),
+      pressure: "hPa",
+      wind_speed: wind_speed.name(),
+      humidity: "%",
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_near(value: f32, expected: f32) {
+    assert!((value - expected).abs() < 1e-3,
+            "{} instead of {}",
+            value,
+            expected);
+  }
+
+  #[test]
+  fn converts_temperatures() {
+    let f = TemperatureUnit::parse("f").unwrap();
+    assert_near(f.from_celsius(0.0), 32.0);
+    assert_near(f.from_celsius(100.0), 212.0);
+    assert_near(f.from_celsius(-40.0), -40.0);
+    assert_near(f.from_celsius(37.0), 98.6);
+    assert_eq!(TemperatureUnit::parse("c").unwrap().from_celsius(21.5), 21.5);
+    assert_eq!(TemperatureUnit::parse_option(&None),
+               Ok(TemperatureUnit::Celsius));
+    assert!(TemperatureUnit::parse("k").is_err());
+  }
+
+  #[test]
+  fn converts_wind_speeds() {
+    let kn = WindUnit::parse("kn").unwrap();
+    let mph = WindUnit::parse("mph").unwrap();
+    assert_near(kn.from_ms(1852.0 / 3600.0), 1.0);
+    assert_near(kn.from_ms(10.0), 19.438);
+    assert_near(mph.from_ms(1609.344 / 3600.0), 1.0);
+    assert_near(mph.from_ms(10.0), 22.369);
+    assert_eq!(WindUnit::parse("ms").unwrap().from_ms(7.5), 7.5);
+    assert_eq!(WindUnit::parse_option(&None),
+               Ok(WindUnit::MetersPerSecond));
+    assert!(WindUnit::parse("kmh").is_err());
+  }
+}